@@ -21,9 +21,15 @@ pub enum OpCode {
     XORI = 0b0100_0001,
     SLLI = 0b0110_0001,
     SRLI = 0b0111_0001,
+    JAL = 0b1000_0001,
+    RETI = 0b1001_0001,
+    HALT = 0b1010_0001,
+    WFI = 0b1011_0001,
     // Load/Store
     LD = 0b0000_0011,
     STR = 0b0001_0011,
+    PUSH = 0b0010_0011,
+    POP = 0b0011_0011,
     // B
     BE = 0b0000_0010,
     BNE = 0b0001_0010,
@@ -45,9 +51,10 @@ impl From<OpCode> for u8 {
     }
 }
 
-impl From<u8> for OpCode {
-    fn from(instr: u8) -> Self {
-        match instr {
+impl OpCode {
+    /// Return the opcode encoded as `value`, `None` if no instruction uses it.
+    pub fn decode(value: u8) -> Option<Self> {
+        let opcode = match value {
             // R-Type Instructions
             0b0000_0000 => OpCode::ADD,
             0b0001_0000 => OpCode::SUB,
@@ -64,9 +71,15 @@ impl From<u8> for OpCode {
             0b0100_0001 => OpCode::XORI,
             0b0110_0001 => OpCode::SLLI,
             0b0111_0001 => OpCode::SRLI,
+            0b1000_0001 => OpCode::JAL,
+            0b1001_0001 => OpCode::RETI,
+            0b1010_0001 => OpCode::HALT,
+            0b1011_0001 => OpCode::WFI,
             // Load/Store Instructions
             0b0000_0011 => OpCode::LD,
             0b0001_0011 => OpCode::STR,
+            0b0010_0011 => OpCode::PUSH,
+            0b0011_0011 => OpCode::POP,
             // B-Type Instructions
             0b0000_0010 => OpCode::BE,
             0b0001_0010 => OpCode::BNE,
@@ -74,8 +87,16 @@ impl From<u8> for OpCode {
             0b0101_0010 => OpCode::BGE,
             0b0110_0010 => OpCode::BLTU,
             0b0111_0010 => OpCode::BGEU,
-            _ => panic!("[Instruction] Unknown value : {} ", instr),
-        }
+            _ => return None,
+        };
+
+        Some(opcode)
+    }
+}
+
+impl From<u8> for OpCode {
+    fn from(instr: u8) -> Self {
+        OpCode::decode(instr).unwrap_or_else(|| panic!("[Instruction] Unknown value : {} ", instr))
     }
 }
 
@@ -140,8 +161,14 @@ impl Instruction {
             | OpCode::XORI
             | OpCode::SLLI
             | OpCode::SRLI
+            | OpCode::JAL
+            | OpCode::RETI
+            | OpCode::HALT
+            | OpCode::WFI
             | OpCode::LD
-            | OpCode::STR => Self {
+            | OpCode::STR
+            | OpCode::PUSH
+            | OpCode::POP => Self {
                 instruction: InstructionEnum::IInstruction(IInstruction(opcode as u32)),
             },
             OpCode::BE | OpCode::BNE | OpCode::BLT | OpCode::BGE | OpCode::BLTU | OpCode::BGEU => {
@@ -168,11 +195,14 @@ impl Instruction {
         }
     }
 
-    pub fn set_upper(&self, upper: u32) {
+    pub fn set_upper(&mut self, upper: u32) {
         match self.instruction {
             InstructionEnum::RInstruction(_) => panic!("RInstruction does not support get_upper"),
             InstructionEnum::IInstruction(_) => panic!("IInstruction does not support get_upper"),
-            InstructionEnum::BInstruction(mut inst) => inst.set_upper(upper),
+            InstructionEnum::BInstruction(mut inst) => {
+                inst.set_upper(upper);
+                self.instruction = InstructionEnum::BInstruction(inst);
+            }
         }
     }
 
@@ -318,25 +348,14 @@ pub fn encode_instruction(instruction: Instruction) -> u32 {
 }
 
 pub fn decode_instruction(instruction: u32) -> Instruction {
-    if instruction >> 1 & 1 == 0 && instruction & 1 == 0 {
-        Instruction {
-            instruction: InstructionEnum::RInstruction(RInstruction(instruction)),
-        }
-    } else if instruction >> 1 & 1 == 0 && instruction & 1 == 1 {
-        Instruction {
-            instruction: InstructionEnum::IInstruction(IInstruction(instruction)),
-        }
-    } else if instruction >> 1 & 1 == 1 && instruction & 1 == 0 {
-        Instruction {
-            instruction: InstructionEnum::BInstruction(BInstruction(instruction)),
-        }
-    } else {
-        panic!(
-            "[read_instruction] unrecognized instruction bit 1 is \"{}\" bit 0 is \"{}\"",
-            instruction >> 1 & 1,
-            instruction & 1
-        )
-    }
+    // The 2 low bits of the opcode give the format, memory instructions (0b11) use the I format.
+    let instruction = match instruction & 0b11 {
+        0b00 => InstructionEnum::RInstruction(RInstruction(instruction)),
+        0b10 => InstructionEnum::BInstruction(BInstruction(instruction)),
+        _ => InstructionEnum::IInstruction(IInstruction(instruction)),
+    };
+
+    Instruction { instruction }
 }
 
 #[cfg(test)]
@@ -576,4 +595,35 @@ mod tests {
             InstructionEnum::IInstruction(_)
         ));
     }
+
+    #[test]
+    fn decode_instruction_memory() {
+        let instruction = decode_instruction(0x002A_1203);
+        assert_eq!(instruction.get_opcode(), OpCode::LD);
+        assert_eq!(instruction.get_immediate(), 42);
+        assert_eq!(instruction.get_rd(), 2);
+        assert_eq!(instruction.get_rs1(), 1);
+
+        let instruction = decode_instruction(0x0000_0013);
+        assert_eq!(instruction.get_opcode(), OpCode::STR);
+    }
+
+    #[test]
+    fn decode_opcode() {
+        assert_eq!(OpCode::decode(0b1001_0001), Some(OpCode::RETI));
+        assert_eq!(OpCode::decode(0b0011_0011), Some(OpCode::POP));
+        assert_eq!(OpCode::decode(0b1011_0001), Some(OpCode::WFI));
+        assert_eq!(OpCode::decode(0xFF), None);
+    }
+
+    #[test]
+    fn set_upper_and_lower() {
+        let mut instruction = Instruction::new(OpCode::BLT);
+        instruction.set_upper(0xabc);
+        instruction.set_lower(0xd);
+
+        assert_eq!(instruction.get_upper(), 0xabc);
+        assert_eq!(instruction.get_lower(), 0xd);
+        assert_eq!(instruction.get_opcode(), OpCode::BLT);
+    }
 }
@@ -1,8 +1,9 @@
 use std::collections::HashMap;
 
-use blalst::LSTNodeKind;
+use blalst::{LSTError, LSTNode, LSTNodeKind};
 use blarse::Parser;
 use blex::Lexer;
+use blib::INSTRUCTION_SIZE;
 
 use crate::{ASTError, ASTErrorKind, ASTNode};
 
@@ -11,6 +12,8 @@ pub struct ASTBuilder<'a> {
     parser: Parser<'a>,
     address: u16,
     labels: HashMap<String, u16>,
+    /// Every label of the source, so a label may be used before its definition.
+    resolved: HashMap<String, u16>,
 }
 
 impl<'a> ASTBuilder<'a> {
@@ -19,9 +22,15 @@ impl<'a> ASTBuilder<'a> {
             src,
             parser: Parser::new(lexer),
             labels: HashMap::new(),
+            resolved: resolve_labels(src, Parser::new(lexer)),
             address: 0,
         }
     }
+
+    /// Return the labels defined so far, by name, with their address.
+    pub fn labels(&self) -> &HashMap<String, u16> {
+        &self.labels
+    }
 }
 
 impl<'a> Iterator for ASTBuilder<'a> {
@@ -49,11 +58,38 @@ impl<'a> Iterator for ASTBuilder<'a> {
                     self.labels.insert(label.to_string(), self.address);
                 }
                 LSTNodeKind::Instruction(_) => {
-                    self.address += 1;
+                    self.address += INSTRUCTION_SIZE;
 
-                    return Some((node, self.src, &self.labels).try_into());
+                    return Some((node, self.src, &self.resolved).try_into());
                 }
             }
         }
     }
 }
+
+/// Return the address of every label defined by `nodes`, the first one when
+/// a label is defined twice.
+///
+/// Addresses are counted as [ASTBuilder] does, so labels resolve the same
+/// whether they are used before or after their definition.
+pub fn resolve_labels(
+    src: &str,
+    nodes: impl Iterator<Item = Result<LSTNode, LSTError>>,
+) -> HashMap<String, u16> {
+    let mut labels = HashMap::new();
+    let mut address = 0;
+
+    for node in nodes.flatten() {
+        match node.kind() {
+            LSTNodeKind::EmptyLine => (),
+            LSTNodeKind::Label => {
+                let label = &src[node.span().range()];
+
+                labels.entry(label.to_string()).or_insert(address);
+            }
+            LSTNodeKind::Instruction(_) => address += INSTRUCTION_SIZE,
+        }
+    }
+
+    labels
+}
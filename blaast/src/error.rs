@@ -24,7 +24,9 @@ impl ASTError {
 #[derive(Clone, Debug, PartialEq)]
 pub enum ASTErrorKind {
     BadImmediate,
+    BadOperandCount,
     UnknownInstruction,
+    UnknownLabel,
     DuplicateLabel,
     LSTError(LSTErrorKind),
     ASTErrors(Vec<ASTError>),
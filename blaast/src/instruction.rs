@@ -52,9 +52,15 @@ pub enum ASTInstructionKind {
     XORI,
     SLLI,
     SRLI,
+    JAL,
+    RETI,
+    HALT,
+    WFI,
     // Load/Store Instructions
     LD,
     STR,
+    PUSH,
+    POP,
     // B-Type Instructions
     BE,
     BNE,
@@ -62,6 +68,77 @@ pub enum ASTInstructionKind {
     BGE,
     BLTU,
     BGEU,
+    // Subroutine Instructions
+    CALL,
+    RET,
+}
+
+impl ASTInstructionKind {
+    /// Every instruction kind, in opcode table order.
+    pub const ALL: [ASTInstructionKind; 30] = {
+        use ASTInstructionKind::*;
+
+        [
+            ADD, SUB, OR, AND, XOR, SLL, SRL, ADDI, SUBI, ORI, ANDI, XORI, SLLI, SRLI, JAL, RETI,
+            HALT, WFI, LD, STR, PUSH, POP, BE, BNE, BLT, BGE, BLTU, BGEU, CALL, RET,
+        ]
+    };
+
+    /// The mnemonic used in blasm source for this instruction.
+    ///
+    /// Mnemonics are case-insensitive, this is the lowercase spelling.
+    pub fn mnemonic(&self) -> &'static str {
+        use ASTInstructionKind::*;
+
+        match self {
+            // R-Type Instructions
+            ADD => "add",
+            SUB => "sub",
+            OR => "or",
+            AND => "and",
+            XOR => "xor",
+            SLL => "sll",
+            SRL => "srl",
+            // I-Type Instructions
+            ADDI => "addi",
+            SUBI => "subi",
+            ORI => "ori",
+            ANDI => "andi",
+            XORI => "xori",
+            SLLI => "slli",
+            SRLI => "srli",
+            JAL => "jal",
+            RETI => "reti",
+            HALT => "halt",
+            WFI => "wfi",
+            // Load/Store Instructions
+            LD => "ld",
+            STR => "str",
+            PUSH => "push",
+            POP => "pop",
+            // B-Type Instructions
+            BE => "be",
+            BNE => "bne",
+            BLT => "blt",
+            BGE => "bge",
+            BLTU => "bltu",
+            BGEU => "bgeu",
+            // Subroutine Instructions
+            CALL => "call",
+            RET => "ret",
+        }
+    }
+
+    /// Number of operands expected by the instruction.
+    pub fn arity(&self) -> usize {
+        use ASTInstructionKind::*;
+
+        match self {
+            RET | RETI | HALT | WFI => 0,
+            PUSH | POP | CALL => 1,
+            _ => 3,
+        }
+    }
 }
 
 impl TryFrom<(LSTInstruction, &str, &HashMap<String, u16>)> for ASTInstruction {
@@ -77,10 +154,19 @@ impl TryFrom<(LSTInstruction, &str, &HashMap<String, u16>)> for ASTInstruction {
             .into_iter()
             .map(|operand| (operand, src, labels).try_into());
 
-        let kind = (instruction.kind(), src).try_into();
+        let kind: Result<ASTInstructionKind, _> = (instruction.kind(), src).try_into();
+
+        let count = match kind {
+            Ok(kind) if kind.arity() != instruction.operands().len() => Err(ASTError::new(
+                instruction.span(),
+                ASTErrorKind::BadOperandCount,
+            )),
+            _ => Ok(()),
+        };
 
         let errors: Vec<_> = iter::once(kind.clone())
             .filter_map(|result| result.err())
+            .chain(iter::once(count).filter_map(|result| result.err()))
             .chain(operands.clone().filter_map(|result| result.err()))
             .collect();
 
@@ -102,44 +188,31 @@ impl TryFrom<(LSTInstructionKind, &str)> for ASTInstructionKind {
     type Error = ASTError;
 
     fn try_from((kind, src): (LSTInstructionKind, &str)) -> Result<Self, Self::Error> {
-        use ASTInstructionKind::*;
-
         let span = kind.span();
 
-        let kind = match &src[span.range()] {
-            // R-Type Instructions
-            "add" => ADD,
-            "sub" => SUB,
-            "or" => OR,
-            "and" => AND,
-            "xor" => XOR,
-            "sll" => SLL,
-            "srl" => SRL,
-            // I-Type Instructions
-            "addi" => ADDI,
-            "subi" => SUBI,
-            "ori" => ORI,
-            "andi" => ANDI,
-            "xori" => XORI,
-            "slli" => SLLI,
-            "srli" => SRLI,
-            // Load/Store Instructions
-            "ld" => LD,
-            "str" => STR,
-            // B-Type Instructions
-            "be" => BE,
-            "bne" => BNE,
-            "blt" => BLT,
-            "bge" => BGE,
-            "bltu" => BLTU,
-            "bgeu" => BGEU,
-            _ => {
-                let kind = ASTErrorKind::UnknownInstruction;
-
-                return Err(ASTError::new(span, kind));
-            }
-        };
+        let mnemonic = &src[span.range()];
+
+        ASTInstructionKind::ALL
+            .into_iter()
+            .find(|kind| kind.mnemonic().eq_ignore_ascii_case(mnemonic))
+            .ok_or_else(|| ASTError::new(span, ASTErrorKind::UnknownInstruction))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blex::highlight::MNEMONICS;
+
+    use crate::ASTInstructionKind;
+
+    #[test]
+    fn same_mnemonics_as_lexer_table() {
+        let names: Vec<_> = MNEMONICS.iter().map(|mnemonic| mnemonic.name).collect();
+        let kinds: Vec<_> = ASTInstructionKind::ALL
+            .iter()
+            .map(|kind| kind.mnemonic())
+            .collect();
 
-        Ok(kind)
+        assert_eq!(kinds, names);
     }
 }
@@ -4,7 +4,7 @@ mod instruction;
 mod node;
 mod operand;
 
-pub use ast::ASTBuilder;
+pub use ast::{resolve_labels, ASTBuilder};
 pub use error::{ASTError, ASTErrorKind};
 pub use instruction::{ASTInstruction, ASTInstructionKind};
 pub use node::{ASTNode, ASTNodeKind};
@@ -41,7 +41,7 @@ impl TryFrom<(LSTOperand, &str, &HashMap<String, u16>)> for ASTOperand {
 
         let min = i16::MIN as i32;
         let max = u16::MAX as i32;
-        let range = min..max;
+        let range = min..=max;
 
         match kind {
             LSTOperandKind::Immediate => match src[span.range()].parse() {
@@ -53,13 +53,15 @@ impl TryFrom<(LSTOperand, &str, &HashMap<String, u16>)> for ASTOperand {
                 }
                 _ => Err(ASTError::new(span, ASTErrorKind::BadImmediate)),
             },
-            LSTOperandKind::Label => {
-                let label = &src[span.range()];
-                let kind = ASTOperandKind::Immediate(*labels.get(label).unwrap());
-                let operand = ASTOperand::new(span, kind);
+            LSTOperandKind::Label => match labels.get(&src[span.range()]) {
+                Some(&address) => {
+                    let kind = ASTOperandKind::Immediate(address);
+                    let operand = ASTOperand::new(span, kind);
 
-                Ok(operand)
-            }
+                    Ok(operand)
+                }
+                None => Err(ASTError::new(span, ASTErrorKind::UnknownLabel)),
+            },
         }
     }
 }
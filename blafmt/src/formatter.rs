@@ -0,0 +1,136 @@
+use blalst::{LSTError, LSTInstruction, LSTNodeKind};
+use blarse::Parser;
+use blex::Lexer;
+
+/// Format `src`, or return the first syntax error that prevents it.
+pub fn format(src: &str) -> Result<String, LSTError> {
+    let lexer = Lexer::new(src);
+    let parser = Parser::new(&lexer);
+
+    let mut output = String::with_capacity(src.len());
+    let mut line_open = false;
+
+    for node in parser {
+        let node = node?;
+        let text = &src[node.span().range()];
+
+        match node.kind() {
+            LSTNodeKind::EmptyLine if text.trim().is_empty() => {
+                if line_open {
+                    line_open = false;
+                    output.push('\n');
+                } else if !output.is_empty() && !output.ends_with("\n\n") {
+                    output.push('\n');
+                }
+            }
+            LSTNodeKind::EmptyLine => {
+                if line_open {
+                    output.push(' ');
+                }
+                line_open = true;
+                output.push_str(text.trim());
+            }
+            LSTNodeKind::Label => {
+                if line_open {
+                    output.push(' ');
+                }
+                line_open = true;
+                output.push_str(text);
+            }
+            LSTNodeKind::Instruction(instruction) => {
+                if line_open {
+                    output.push(' ');
+                }
+                line_open = false;
+                instruction_line(&mut output, src, &instruction);
+            }
+        }
+    }
+
+    if line_open {
+        output.push('\n');
+    }
+
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+
+    Ok(output)
+}
+
+fn instruction_line(output: &mut String, src: &str, instruction: &LSTInstruction) {
+    output.push_str(&src[instruction.kind().span().range()].to_ascii_lowercase());
+
+    for (index, operand) in instruction.operands().iter().enumerate() {
+        output.push_str(if index == 0 { " " } else { ", " });
+        output.push_str(&src[operand.span().range()]);
+    }
+
+    if let Some(comment) = instruction.comment() {
+        output.push(' ');
+        output.push_str(src[comment.range()].trim_end());
+    }
+
+    output.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format;
+
+    #[test]
+    fn canonical_spacing() {
+        let src = "add 3,2,  1\nbne 1 , 0,@Loop   # loop\n";
+
+        assert_eq!(
+            format(src).unwrap(),
+            "add 3, 2, 1\nbne 1, 0, @Loop # loop\n"
+        );
+    }
+
+    #[test]
+    fn label_with_comment_or_label() {
+        assert_eq!(format("@Loop # c\n").unwrap(), "@Loop # c\n");
+        assert_eq!(format("@A @B\n").unwrap(), "@A @B\n");
+        assert_eq!(format("@A\t@B   # c\n").unwrap(), "@A @B # c\n");
+    }
+
+    #[test]
+    fn lowercase_mnemonics() {
+        assert_eq!(
+            format("ADDI 1, 0, 1\nBne 1, 0, 0\n").unwrap(),
+            "addi 1, 0, 1\nbne 1, 0, 0\n"
+        );
+    }
+
+    #[test]
+    fn normalise_whitespace() {
+        let src = "@Loop\r\n\tsubi 1,\t1, 1\r\n \r\nbne 1, 0, @Loop";
+
+        assert_eq!(
+            format(src).unwrap(),
+            "@Loop\nsubi 1, 1, 1\n\nbne 1, 0, @Loop\n"
+        );
+    }
+
+    #[test]
+    fn collapse_empty_lines() {
+        let src = "\n\n# header\n\n\n\n@Fibo\naddi 1, 0, 10\n\n\n";
+
+        assert_eq!(format(src).unwrap(), "# header\n\n@Fibo\naddi 1, 0, 10\n");
+    }
+
+    #[test]
+    fn idempotent() {
+        let src = include_str!("../../demos/blasm/fibo.blasm");
+
+        let once = format(src).unwrap();
+
+        assert_eq!(format(&once).unwrap(), once);
+    }
+
+    #[test]
+    fn syntax_error() {
+        assert!(format("add 1, , 2\n").is_err());
+    }
+}
@@ -0,0 +1,24 @@
+//! Formatter for blasm sources.
+//!
+//! The formatter works on the **Lossless Syntax Tree** produced by `blarse`
+//! and prints it back in the canonical blasm layout:
+//! - lowercase mnemonics,
+//! - one space between the mnemonic and its operands,
+//! - operands separated by `", "`,
+//! - a single space before a trailing comment,
+//! - at most one consecutive empty line.
+//!
+//! Example
+//!
+//! ```rust
+//! let src = "addi   1,0 ,  10   # counter\n\n\n@Loop\n";
+//!
+//! assert_eq!(
+//!     blafmt::format(src),
+//!     Ok(String::from("addi 1, 0, 10 # counter\n\n@Loop\n"))
+//! );
+//! ```
+
+mod formatter;
+
+pub use formatter::format;
@@ -20,6 +20,11 @@ impl LSTError {
     pub fn kind(&self) -> LSTErrorKind {
         self.kind
     }
+
+    /// Return the same error moved `offset` bytes further in the input.
+    pub fn shift(&self, offset: usize) -> Self {
+        Self::new(self.span.shift(offset), self.kind)
+    }
 }
 
 impl LSTError {
@@ -31,6 +36,10 @@ impl LSTError {
         Self::new(span, kind)
     }
 
+    pub fn unknown(token: Token) -> Self {
+        Self::new(token.span(), LSTErrorKind::UnknownToken)
+    }
+
     pub fn unexpected(token: Token) -> Self {
         let span = token.span();
 
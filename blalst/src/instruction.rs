@@ -2,19 +2,26 @@ use blib::Span;
 
 use crate::LSTOperand;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct LSTInstruction {
     span: Span,
     kind: LSTInstructionKind,
     operands: Vec<LSTOperand>,
+    comment: Option<Span>,
 }
 
 impl LSTInstruction {
-    pub fn new(span: Span, kind: LSTInstructionKind, operands: Vec<LSTOperand>) -> Self {
+    pub fn new(
+        span: Span,
+        kind: LSTInstructionKind,
+        operands: Vec<LSTOperand>,
+        comment: Option<Span>,
+    ) -> Self {
         Self {
             span,
             kind,
             operands,
+            comment,
         }
     }
 
@@ -29,9 +36,27 @@ impl LSTInstruction {
     pub fn operands(&self) -> Vec<LSTOperand> {
         self.operands.clone()
     }
+
+    /// Span of the trailing comment of the instruction line, if any.
+    pub fn comment(&self) -> Option<Span> {
+        self.comment
+    }
+
+    /// Return the same instruction moved `offset` bytes further in the input.
+    pub fn shift(&self, offset: usize) -> Self {
+        Self::new(
+            self.span.shift(offset),
+            LSTInstructionKind::new(self.kind.span().shift(offset)),
+            self.operands
+                .iter()
+                .map(|operand| operand.shift(offset))
+                .collect(),
+            self.comment.map(|comment| comment.shift(offset)),
+        )
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct LSTInstructionKind {
     span: Span,
 }
@@ -2,7 +2,7 @@ use blib::Span;
 
 use crate::LSTInstruction;
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct LSTNode {
     span: Span,
     kind: LSTNodeKind,
@@ -20,9 +20,21 @@ impl LSTNode {
     pub fn kind(&self) -> LSTNodeKind {
         self.kind.clone()
     }
+
+    /// Return the same node moved `offset` bytes further in the input.
+    pub fn shift(&self, offset: usize) -> Self {
+        let kind = match &self.kind {
+            LSTNodeKind::Instruction(instruction) => {
+                LSTNodeKind::Instruction(instruction.shift(offset))
+            }
+            kind => kind.clone(),
+        };
+
+        Self::new(self.span.shift(offset), kind)
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum LSTNodeKind {
     EmptyLine,
     Instruction(LSTInstruction),
@@ -3,7 +3,7 @@ use blib::Span;
 
 use crate::LSTError;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct LSTOperand {
     span: Span,
     kind: LSTOperandKind,
@@ -21,9 +21,14 @@ impl LSTOperand {
     pub fn kind(&self) -> LSTOperandKind {
         self.kind
     }
+
+    /// Return the same operand moved `offset` bytes further in the input.
+    pub fn shift(&self, offset: usize) -> Self {
+        Self::new(self.span.shift(offset), self.kind)
+    }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum LSTOperandKind {
     Immediate,
     Label,
@@ -0,0 +1,98 @@
+//! Sources that only differ from `corpus/reference.blasm` by their whitespace:
+//! line endings, tabs, indentation, trailing spaces and a missing final line feed.
+
+use blalst::{LSTError, LSTNode, LSTNodeKind};
+use blex::Lexer;
+
+use crate::{IncrementalParser, Parser};
+
+const REFERENCE: &str = include_str!("../corpus/reference.blasm");
+
+const CORPUS: [(&str, &str); 7] = [
+    ("crlf", include_str!("../corpus/crlf.blasm")),
+    (
+        "crlf_no_trailing_newline",
+        include_str!("../corpus/crlf_no_trailing_newline.blasm"),
+    ),
+    ("indented", include_str!("../corpus/indented.blasm")),
+    (
+        "mixed_line_endings",
+        include_str!("../corpus/mixed_line_endings.blasm"),
+    ),
+    (
+        "no_trailing_newline",
+        include_str!("../corpus/no_trailing_newline.blasm"),
+    ),
+    ("tabs", include_str!("../corpus/tabs.blasm")),
+    (
+        "trailing_whitespace",
+        include_str!("../corpus/trailing_whitespace.blasm"),
+    ),
+];
+
+/// Describe `node` without its whitespace, so equivalent sources give the same shape.
+fn shape(src: &str, node: Result<LSTNode, LSTError>) -> String {
+    let node = node.unwrap_or_else(|error| panic!("{error:?} in {src:?}"));
+    let text = |span: blib::Span| src[span.range()].trim();
+
+    match node.kind() {
+        LSTNodeKind::EmptyLine => format!("empty {}", text(node.span())),
+        LSTNodeKind::Label => format!("label {}", text(node.span())),
+        LSTNodeKind::Instruction(instruction) => {
+            let operands: Vec<_> = instruction
+                .operands()
+                .iter()
+                .map(|operand| text(operand.span()))
+                .collect();
+            let comment = instruction.comment().map(text).unwrap_or_default();
+
+            format!(
+                "{} {} {}",
+                text(instruction.kind().span()),
+                operands.join(", "),
+                comment
+            )
+        }
+    }
+}
+
+fn shapes(src: &str) -> Vec<String> {
+    let lexer = Lexer::new(src);
+
+    Parser::new(&lexer).map(|node| shape(src, node)).collect()
+}
+
+#[test]
+fn same_nodes_as_reference() {
+    let reference = shapes(REFERENCE);
+
+    for (name, src) in CORPUS {
+        assert_eq!(shapes(src), reference, "corpus file {name}");
+    }
+}
+
+#[test]
+fn spans_cover_lines() {
+    for (name, src) in CORPUS {
+        let lexer = Lexer::new(src);
+
+        for node in Parser::new(&lexer) {
+            let node = node.unwrap();
+            let text = &src[node.span().range()];
+
+            assert!(!text.is_empty(), "corpus file {name}");
+            assert!(!text[..text.len() - 1].contains('\n'), "corpus file {name}");
+        }
+    }
+}
+
+#[test]
+fn incremental() {
+    for (name, src) in CORPUS {
+        let lexer = Lexer::new(src);
+        let full: Vec<_> = Parser::new(&lexer).collect();
+        let incremental: Vec<_> = IncrementalParser::new(src).nodes().collect();
+
+        assert_eq!(incremental, full, "corpus file {name}");
+    }
+}
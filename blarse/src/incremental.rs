@@ -0,0 +1,272 @@
+use core::ops::Range;
+
+use blalst::{LSTError, LSTNode};
+use blex::Lexer;
+use blib::Span;
+
+use crate::Parser;
+
+/// A change of the source: the bytes in `span` are replaced by `text`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Edit {
+    span: Span,
+    text: String,
+}
+
+impl Edit {
+    pub fn new(span: Span, text: impl Into<String>) -> Self {
+        Self {
+            span,
+            text: text.into(),
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+/// A parsed line. The spans of its nodes are relative to the start of the line,
+/// so lines after an edit are kept as is and only their start moves.
+#[derive(Clone, Debug)]
+struct Line {
+    start: usize,
+    nodes: Vec<Result<LSTNode, LSTError>>,
+}
+
+/// Parser keeping the **LST** of a source up to date across edits.
+///
+/// A node never spans more than one line, so an edit only re-lexes and
+/// re-parses the lines it touches; the nodes of every other line are reused.
+///
+/// Example
+///
+/// ```rust
+/// use blarse::{Edit, IncrementalParser};
+/// use blib::Span;
+///
+/// let mut parser = IncrementalParser::new("addi 1, 0, 1\naddi 2, 0, 2\n");
+///
+/// // Replace the `2` immediate of the second line.
+/// let lines = parser.edit(Edit::new(Span::new(24, 25), "42"));
+///
+/// assert_eq!(parser.src(), "addi 1, 0, 1\naddi 2, 0, 42\n");
+/// assert_eq!(lines, 1..2);
+/// ```
+#[derive(Clone, Debug)]
+pub struct IncrementalParser {
+    src: String,
+    lines: Vec<Line>,
+}
+
+impl IncrementalParser {
+    pub fn new(src: impl Into<String>) -> Self {
+        let mut parser = Self {
+            src: src.into(),
+            lines: Vec::new(),
+        };
+
+        parser.lines = parser.parse_lines(0, parser.src.len(), true);
+
+        parser
+    }
+
+    /// Return the current source.
+    pub fn src(&self) -> &str {
+        &self.src
+    }
+
+    /// Return the nodes of the whole source, as [Parser] would produce them.
+    pub fn nodes(&self) -> impl Iterator<Item = Result<LSTNode, LSTError>> + '_ {
+        self.lines.iter().flat_map(|line| {
+            line.nodes.iter().map(move |node| match node {
+                Ok(node) => Ok(node.shift(line.start)),
+                Err(error) => Err(error.shift(line.start)),
+            })
+        })
+    }
+
+    /// Apply `edit` and return the indices of the lines that were parsed again.
+    ///
+    /// Panics if the span of the edit is out of the source or not on a `char` boundary.
+    pub fn edit(&mut self, edit: Edit) -> Range<usize> {
+        let span = edit.span();
+
+        let first = self.line(span.start());
+        let last = self.line(span.end());
+        let at_eof = last == self.lines.len() - 1;
+
+        let start = self.lines[first].start;
+        let end = match self.lines.get(last + 1) {
+            Some(line) => line.start,
+            None => self.src.len(),
+        };
+
+        self.src.replace_range(span.range(), edit.text());
+
+        let delta = edit.text().len() as isize - span.range().len() as isize;
+        let end = (end as isize + delta) as usize;
+
+        let lines = self.parse_lines(start, end, at_eof);
+        let count = lines.len();
+
+        self.lines.splice(first..=last, lines);
+
+        for line in &mut self.lines[first + count..] {
+            line.start = (line.start as isize + delta) as usize;
+        }
+
+        first..first + count
+    }
+
+    /// Return the index of the line containing `offset`.
+    fn line(&self, offset: usize) -> usize {
+        self.lines.partition_point(|line| line.start <= offset) - 1
+    }
+
+    /// Parse the lines between `start` and `end`, which must be line boundaries.
+    fn parse_lines(&self, start: usize, end: usize, at_eof: bool) -> Vec<Line> {
+        let region = &self.src[start..end];
+
+        let starts: Vec<_> = core::iter::once(start)
+            .chain(
+                region
+                    .match_indices('\n')
+                    .map(|(index, _)| start + index + 1)
+                    .filter(|&next| next < end || at_eof),
+            )
+            .collect();
+
+        starts
+            .iter()
+            .zip(starts.iter().skip(1).copied().chain(core::iter::once(end)))
+            .map(|(&start, end)| {
+                let lexer = Lexer::new(&self.src[start..end]);
+
+                Line {
+                    start,
+                    nodes: Parser::new(&lexer).collect(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use blex::Lexer;
+    use blib::Span;
+
+    use crate::{Edit, IncrementalParser, Parser};
+
+    fn assert_equivalent(parser: &IncrementalParser) {
+        let lexer = Lexer::new(parser.src());
+        let full: Vec<_> = Parser::new(&lexer).collect();
+        let incremental: Vec<_> = parser.nodes().collect();
+
+        assert_eq!(incremental, full, "source: {:?}", parser.src());
+    }
+
+    #[test]
+    fn new() {
+        let src = include_str!("../../demos/blasm/fibo.blasm");
+
+        assert_equivalent(&IncrementalParser::new(src));
+        assert_equivalent(&IncrementalParser::new(""));
+        assert_equivalent(&IncrementalParser::new("@Loop"));
+    }
+
+    #[test]
+    fn edit_in_line() {
+        let mut parser = IncrementalParser::new("addi 1, 0, 1\n@Loop\nbne 1, 0, @Loop\n");
+
+        let lines = parser.edit(Edit::new(Span::new(5, 6), "3"));
+
+        assert_eq!(lines, 0..1);
+        assert_equivalent(&parser);
+    }
+
+    #[test]
+    fn insert_lines() {
+        let mut parser = IncrementalParser::new("addi 1, 0, 1\nbne 1, 0, 0\n");
+
+        let lines = parser.edit(Edit::new(Span::new(13, 13), "@Loop\nsubi 1, 1, 1\n"));
+
+        assert_eq!(lines, 1..4);
+        assert_eq!(
+            parser.src(),
+            "addi 1, 0, 1\n@Loop\nsubi 1, 1, 1\nbne 1, 0, 0\n"
+        );
+        assert_equivalent(&parser);
+    }
+
+    #[test]
+    fn join_lines() {
+        let mut parser = IncrementalParser::new("addi 1, 0, 1\n\nbne 1, 0, 0\n");
+
+        let lines = parser.edit(Edit::new(Span::new(12, 14), ""));
+
+        assert_eq!(lines, 0..1);
+        assert_eq!(parser.src(), "addi 1, 0, 1bne 1, 0, 0\n");
+        assert_equivalent(&parser);
+    }
+
+    #[test]
+    fn edit_at_end() {
+        let mut parser = IncrementalParser::new("addi 1, 0, 1");
+
+        parser.edit(Edit::new(Span::new(12, 12), "\n"));
+        assert_equivalent(&parser);
+
+        parser.edit(Edit::new(Span::new(13, 13), "@end"));
+        assert_equivalent(&parser);
+
+        parser.edit(Edit::new(Span::new(0, 17), ""));
+        assert_equivalent(&parser);
+    }
+
+    #[test]
+    fn random_edits() {
+        const PIECES: [&str; 13] = [
+            "",
+            "\n",
+            "add",
+            " ",
+            ", ",
+            "@Loop",
+            "# note",
+            "1",
+            "bne 1, 0, @Loop\n",
+            ":",
+            "\r\n",
+            "\t",
+            "\r",
+        ];
+
+        let mut parser = IncrementalParser::new(include_str!("../../demos/blasm/fibo.blasm"));
+        let mut seed: u64 = 0x5EED;
+
+        let mut next = |bound: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+
+            (seed >> 33) as usize % bound.max(1)
+        };
+
+        for _ in 0..500 {
+            let len = parser.src().len();
+            let start = next(len + 1);
+            let end = (start + next(8)).min(len);
+            let text = PIECES[next(PIECES.len())];
+
+            parser.edit(Edit::new(Span::new(start, end), text));
+
+            assert_equivalent(&parser);
+        }
+    }
+}
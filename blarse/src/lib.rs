@@ -1,3 +1,7 @@
+#[cfg(test)]
+mod corpus;
+mod incremental;
 mod parser;
 
+pub use incremental::{Edit, IncrementalParser};
 pub use parser::Parser;
@@ -36,9 +36,29 @@ impl<'a> Parser<'a> {
         match self.bump() {
             None => Err(LSTError::expected(kind, self.lexer.len())),
             Some(token) if token.kind() == kind => Ok(token),
+            Some(token) if token.kind() == TokenKind::Unknown => Err(LSTError::unknown(token)),
             Some(token) => Err(LSTError::expected(kind, token.span().start())),
         }
     }
+
+    /// Consume the end of a line, which is a line feed or the end of the input.
+    /// Return the end of the line.
+    fn end_of_line(&mut self) -> Result<usize, LSTError> {
+        if self.peek().is_none() {
+            return Ok(self.lexer.index());
+        }
+
+        self.try_lex(TokenKind::LineFeed).map(|token| token.end())
+    }
+
+    /// Skip the rest of the current line so the next node starts on a fresh line.
+    fn recover(&mut self) {
+        while let Some(token) = self.bump() {
+            if token.kind() == TokenKind::LineFeed {
+                break;
+            }
+        }
+    }
 }
 
 impl<'a> Parser<'a> {
@@ -59,6 +79,7 @@ impl<'a> Iterator for Parser<'a> {
     type Item = Result<LSTNode, LSTError>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        self.lex(TokenKind::Space);
         self.lex(TokenKind::Immediate);
 
         let token = self.bump()?;
@@ -67,9 +88,14 @@ impl<'a> Iterator for Parser<'a> {
             TokenKind::Label => self.label(token),
             TokenKind::LineFeed | TokenKind::Comment => self.empty_line(token),
             TokenKind::Mnemonic => self.instruction(token),
+            TokenKind::Unknown => Err(LSTError::unknown(token)),
             _ => Err(LSTError::unexpected(token)),
         };
 
+        if node.is_err() {
+            self.recover();
+        }
+
         Some(node)
     }
 }
@@ -12,17 +12,15 @@ impl<'a> Parser<'a> {
 
         self.lex(TokenKind::Space);
 
-        self.lex(TokenKind::Comment);
+        let comment = self.lex(TokenKind::Comment).map(|token| token.span());
 
-        let linefeed = self.try_lex(TokenKind::LineFeed)?;
-
-        let end = linefeed.end();
+        let end = self.end_of_line()?;
 
         let span = Span::new(start, end);
 
         let kind = LSTInstructionKind::new(mnemonic.span());
 
-        let instruction = LSTInstruction::new(span, kind, operands);
+        let instruction = LSTInstruction::new(span, kind, operands, comment);
 
         let kind = LSTNodeKind::Instruction(instruction);
 
@@ -62,6 +60,10 @@ impl<'a> Parser<'a> {
     }
 
     fn operand(&mut self) -> Result<LSTOperand, LSTError> {
+        if let Some(token) = self.lex(TokenKind::Unknown) {
+            return Err(LSTError::unknown(token));
+        }
+
         self.lex(TokenKind::Immediate)
             .or_else(|| self.lex(TokenKind::Label))
             .map(|token| token.try_into())
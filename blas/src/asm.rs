@@ -1,18 +1,34 @@
+use std::collections::HashMap;
+
 use blaast::{ASTBuilder, ASTNodeKind};
 use blex::Lexer;
+use blib::Span;
 
 use crate::{ASMError, PseudoInstruction};
 
 pub struct ASM<'a> {
     ast: ASTBuilder<'a>,
+    spans: Vec<Span>,
 }
 
 impl<'a> ASM<'a> {
     pub fn new(src: &'a str, lexer: &'a Lexer) -> Self {
         Self {
             ast: ASTBuilder::new(src, lexer),
+            spans: Vec::new(),
         }
     }
+
+    /// Return the labels defined so far, by name, with their address.
+    pub fn labels(&self) -> &HashMap<String, u16> {
+        self.ast.labels()
+    }
+
+    /// Return the span in the source of each instruction assembled so far, the
+    /// one at address `2 * n` being the `n`th.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
 }
 
 impl<'a> Iterator for ASM<'a> {
@@ -22,7 +38,13 @@ impl<'a> Iterator for ASM<'a> {
         match self.ast.next()? {
             Ok(node) => match node.kind() {
                 ASTNodeKind::Instruction(instruction) => {
-                    return Some(instruction.try_into());
+                    let instruction = instruction.try_into();
+
+                    if instruction.is_ok() {
+                        self.spans.push(node.span());
+                    }
+
+                    return Some(instruction);
                 }
             },
             Err(err) => return Some(Err(err.into())),
@@ -6,6 +6,9 @@ use blaast::ASTInstructionKind;
 use crate::ASMError;
 use crate::Register;
 
+/// Register holding the return address written by `call` and read by `ret`.
+pub const LINK_REGISTER: Register = Register::R14;
+
 /// Pseudo Instructions returned by the assembler.
 ///
 /// R-Type Instructions:
@@ -16,6 +19,9 @@ use crate::Register;
 ///
 /// B-Type Instructions:
 /// B-INSTR(Source Register 1, Source Register 2, Offset)
+///
+/// Subroutine Instructions:
+/// `CALL(Address)` and `RET` are `JAL` with the link register, [LINK_REGISTER].
 #[derive(Debug, PartialEq)]
 pub enum PseudoInstruction {
     // R-Type Instructions
@@ -34,9 +40,15 @@ pub enum PseudoInstruction {
     XORI(Register, Register, u16),
     SLLI(Register, Register, u16),
     SRLI(Register, Register, u16),
+    JAL(Register, Register, u16),
+    RETI,
+    HALT,
+    WFI,
     // Load/Store Instructions
     LD(Register, Register, u16),
     STR(Register, Register, u16),
+    PUSH(Register),
+    POP(Register),
     // B-Type Instructions
     BE(Register, Register, i16),
     BNE(Register, Register, i16),
@@ -44,6 +56,9 @@ pub enum PseudoInstruction {
     BGE(Register, Register, i16),
     BLTU(Register, Register, i16),
     BGEU(Register, Register, i16),
+    // Subroutine Instructions
+    CALL(u16),
+    RET,
 }
 
 impl TryFrom<ASTInstruction> for PseudoInstruction {
@@ -155,6 +170,16 @@ impl TryFrom<ASTInstruction> for PseudoInstruction {
 
                 Ok(SRLI(rd, rs, imm))
             }
+            ASTInstructionKind::JAL => {
+                let rd = operands[0].try_into()?;
+                let rs = operands[1].try_into()?;
+                let imm = operands[2].into();
+
+                Ok(JAL(rd, rs, imm))
+            }
+            ASTInstructionKind::RETI => Ok(RETI),
+            ASTInstructionKind::HALT => Ok(HALT),
+            ASTInstructionKind::WFI => Ok(WFI),
             // Load/Store Instructions
             ASTInstructionKind::LD => {
                 let rd = operands[0].try_into()?;
@@ -170,6 +195,16 @@ impl TryFrom<ASTInstruction> for PseudoInstruction {
 
                 Ok(STR(rd, rs, offset))
             }
+            ASTInstructionKind::PUSH => {
+                let rs = operands[0].try_into()?;
+
+                Ok(PUSH(rs))
+            }
+            ASTInstructionKind::POP => {
+                let rd = operands[0].try_into()?;
+
+                Ok(POP(rd))
+            }
             // B-Type Instructions
             ASTInstructionKind::BE => {
                 let rs = operands[0].try_into()?;
@@ -213,6 +248,13 @@ impl TryFrom<ASTInstruction> for PseudoInstruction {
 
                 Ok(BGEU(rs, rt, offset))
             }
+            // Subroutine Instructions
+            ASTInstructionKind::CALL => {
+                let address = operands[0].into();
+
+                Ok(CALL(address))
+            }
+            ASTInstructionKind::RET => Ok(RET),
         }
     }
 }
@@ -227,7 +269,7 @@ impl From<PseudoInstruction> for Instruction {
             | PseudoInstruction::XOR(rd, rs1, rs2)
             | PseudoInstruction::SLL(rd, rs1, rs2)
             | PseudoInstruction::SRL(rd, rs1, rs2) => {
-                let mut instr = Instruction::new(OpCode::ADD);
+                let mut instr = Instruction::new(OpCode::from(value));
                 instr.set_rd(rd as u32);
                 instr.set_rs1(rs1 as u32);
                 instr.set_rs2(rs2 as u32);
@@ -240,7 +282,8 @@ impl From<PseudoInstruction> for Instruction {
             | PseudoInstruction::ANDI(rd, rs1, imm)
             | PseudoInstruction::XORI(rd, rs1, imm)
             | PseudoInstruction::SLLI(rd, rs1, imm)
-            | PseudoInstruction::SRLI(rd, rs1, imm) => {
+            | PseudoInstruction::SRLI(rd, rs1, imm)
+            | PseudoInstruction::JAL(rd, rs1, imm) => {
                 let mut instr = Instruction::new(OpCode::from(value));
                 instr.set_rd(rd as u32);
                 instr.set_rs1(rs1 as u32);
@@ -256,6 +299,25 @@ impl From<PseudoInstruction> for Instruction {
 
                 instr
             }
+            PseudoInstruction::RETI => Instruction::new(OpCode::RETI),
+            PseudoInstruction::HALT => Instruction::new(OpCode::HALT),
+            PseudoInstruction::WFI => Instruction::new(OpCode::WFI),
+            PseudoInstruction::PUSH(rs) => {
+                let mut instr = Instruction::new(OpCode::PUSH);
+                instr.set_rs1(rs as u32);
+
+                instr
+            }
+            PseudoInstruction::POP(rd) => {
+                let mut instr = Instruction::new(OpCode::POP);
+                instr.set_rd(rd as u32);
+
+                instr
+            }
+            PseudoInstruction::CALL(address) => {
+                PseudoInstruction::JAL(LINK_REGISTER, Register::R0, address).into()
+            }
+            PseudoInstruction::RET => PseudoInstruction::JAL(Register::R0, LINK_REGISTER, 0).into(),
             PseudoInstruction::BE(rs1, rs2, offset)
             | PseudoInstruction::BNE(rs1, rs2, offset)
             | PseudoInstruction::BLT(rs1, rs2, offset)
@@ -265,8 +327,10 @@ impl From<PseudoInstruction> for Instruction {
                 let mut instr = Instruction::new(OpCode::from(value));
                 instr.set_rs1(rs1 as u32);
                 instr.set_rs2(rs2 as u32);
-                instr.set_lower((offset & 15) as u32);
-                instr.set_upper((offset & !15) as u32);
+                // The immediate is the absolute address of the target.
+                let address = offset as u16;
+                instr.set_lower((address & 15) as u32);
+                instr.set_upper((address >> 4) as u32);
 
                 instr
             }
@@ -291,14 +355,21 @@ impl From<PseudoInstruction> for OpCode {
             PseudoInstruction::XORI(_, _, _) => OpCode::XORI,
             PseudoInstruction::SLLI(_, _, _) => OpCode::SLLI,
             PseudoInstruction::SRLI(_, _, _) => OpCode::SRLI,
+            PseudoInstruction::JAL(_, _, _) => OpCode::JAL,
+            PseudoInstruction::RETI => OpCode::RETI,
+            PseudoInstruction::HALT => OpCode::HALT,
+            PseudoInstruction::WFI => OpCode::WFI,
             PseudoInstruction::LD(_, _, _) => OpCode::LD,
             PseudoInstruction::STR(_, _, _) => OpCode::STR,
+            PseudoInstruction::PUSH(_) => OpCode::PUSH,
+            PseudoInstruction::POP(_) => OpCode::POP,
             PseudoInstruction::BE(_, _, _) => OpCode::BE,
             PseudoInstruction::BNE(_, _, _) => OpCode::BNE,
             PseudoInstruction::BLT(_, _, _) => OpCode::BLT,
             PseudoInstruction::BGE(_, _, _) => OpCode::BGE,
             PseudoInstruction::BLTU(_, _, _) => OpCode::BLTU,
             PseudoInstruction::BGEU(_, _, _) => OpCode::BGEU,
+            PseudoInstruction::CALL(_) | PseudoInstruction::RET => OpCode::JAL,
         }
     }
 }
@@ -8,5 +8,5 @@ mod test;
 
 pub use asm::ASM;
 pub use error::{ASMError, ASMErrorKind};
-pub use instruction::PseudoInstruction;
+pub use instruction::{PseudoInstruction, LINK_REGISTER};
 pub use register::Register;
@@ -327,3 +327,167 @@ bgeu 0, 1, @label # Another comment
         PseudoInstruction::BGEU(Register::R0, Register::R1, 0)
     );
 }
+
+#[test]
+fn test_instr_case_insensitive() {
+    let text = "ADD 0, 1, 2\nAddi 0, 1, 2\n";
+    let lexer = Lexer::new(text);
+    let mut asm = ASM::new(text, &lexer);
+
+    assert_eq!(
+        asm.next(),
+        Some(Ok(PseudoInstruction::ADD(
+            Register::R0,
+            Register::R1,
+            Register::R2
+        )))
+    );
+    assert_eq!(
+        asm.next(),
+        Some(Ok(PseudoInstruction::ADDI(Register::R0, Register::R1, 2)))
+    );
+    assert_eq!(asm.next(), None);
+}
+
+#[test]
+fn test_unicode_comment() {
+    let text = "# décrémente le compteur\nsubi 1, 1, 1 # é\n";
+    let lexer = Lexer::new(text);
+    let mut asm = ASM::new(text, &lexer);
+
+    assert_eq!(
+        asm.next(),
+        Some(Ok(PseudoInstruction::SUBI(Register::R1, Register::R1, 1)))
+    );
+    assert_eq!(asm.next(), None);
+}
+
+#[test]
+fn test_label_address_in_words() {
+    let text = "addi 0, 1, 2\n@label\nbe 0, 1, @label\n";
+    let lexer = Lexer::new(text);
+    let mut asm = ASM::new(text, &lexer);
+
+    asm.next();
+
+    assert_eq!(
+        asm.next(),
+        Some(Ok(PseudoInstruction::BE(Register::R0, Register::R1, 2)))
+    );
+}
+
+#[test]
+fn test_labels() {
+    let text = "addi 0, 1, 2\n@first\n@second\nbe 0, 1, @first\n@third\n";
+    let lexer = Lexer::new(text);
+    let mut asm = ASM::new(text, &lexer);
+
+    asm.by_ref().for_each(drop);
+
+    let mut labels: Vec<_> = asm.labels().iter().collect();
+    labels.sort();
+
+    assert_eq!(
+        labels,
+        [
+            (&"@first".to_string(), &2),
+            (&"@second".to_string(), &2),
+            (&"@third".to_string(), &4)
+        ]
+    );
+}
+
+#[test]
+fn test_spans() {
+    let text = "@label\naddi 0, 1, 2 # comment\n\nbe 0, 1, @label\n";
+    let lexer = Lexer::new(text);
+    let mut asm = ASM::new(text, &lexer);
+
+    asm.by_ref().for_each(drop);
+
+    let starts: Vec<usize> = asm.spans().iter().map(|span| span.start()).collect();
+
+    assert_eq!(
+        starts,
+        [text.find("addi").unwrap(), text.find("be 0").unwrap()]
+    );
+}
+
+#[test]
+fn test_encode_opcode() {
+    use asmlib::instruction::{Instruction, OpCode};
+
+    let text = "sub 0, 1, 2\nxor 3, 4, 5\nbltu 1, 2, 4660\n";
+    let lexer = Lexer::new(text);
+    let asm = ASM::new(text, &lexer);
+
+    let instructions: Vec<Instruction> = asm.map(|pseudo| pseudo.unwrap().into()).collect();
+
+    assert_eq!(instructions[0].get_opcode(), OpCode::SUB);
+    assert_eq!(instructions[1].get_opcode(), OpCode::XOR);
+    assert_eq!(instructions[1].get_rd(), 3);
+    assert_eq!(instructions[2].get_opcode(), OpCode::BLTU);
+    assert_eq!(instructions[2].get_upper(), 0x123);
+    assert_eq!(instructions[2].get_lower(), 0x4);
+}
+
+#[test]
+fn test_subroutine() {
+    use asmlib::instruction::{Instruction, OpCode};
+
+    let text = "call 6\nret\npush 1\npop 2\njal 3, 4, 5\n";
+    let lexer = Lexer::new(text);
+    let asm = ASM::new(text, &lexer);
+
+    let pseudo: Vec<_> = asm.map(|pseudo| pseudo.unwrap()).collect();
+
+    assert_eq!(
+        pseudo,
+        [
+            PseudoInstruction::CALL(6),
+            PseudoInstruction::RET,
+            PseudoInstruction::PUSH(Register::R1),
+            PseudoInstruction::POP(Register::R2),
+            PseudoInstruction::JAL(Register::R3, Register::R4, 5),
+        ]
+    );
+
+    let instructions: Vec<Instruction> = pseudo.into_iter().map(Instruction::from).collect();
+
+    assert_eq!(instructions[0].get_opcode(), OpCode::JAL);
+    assert_eq!(instructions[0].get_rd(), LINK_REGISTER as u32);
+    assert_eq!(instructions[0].get_rs1(), 0);
+    assert_eq!(instructions[0].get_immediate(), 6);
+    assert_eq!(instructions[1].get_opcode(), OpCode::JAL);
+    assert_eq!(instructions[1].get_rd(), 0);
+    assert_eq!(instructions[1].get_rs1(), LINK_REGISTER as u32);
+    assert_eq!(instructions[2].get_opcode(), OpCode::PUSH);
+    assert_eq!(instructions[2].get_rs1(), 1);
+    assert_eq!(instructions[3].get_opcode(), OpCode::POP);
+    assert_eq!(instructions[3].get_rd(), 2);
+}
+
+#[test]
+fn test_subroutine_operand_count() {
+    let text = "ret 1\npush\ncall 1, 2\n";
+    let lexer = Lexer::new(text);
+    let mut asm = ASM::new(text, &lexer);
+
+    assert!(asm.all(|pseudo| pseudo.is_err()));
+}
+
+#[test]
+fn test_forward_label() {
+    use asmlib::instruction::Instruction;
+
+    let text = "be 0, 0, @Main\n@Sub\nret\n@Main\ncall @Sub\ncall @Missing\n";
+    let lexer = Lexer::new(text);
+    let mut asm = ASM::new(text, &lexer);
+
+    let branch = Instruction::from(asm.next().unwrap().unwrap());
+    assert_eq!(branch.get_upper() << 4 | branch.get_lower(), 4);
+    assert_eq!(asm.next(), Some(Ok(PseudoInstruction::RET)));
+    assert_eq!(asm.next(), Some(Ok(PseudoInstruction::CALL(2))));
+    assert!(asm.next().unwrap().is_err());
+    assert_eq!(asm.labels().len(), 2);
+}
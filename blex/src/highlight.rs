@@ -0,0 +1,396 @@
+//! Semantic highlighting of blasm sources.
+//!
+//! [highlight] classifies every byte range of an input into a [Category].
+//! The exporters ([ansi], [html] and [textmate]) all use the same
+//! [MNEMONICS] table and the same colours, so every place rendering blasm
+//! shows it the same way.
+
+pub mod ansi;
+pub mod html;
+pub mod textmate;
+
+use blib::Span;
+
+use crate::{Lexer, TokenKind};
+
+/// Semantic category of a range of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Category {
+    /// An instruction mnemonic: `add`.
+    Mnemonic,
+    /// A mnemonic the assembler translates to another instruction: `subi`.
+    PseudoOp,
+    /// An assembler directive. blasm does not have any yet, the category is
+    /// reserved so that themes and grammars already know about it.
+    Directive,
+    /// An operand naming a register.
+    Register,
+    /// A label at the start of a line: `@Loop`.
+    LabelDefinition,
+    /// A label used as an operand.
+    LabelReference,
+    /// An immediate operand.
+    Number,
+    /// A comment, from `#` to the end of the line.
+    Comment,
+    /// Anything that is not valid blasm.
+    Error,
+    /// Operand separators.
+    Punctuation,
+    /// Spaces and line feeds.
+    Whitespace,
+}
+
+impl Category {
+    /// Every category that is rendered with a colour.
+    pub const STYLED: [Category; 9] = [
+        Category::Mnemonic,
+        Category::PseudoOp,
+        Category::Directive,
+        Category::Register,
+        Category::LabelDefinition,
+        Category::LabelReference,
+        Category::Number,
+        Category::Comment,
+        Category::Error,
+    ];
+
+    /// Short name of the category, used for CSS classes.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Category::Mnemonic => "mnemonic",
+            Category::PseudoOp => "pseudo-op",
+            Category::Directive => "directive",
+            Category::Register => "register",
+            Category::LabelDefinition => "label-definition",
+            Category::LabelReference => "label-reference",
+            Category::Number => "number",
+            Category::Comment => "comment",
+            Category::Error => "error",
+            Category::Punctuation => "punctuation",
+            Category::Whitespace => "whitespace",
+        }
+    }
+
+    /// TextMate scope of the category.
+    pub fn scope(&self) -> &'static str {
+        match self {
+            Category::Mnemonic => "keyword.other.mnemonic.blasm",
+            Category::PseudoOp => "keyword.other.pseudo.blasm",
+            Category::Directive => "keyword.control.directive.blasm",
+            Category::Register => "variable.other.register.blasm",
+            Category::LabelDefinition => "entity.name.label.blasm",
+            Category::LabelReference => "variable.other.label.blasm",
+            Category::Number => "constant.numeric.blasm",
+            Category::Comment => "comment.line.number-sign.blasm",
+            Category::Error => "invalid.illegal.blasm",
+            Category::Punctuation => "punctuation.separator.blasm",
+            Category::Whitespace => "text.whitespace.blasm",
+        }
+    }
+
+    /// RGB colour of the category, `None` for unstyled categories.
+    pub fn color(&self) -> Option<(u8, u8, u8)> {
+        match self {
+            Category::Mnemonic => Some((0x56, 0x9C, 0xD6)),
+            Category::PseudoOp => Some((0xC5, 0x86, 0xC0)),
+            Category::Directive => Some((0xD7, 0xBA, 0x7D)),
+            Category::Register => Some((0x9C, 0xDC, 0xFE)),
+            Category::LabelDefinition => Some((0xDC, 0xDC, 0xAA)),
+            Category::LabelReference => Some((0x4E, 0xC9, 0xB0)),
+            Category::Number => Some((0xB5, 0xCE, 0xA8)),
+            Category::Comment => Some((0x6A, 0x99, 0x55)),
+            Category::Error => Some((0xF4, 0x47, 0x47)),
+            Category::Punctuation | Category::Whitespace => None,
+        }
+    }
+}
+
+/// Operand layout of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `rd, rs1, rs2`: three registers.
+    R,
+    /// `rd, rs1, imm`: two registers and an immediate.
+    I,
+    /// `rs1, rs2, address`: two registers and an immediate or a label.
+    B,
+    /// `register`: one register, pushed to or popped from the stack.
+    S,
+    /// `address`: at most an immediate or a label, for jumps through the link
+    /// register or the stack and instructions without operands.
+    J,
+}
+
+impl Format {
+    /// Number of leading operands that are registers.
+    pub fn registers(&self) -> usize {
+        match self {
+            Format::R => 3,
+            Format::I | Format::B => 2,
+            Format::S => 1,
+            Format::J => 0,
+        }
+    }
+
+    /// Maximum number of operands.
+    pub fn operands(&self) -> usize {
+        match self {
+            Format::R | Format::I | Format::B => 3,
+            Format::S | Format::J => 1,
+        }
+    }
+}
+
+/// An entry of the mnemonic table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Mnemonic {
+    pub name: &'static str,
+    pub format: Format,
+    pub pseudo: bool,
+}
+
+const fn mnemonic(name: &'static str, format: Format, pseudo: bool) -> Mnemonic {
+    Mnemonic {
+        name,
+        format,
+        pseudo,
+    }
+}
+
+/// Every mnemonic known by the assembler.
+pub const MNEMONICS: [Mnemonic; 30] = [
+    // R-Type Instructions
+    mnemonic("add", Format::R, false),
+    mnemonic("sub", Format::R, false),
+    mnemonic("or", Format::R, false),
+    mnemonic("and", Format::R, false),
+    mnemonic("xor", Format::R, false),
+    mnemonic("sll", Format::R, false),
+    mnemonic("srl", Format::R, false),
+    // I-Type Instructions
+    mnemonic("addi", Format::I, false),
+    mnemonic("subi", Format::I, true),
+    mnemonic("ori", Format::I, false),
+    mnemonic("andi", Format::I, false),
+    mnemonic("xori", Format::I, false),
+    mnemonic("slli", Format::I, false),
+    mnemonic("srli", Format::I, false),
+    mnemonic("jal", Format::I, false),
+    mnemonic("reti", Format::J, false),
+    mnemonic("halt", Format::J, false),
+    mnemonic("wfi", Format::J, false),
+    // Load/Store Instructions
+    mnemonic("ld", Format::I, false),
+    mnemonic("str", Format::I, false),
+    mnemonic("push", Format::S, false),
+    mnemonic("pop", Format::S, false),
+    // B-Type Instructions
+    mnemonic("be", Format::B, false),
+    mnemonic("bne", Format::B, false),
+    mnemonic("blt", Format::B, false),
+    mnemonic("bge", Format::B, false),
+    mnemonic("bltu", Format::B, false),
+    mnemonic("bgeu", Format::B, false),
+    // Subroutine Instructions
+    mnemonic("call", Format::J, true),
+    mnemonic("ret", Format::J, true),
+];
+
+/// Return the entry of [MNEMONICS] named `name`, ignoring case.
+pub fn find_mnemonic(name: &str) -> Option<&'static Mnemonic> {
+    MNEMONICS
+        .iter()
+        .find(|mnemonic| mnemonic.name.eq_ignore_ascii_case(name))
+}
+
+/// A classified range of the input.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Highlight {
+    span: Span,
+    category: Category,
+}
+
+impl Highlight {
+    pub fn new(span: Span, category: Category) -> Self {
+        Self { span, category }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn category(&self) -> Category {
+        self.category
+    }
+}
+
+/// Classify every byte range of `src`.
+///
+/// The ranges are contiguous and cover the whole input.
+///
+/// Example
+///
+/// ```rust
+/// use blex::highlight::{highlight, Category};
+///
+/// let categories: Vec<_> = highlight("bne 1, 0, @Loop")
+///     .iter()
+///     .map(|highlight| highlight.category())
+///     .filter(|category| *category != Category::Whitespace)
+///     .collect();
+///
+/// assert_eq!(
+///     categories,
+///     [
+///         Category::Mnemonic,
+///         Category::Register,
+///         Category::Punctuation,
+///         Category::Register,
+///         Category::Punctuation,
+///         Category::LabelReference,
+///     ]
+/// );
+/// ```
+pub fn highlight(src: &str) -> Vec<Highlight> {
+    let lexer = Lexer::new(src);
+
+    let mut highlights = Vec::new();
+
+    // Instruction of the current line and index of the operand being read.
+    let mut instruction: Option<Option<&Mnemonic>> = None;
+    let mut operand = 0;
+    let mut line_start = true;
+
+    for token in &lexer {
+        let category = match token.kind() {
+            // Lines may be indented, leading whitespace keeps the line start.
+            TokenKind::Space => {
+                highlights.push(Highlight::new(token.span(), Category::Whitespace));
+                continue;
+            }
+            TokenKind::LineFeed => {
+                instruction = None;
+                operand = 0;
+                line_start = true;
+
+                highlights.push(Highlight::new(token.span(), Category::Whitespace));
+                continue;
+            }
+            TokenKind::Comment => Category::Comment,
+            TokenKind::Comma => {
+                operand += 1;
+                Category::Punctuation
+            }
+            TokenKind::Label if line_start => Category::LabelDefinition,
+            TokenKind::Label if instruction.is_some() => Category::LabelReference,
+            TokenKind::Mnemonic if line_start => {
+                let mnemonic = find_mnemonic(&src[token.span().range()]);
+
+                instruction = Some(mnemonic);
+
+                match mnemonic {
+                    Some(mnemonic) if mnemonic.pseudo => Category::PseudoOp,
+                    Some(_) => Category::Mnemonic,
+                    None => Category::Error,
+                }
+            }
+            TokenKind::Immediate => match instruction {
+                Some(Some(mnemonic)) if operand < mnemonic.format.registers() => Category::Register,
+                _ => Category::Number,
+            },
+            TokenKind::Label | TokenKind::Mnemonic | TokenKind::Unknown => Category::Error,
+        };
+
+        line_start = false;
+
+        highlights.push(Highlight::new(token.span(), category));
+    }
+
+    highlights
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{highlight, Category, Highlight};
+
+    #[test]
+    fn instruction_lines() {
+        use Category::*;
+
+        let src = "@Loop\nsubi 1, 1, 1 # dec\nadd 4, 0, 3\nfoo 1\n:";
+
+        let categories: Vec<_> = highlight(src)
+            .into_iter()
+            .map(|highlight| (&src[highlight.span().range()], highlight.category()))
+            .filter(|(_, category)| !matches!(category, Whitespace | Punctuation))
+            .collect();
+
+        assert_eq!(
+            categories,
+            [
+                ("@Loop", LabelDefinition),
+                ("subi", PseudoOp),
+                ("1", Register),
+                ("1", Register),
+                ("1", Number),
+                ("# dec", Comment),
+                ("add", Mnemonic),
+                ("4", Register),
+                ("0", Register),
+                ("3", Register),
+                ("foo", Error),
+                ("1", Number),
+                (":", Error),
+            ]
+        );
+    }
+
+    #[test]
+    fn indented_lines() {
+        use Category::*;
+
+        let src = "  @Loop\n\taddi 1, 1, 1\n \t be 1, 0, @Loop\n";
+
+        let categories: Vec<_> = highlight(src)
+            .into_iter()
+            .map(|highlight| (&src[highlight.span().range()], highlight.category()))
+            .filter(|(_, category)| !matches!(category, Whitespace | Punctuation))
+            .collect();
+
+        assert_eq!(
+            categories,
+            [
+                ("@Loop", LabelDefinition),
+                ("addi", Mnemonic),
+                ("1", Register),
+                ("1", Register),
+                ("1", Number),
+                ("be", Mnemonic),
+                ("1", Register),
+                ("0", Register),
+                ("@Loop", LabelReference),
+            ]
+        );
+    }
+
+    #[test]
+    fn covers_input() {
+        let src = include_str!("../../demos/blasm/red_square.blasm");
+
+        let highlights = highlight(src);
+
+        assert_eq!(
+            highlights.first().map(Highlight::span).map(|s| s.start()),
+            Some(0)
+        );
+        assert_eq!(
+            highlights.last().map(Highlight::span).map(|s| s.end()),
+            Some(src.len())
+        );
+
+        for pair in highlights.windows(2) {
+            assert_eq!(pair[0].span().end(), pair[1].span().start());
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! ANSI terminal colouring.
+
+use super::{highlight, Category};
+
+/// Escape sequence starting the colour of `category`, if it has one.
+pub fn escape(category: Category) -> Option<String> {
+    let (r, g, b) = category.color()?;
+
+    let bold = match category {
+        Category::Mnemonic | Category::PseudoOp | Category::Directive => "1;",
+        _ => "",
+    };
+
+    Some(format!("\x1b[{bold}38;2;{r};{g};{b}m"))
+}
+
+/// Escape sequence resetting the colour.
+pub const RESET: &str = "\x1b[0m";
+
+/// Return `src` coloured with 24-bit ANSI escape sequences.
+///
+/// Example
+///
+/// ```rust
+/// use blex::highlight::ansi;
+///
+/// let coloured = ansi::render("add 1, 2, 3\n");
+///
+/// assert!(coloured.starts_with("\x1b[1;38;2;86;156;214madd\x1b[0m"));
+/// ```
+pub fn render(src: &str) -> String {
+    let mut output = String::with_capacity(src.len() * 2);
+
+    for highlight in highlight(src) {
+        let text = &src[highlight.span().range()];
+
+        match escape(highlight.category()) {
+            Some(escape) => {
+                output.push_str(&escape);
+                output.push_str(text);
+                output.push_str(RESET);
+            }
+            None => output.push_str(text),
+        }
+    }
+
+    output
+}
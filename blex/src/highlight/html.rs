@@ -0,0 +1,103 @@
+//! HTML rendering.
+//!
+//! Every range is wrapped in a `<span>` whose class is `blasm-` followed by
+//! [Category::name]; [stylesheet] gives the matching colours.
+
+use super::{highlight, Category};
+
+/// CSS class of `category`.
+pub fn class(category: Category) -> String {
+    format!("blasm-{}", category.name())
+}
+
+/// Return `src` as an HTML `<pre>` block.
+///
+/// Example
+///
+/// ```rust
+/// use blex::highlight::html;
+///
+/// assert_eq!(
+///     html::render("# a < b\n"),
+///     "<pre class=\"blasm\"><code><span class=\"blasm-comment\"># a &lt; b</span>\n</code></pre>"
+/// );
+/// ```
+pub fn render(src: &str) -> String {
+    let mut output = String::from("<pre class=\"blasm\"><code>");
+
+    for highlight in highlight(src) {
+        let text = escape(&src[highlight.span().range()]);
+
+        match highlight.category() {
+            Category::Whitespace | Category::Punctuation => output.push_str(&text),
+            category => {
+                output.push_str(&format!(
+                    "<span class=\"{}\">{text}</span>",
+                    class(category)
+                ));
+            }
+        }
+    }
+
+    output.push_str("</code></pre>");
+
+    output
+}
+
+/// Return the CSS rules colouring the output of [render].
+pub fn stylesheet() -> String {
+    let mut css = String::new();
+
+    for category in Category::STYLED {
+        if let Some((r, g, b)) = category.color() {
+            css.push_str(&format!(
+                ".blasm .{} {{ color: #{r:02x}{g:02x}{b:02x}; }}\n",
+                class(category)
+            ));
+        }
+    }
+
+    css
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, stylesheet};
+
+    #[test]
+    fn instruction() {
+        assert_eq!(
+            render("bne 1, 0, @Loop"),
+            "<pre class=\"blasm\"><code>\
+             <span class=\"blasm-mnemonic\">bne</span> \
+             <span class=\"blasm-register\">1</span>, \
+             <span class=\"blasm-register\">0</span>, \
+             <span class=\"blasm-label-reference\">@Loop</span>\
+             </code></pre>"
+        );
+    }
+
+    #[test]
+    fn every_class_is_styled() {
+        let css = stylesheet();
+
+        assert_eq!(css.lines().count(), 9);
+        assert!(css.contains(".blasm .blasm-mnemonic { color: #569cd6; }"));
+    }
+}
@@ -0,0 +1,174 @@
+//! TextMate grammar generation.
+//!
+//! The grammar is generated from [MNEMONICS] so editors, the handbook and any
+//! tool using TextMate grammars recognise exactly the mnemonics the assembler
+//! knows, with the scopes of [Category::scope].
+
+use super::{Category, Format, MNEMONICS};
+
+/// Scope name of the blasm language.
+pub const SCOPE: &str = "source.blasm";
+
+const NUMBER: &str = r"-?[0-9]+";
+const LABEL: &str = r"@[A-Za-z0-9]+";
+
+/// Return the TextMate grammar of blasm, as JSON.
+pub fn grammar() -> String {
+    // Mnemonics are case-insensitive.
+    let mut patterns = vec![
+        rule(r"#.*$", Category::Comment),
+        rule(&format!(r"^\s*{LABEL}"), Category::LabelDefinition),
+    ];
+
+    for format in [Format::R, Format::I, Format::B, Format::S, Format::J] {
+        for pseudo in [false, true] {
+            if let Some(pattern) = instruction(format, pseudo) {
+                patterns.push(pattern);
+            }
+        }
+    }
+
+    patterns.push(rule(LABEL, Category::LabelReference));
+    patterns.push(rule(NUMBER, Category::Number));
+    patterns.push(rule(r"[^\s,#]+", Category::Error));
+
+    format!(
+        concat!(
+            "{{\n",
+            "  \"name\": \"blasm\",\n",
+            "  \"scopeName\": \"{}\",\n",
+            "  \"fileTypes\": [\"blasm\"],\n",
+            "  \"patterns\": [\n{}\n  ]\n",
+            "}}\n",
+        ),
+        SCOPE,
+        patterns.join(",\n")
+    )
+}
+
+/// Rule matching the instruction lines of `format` with their operands.
+fn instruction(format: Format, pseudo: bool) -> Option<String> {
+    let names: Vec<_> = MNEMONICS
+        .iter()
+        .filter(|mnemonic| mnemonic.format == format && mnemonic.pseudo == pseudo)
+        .map(|mnemonic| mnemonic.name)
+        .collect();
+
+    if names.is_empty() {
+        return None;
+    }
+
+    let mnemonic = if pseudo {
+        Category::PseudoOp
+    } else {
+        Category::Mnemonic
+    };
+
+    let operand = |index| {
+        if index < format.registers() {
+            format!("({NUMBER})")
+        } else {
+            format!("({NUMBER})|({LABEL})")
+        }
+    };
+
+    // Operands are optional so that lines being typed are still highlighted.
+    let mut operands = String::new();
+
+    for index in (0..format.operands()).rev() {
+        let separator = if index == 0 { r"\s+" } else { r"\s*,\s*" };
+
+        operands = format!("(?:{separator}(?:{}){operands})?", operand(index));
+    }
+
+    let regex = format!(r"^\s*(?i:({}))\b{operands}", names.join("|"));
+
+    let mut captures = vec![(1, mnemonic)];
+
+    for index in 0..format.operands() {
+        let group = captures.len() + 1;
+
+        if index < format.registers() {
+            captures.push((group, Category::Register));
+        } else {
+            captures.push((group, Category::Number));
+            captures.push((group + 1, Category::LabelReference));
+        }
+    }
+
+    let captures: Vec<_> = captures
+        .into_iter()
+        .map(|(index, category)| {
+            format!(
+                "        \"{index}\": {{ \"name\": \"{}\" }}",
+                category.scope()
+            )
+        })
+        .collect();
+
+    Some(format!(
+        "    {{\n      \"match\": \"{}\",\n      \"captures\": {{\n{}\n      }}\n    }}",
+        escape(&regex),
+        captures.join(",\n")
+    ))
+}
+
+fn rule(regex: &str, category: Category) -> String {
+    format!(
+        "    {{ \"match\": \"{}\", \"name\": \"{}\" }}",
+        escape(regex),
+        category.scope()
+    )
+}
+
+/// Escape `text` as the content of a JSON string.
+fn escape(text: &str) -> String {
+    text.replace('\\', r"\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::{grammar, SCOPE};
+    use crate::highlight::MNEMONICS;
+
+    #[test]
+    fn valid_json() {
+        let grammar: Value = serde_json::from_str(&grammar()).unwrap();
+
+        assert_eq!(grammar["scopeName"], SCOPE);
+        assert_eq!(grammar["patterns"].as_array().unwrap().len(), 12);
+    }
+
+    #[test]
+    fn indented_lines() {
+        let grammar: Value = serde_json::from_str(&grammar()).unwrap();
+        let patterns = grammar["patterns"].as_array().unwrap();
+
+        // Label definitions and every instruction rule allow leading whitespace
+        let anchored: Vec<_> = patterns
+            .iter()
+            .filter_map(|pattern| pattern["match"].as_str())
+            .filter(|regex| regex.starts_with('^'))
+            .collect();
+
+        // Only comments, label references, numbers and errors match anywhere
+        assert_eq!(anchored.len(), patterns.len() - 4);
+        assert!(anchored.iter().all(|regex| regex.starts_with(r"^\s*")));
+    }
+
+    #[test]
+    fn every_mnemonic() {
+        let grammar = grammar();
+
+        for mnemonic in MNEMONICS {
+            assert!(
+                grammar.contains(&format!("{}|", mnemonic.name))
+                    || grammar.contains(&format!("{})", mnemonic.name)),
+                "{} is missing",
+                mnemonic.name
+            );
+        }
+    }
+}
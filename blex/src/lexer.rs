@@ -120,7 +120,7 @@ impl<'a> LexerIter<'a> {
         self.index == self.len
     }
 
-    /// Indicates the current index in the Lexer input.
+    /// Indicates the current index in the Lexer input, in bytes.
     pub fn index(&self) -> usize {
         self.index
     }
@@ -137,8 +137,14 @@ impl<'a> LexerIter<'a> {
 
         let start = self.index();
 
-        let kind = match self.chars.next()? {
-            ' ' => self.space(),
+        let c = self.chars.next()?;
+
+        let kind = match c {
+            '\r' if self.chars.peek() == Some(&'\n') => {
+                self.eat();
+                TokenKind::LineFeed
+            }
+            ' ' | '\t' | '\r' => self.space(),
             '#' => self.comment(),
             ',' => TokenKind::Comma,
             '-' | '0'..='9' => self.immediate(),
@@ -146,12 +152,12 @@ impl<'a> LexerIter<'a> {
                 self.label()
             }
             '\n' => TokenKind::LineFeed,
-            'a'..='z' => self.mnemonic(),
+            'a'..='z' | 'A'..='Z' => self.mnemonic(),
             _ => TokenKind::Unknown,
         };
 
         // In every cases we need to eat at least 1 item.
-        self.index += 1;
+        self.index += c.len_utf8();
 
         let end = self.index();
 
@@ -160,14 +166,19 @@ impl<'a> LexerIter<'a> {
         Some(Token::new(span, kind))
     }
 
+    /// Consume the next character, keeping `index` a byte offset in the input.
+    fn eat(&mut self) {
+        if let Some(c) = self.chars.next() {
+            self.index += c.len_utf8();
+        }
+    }
+
     fn comment(&mut self) -> TokenKind {
         loop {
-            match self.chars.peek() {
+            match self.chars.peek().copied() {
                 Some('\n') | None => break,
-                Some(_) => {
-                    self.chars.next();
-                    self.index += 1;
-                }
+                Some('\r') if self.chars.clone().nth(1) == Some('\n') => break,
+                Some(_) => self.eat(),
             }
         }
 
@@ -176,8 +187,7 @@ impl<'a> LexerIter<'a> {
 
     fn immediate(&mut self) -> TokenKind {
         while let Some('0'..='9') = self.chars.peek() {
-            self.chars.next();
-            self.index += 1;
+            self.eat();
         }
 
         TokenKind::Immediate
@@ -185,26 +195,28 @@ impl<'a> LexerIter<'a> {
 
     fn label(&mut self) -> TokenKind {
         while let Some('a'..='z' | 'A'..='Z' | '0'..='9') = self.chars.peek() {
-            self.chars.next();
-            self.index += 1;
+            self.eat();
         }
 
         TokenKind::Label
     }
 
     fn mnemonic(&mut self) -> TokenKind {
-        while let Some('a'..='z') = self.chars.peek() {
-            self.chars.next();
-            self.index += 1;
+        while let Some('a'..='z' | 'A'..='Z') = self.chars.peek() {
+            self.eat();
         }
 
         TokenKind::Mnemonic
     }
 
+    /// Tabs and carriage returns not followed by a line feed are spaces.
     fn space(&mut self) -> TokenKind {
-        while let Some(' ') = self.chars.peek() {
-            self.chars.next();
-            self.index += 1;
+        loop {
+            match self.chars.peek().copied() {
+                Some(' ' | '\t') => self.eat(),
+                Some('\r') if self.chars.clone().nth(1) != Some('\n') => self.eat(),
+                _ => break,
+            }
         }
 
         TokenKind::Space
@@ -294,6 +306,50 @@ mod tests {
         assert_eq!(it.next(), None);
     }
 
+    #[test]
+    fn carriage_return_line_feed() {
+        // Given
+        let line = "#\r\n\r\n";
+        let lexer = Lexer::new(line);
+        let mut it = lexer.iter();
+
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(0, 1), TokenKind::Comment))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(1, 3), TokenKind::LineFeed))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(3, 5), TokenKind::LineFeed))
+        );
+        assert_eq!(it.next(), None);
+    }
+
+    #[test]
+    fn whitespace() {
+        // Given
+        let line = " \t\r \r\n\t";
+        let lexer = Lexer::new(line);
+        let mut it = lexer.iter();
+
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(0, 4), TokenKind::Space))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(4, 6), TokenKind::LineFeed))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(6, 7), TokenKind::Space))
+        );
+        assert_eq!(it.next(), None);
+    }
+
     #[test]
     fn immediate() {
         // Given
@@ -350,6 +406,70 @@ mod tests {
         assert_eq!(it.next(), None);
     }
 
+    #[test]
+    fn uppercase_mnemonic() {
+        // Given
+        let line = "ADD Bne";
+        let lexer = Lexer::new(line);
+        let mut it = lexer.iter();
+
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(0, 3), TokenKind::Mnemonic))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(3, 4), TokenKind::Space))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(4, 7), TokenKind::Mnemonic))
+        );
+        assert_eq!(it.next(), None);
+    }
+
+    #[test]
+    fn unicode_comment() {
+        // Given
+        let line = "# décrémente\nadd";
+        let lexer = Lexer::new(line);
+        let mut it = lexer.iter();
+
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(0, 14), TokenKind::Comment))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(14, 15), TokenKind::LineFeed))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(15, 18), TokenKind::Mnemonic))
+        );
+        assert_eq!(it.next(), None);
+        assert!(it.eof());
+        assert_eq!(&line[15..18], "add");
+    }
+
+    #[test]
+    fn unknown() {
+        // Given
+        let line = "é,";
+        let lexer = Lexer::new(line);
+        let mut it = lexer.iter();
+
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(0, 2), TokenKind::Unknown))
+        );
+        assert_eq!(
+            it.next(),
+            Some(Token::new(Span::new(2, 3), TokenKind::Comma))
+        );
+        assert_eq!(it.next(), None);
+    }
+
     #[test]
     fn negatif_number() {
         // Given
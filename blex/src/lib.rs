@@ -23,6 +23,7 @@
 //!
 //! ```
 
+pub mod highlight;
 mod lexer;
 mod token;
 
@@ -72,11 +72,12 @@ pub enum TokenKind {
     Immediate,
     /// | '@' ('0' .. '9' | 'a' .. 'z' | 'A' .. 'Z')+
     Label,
-    /// | '\n'
+    /// | ('\r')? '\n'
     LineFeed,
-    /// | ('a' .. 'z')+
+    /// | ('a' .. 'z' | 'A' .. 'Z')+
     Mnemonic,
-    /// | ' '
+    /// | (' ' | '\t' | '\r')+
     Space,
+    /// Any other character.
     Unknown,
 }
@@ -3,3 +3,6 @@ mod span;
 
 pub use register::Register;
 pub use span::Span;
+
+/// Number of 16-bit words an instruction takes in memory.
+pub const INSTRUCTION_SIZE: u16 = 2;
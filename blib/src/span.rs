@@ -1,5 +1,6 @@
 use core::ops::Range;
 
+/// A range of bytes in the input.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct Span {
     start: usize,
@@ -22,4 +23,9 @@ impl Span {
     pub fn range(&self) -> Range<usize> {
         self.start..self.end
     }
+
+    /// Return the same span moved `offset` bytes further in the input.
+    pub fn shift(&self, offset: usize) -> Self {
+        Self::new(self.start + offset, self.end + offset)
+    }
 }
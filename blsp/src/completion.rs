@@ -0,0 +1,70 @@
+use blaast::ASTInstructionKind;
+use blib::Register;
+use lsp_types::{CompletionItem, CompletionItemKind};
+
+use crate::{hover, Document};
+
+/// Completion items at `offset`, based on the text before it on the same line.
+pub fn complete(document: &Document, offset: usize) -> Vec<CompletionItem> {
+    let text = document.text();
+    let offset = offset.min(text.len());
+
+    let line_start = text[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let prefix = text[line_start..offset].trim_start();
+
+    if prefix.contains('#') {
+        return Vec::new();
+    }
+
+    let word = prefix.rsplit([' ', '\t', ',']).next().unwrap_or_default();
+
+    if !prefix.contains([' ', '\t']) && !word.starts_with('@') {
+        mnemonics()
+    } else if word.starts_with('@') {
+        labels(document)
+    } else {
+        registers().chain(labels(document)).collect()
+    }
+}
+
+fn mnemonics() -> Vec<CompletionItem> {
+    ASTInstructionKind::ALL
+        .iter()
+        .map(|kind| {
+            let (format, semantics, _) = hover::summary(*kind);
+
+            CompletionItem {
+                label: kind.mnemonic().to_string(),
+                kind: Some(CompletionItemKind::KEYWORD),
+                detail: Some(format!("{format}-type: {semantics}")),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+fn registers() -> impl Iterator<Item = CompletionItem> {
+    (0..16).map(|value| {
+        let register = Register::try_from(value).unwrap();
+
+        CompletionItem {
+            label: value.to_string(),
+            kind: Some(CompletionItemKind::VARIABLE),
+            detail: Some(format!("register {register:?}")),
+            ..Default::default()
+        }
+    })
+}
+
+fn labels(document: &Document) -> Vec<CompletionItem> {
+    document
+        .labels()
+        .iter()
+        .map(|label| CompletionItem {
+            label: document.slice(label.span).to_string(),
+            kind: Some(CompletionItemKind::REFERENCE),
+            detail: Some(format!("address {}", label.address)),
+            ..Default::default()
+        })
+        .collect()
+}
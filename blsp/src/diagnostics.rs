@@ -0,0 +1,92 @@
+use blaast::{ASTError, ASTErrorKind};
+use blalst::LSTErrorKind;
+use blas::{ASMError, ASMErrorKind};
+use blex::TokenKind;
+use blib::Span;
+
+/// An error found in a document, ready to be reported to the client.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    span: Span,
+    message: String,
+}
+
+impl Diagnostic {
+    pub fn new(span: Span, message: String) -> Self {
+        Self { span, message }
+    }
+
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+/// Flatten an [ASTError] into one [Diagnostic] per leaf error.
+pub fn from_ast(error: &ASTError, src: &str, diagnostics: &mut Vec<Diagnostic>) {
+    ast_kind(error.span(), &error.kind(), src, diagnostics)
+}
+
+/// Flatten an [ASMError] into one [Diagnostic] per leaf error.
+pub fn from_asm(error: &ASMError, src: &str, diagnostics: &mut Vec<Diagnostic>) {
+    match error.kind() {
+        ASMErrorKind::ASTError(kind) => ast_kind(error.span(), &kind, src, diagnostics),
+        ASMErrorKind::InvalidRegister => {
+            let message = String::from("invalid register, expected a value from 0 to 15");
+
+            diagnostics.push(Diagnostic::new(error.span(), message))
+        }
+    }
+}
+
+fn ast_kind(span: Span, kind: &ASTErrorKind, src: &str, diagnostics: &mut Vec<Diagnostic>) {
+    let text = src.get(span.range()).unwrap_or_default();
+
+    let message = match kind {
+        ASTErrorKind::BadImmediate => format!("immediate `{text}` does not fit in 16 bits"),
+        ASTErrorKind::BadOperandCount => String::from("wrong number of operands"),
+        ASTErrorKind::UnknownInstruction => format!("unknown instruction `{text}`"),
+        ASTErrorKind::UnknownLabel => format!("label `{text}` is not defined"),
+        ASTErrorKind::DuplicateLabel => format!("label `{text}` is already defined"),
+        ASTErrorKind::LSTError(LSTErrorKind::UnknownToken) => format!("unknown character `{text}`"),
+        ASTErrorKind::LSTError(kind) => lst_message(*kind),
+        ASTErrorKind::ASTErrors(errors) => {
+            for error in errors {
+                from_ast(error, src, diagnostics);
+            }
+
+            return;
+        }
+    };
+
+    diagnostics.push(Diagnostic::new(span, message))
+}
+
+fn lst_message(kind: LSTErrorKind) -> String {
+    match kind {
+        LSTErrorKind::UnknownToken => String::from("unknown character"),
+        LSTErrorKind::ExpectedToken(kind) => format!("expected {}", token_name(kind)),
+        LSTErrorKind::UnexpectedToken(kind) => format!("unexpected {}", token_name(kind)),
+        LSTErrorKind::PossibleTokens(kinds) => {
+            let names: Vec<_> = kinds.iter().map(|kind| token_name(*kind)).collect();
+
+            format!("expected {}", names.join(" or "))
+        }
+    }
+}
+
+fn token_name(kind: TokenKind) -> &'static str {
+    match kind {
+        TokenKind::Comma => "a comma",
+        TokenKind::Comment => "a comment",
+        TokenKind::Immediate => "an immediate",
+        TokenKind::Label => "a label",
+        TokenKind::LineFeed => "the end of the line",
+        TokenKind::Mnemonic => "a mnemonic",
+        TokenKind::Space => "a space",
+        TokenKind::Unknown => "an unknown character",
+    }
+}
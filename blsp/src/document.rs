@@ -0,0 +1,285 @@
+use std::collections::HashMap;
+
+use asmlib::instruction::{encode_instruction, Instruction};
+use blaast::{resolve_labels, ASTError, ASTErrorKind, ASTInstruction, ASTInstructionKind};
+use blalst::{LSTNodeKind, LSTOperand, LSTOperandKind};
+use blarse::{Edit, IncrementalParser};
+use blas::PseudoInstruction;
+use blib::{Span, INSTRUCTION_SIZE};
+use lsp_types::{Position, Range};
+
+use crate::diagnostics::{self, Diagnostic};
+use crate::LineIndex;
+
+/// A label definition.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub address: u16,
+}
+
+/// An instruction line of the document.
+#[derive(Clone, Debug)]
+pub struct Line {
+    pub span: Span,
+    pub mnemonic: Span,
+    pub kind: Option<ASTInstructionKind>,
+    pub operands: Vec<LSTOperand>,
+    pub address: u16,
+    pub encoding: Option<u32>,
+}
+
+/// An open blasm document and everything the server knows about it.
+///
+/// The analysis follows the assembler: labels are resolved over the whole
+/// document, so a label may be used before it is defined.
+///
+/// Edits only re-parse the lines they touch, the rest of the analysis is
+/// computed again from the updated **LST**.
+#[derive(Clone, Debug)]
+pub struct Document {
+    parser: IncrementalParser,
+    index: LineIndex,
+    labels: Vec<Label>,
+    references: Vec<Span>,
+    lines: Vec<Line>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        let mut document = Self {
+            index: LineIndex::new(&text),
+            parser: IncrementalParser::new(text),
+            labels: Vec::new(),
+            references: Vec::new(),
+            lines: Vec::new(),
+            diagnostics: Vec::new(),
+        };
+
+        document.analyze();
+
+        document
+    }
+
+    pub fn text(&self) -> &str {
+        self.parser.src()
+    }
+
+    /// Replace the text in `range` by `text` and analyze the document again.
+    pub fn edit(&mut self, range: Range, text: &str) {
+        let start = self.offset(range.start);
+        let end = self.offset(range.end).max(start);
+
+        self.parser.edit(Edit::new(Span::new(start, end), text));
+        self.index = LineIndex::new(self.parser.src());
+
+        self.analyze();
+    }
+
+    pub fn labels(&self) -> &[Label] {
+        &self.labels
+    }
+
+    pub fn lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    pub fn range(&self, span: Span) -> Range {
+        self.index.range(self.text(), span)
+    }
+
+    pub fn offset(&self, position: Position) -> usize {
+        self.index.offset(self.text(), position)
+    }
+
+    /// Return the source text covered by `span`.
+    pub fn slice(&self, span: Span) -> &str {
+        self.text().get(span.range()).unwrap_or_default()
+    }
+
+    /// Return the label, defined or referenced, under `offset`.
+    pub fn label_at(&self, offset: usize) -> Option<Span> {
+        self.labels
+            .iter()
+            .map(|label| label.span)
+            .chain(self.references.iter().copied())
+            .find(|span| contains(*span, offset))
+    }
+
+    /// Return the definition of the label named by `span`.
+    pub fn definition(&self, span: Span) -> Option<Label> {
+        let name = self.slice(span);
+
+        self.labels
+            .iter()
+            .find(|label| self.slice(label.span) == name)
+            .copied()
+    }
+
+    /// Return every use of the label named by `span`.
+    pub fn references(&self, span: Span) -> impl Iterator<Item = Span> + '_ {
+        let name = self.slice(span).to_string();
+
+        self.references
+            .iter()
+            .copied()
+            .filter(move |reference| self.slice(*reference) == name)
+    }
+
+    /// Return the instruction line under `offset`.
+    pub fn line_at(&self, offset: usize) -> Option<&Line> {
+        self.lines.iter().find(|line| contains(line.span, offset))
+    }
+
+    fn analyze(&mut self) {
+        let src = self.parser.src();
+
+        self.labels.clear();
+        self.references.clear();
+        self.lines.clear();
+        self.diagnostics.clear();
+
+        let resolved = resolve_labels(src, self.parser.nodes());
+        let mut labels = HashMap::new();
+        let mut address = 0;
+
+        for node in self.parser.nodes() {
+            let node = match node {
+                Ok(node) => node,
+                Err(error) => {
+                    diagnostics::from_ast(&error.into(), src, &mut self.diagnostics);
+                    continue;
+                }
+            };
+
+            match node.kind() {
+                LSTNodeKind::EmptyLine => (),
+                LSTNodeKind::Label => {
+                    let span = node.span();
+                    let name = &src[span.range()];
+
+                    if labels.contains_key(name) {
+                        let error = ASTError::new(span, ASTErrorKind::DuplicateLabel);
+
+                        diagnostics::from_ast(&error, src, &mut self.diagnostics);
+                    } else {
+                        labels.insert(name.to_string(), address);
+                    }
+
+                    self.labels.push(Label { span, address });
+                }
+                LSTNodeKind::Instruction(instruction) => {
+                    let operands = instruction.operands();
+
+                    self.references.extend(
+                        operands
+                            .iter()
+                            .filter(|operand| matches!(operand.kind(), LSTOperandKind::Label))
+                            .map(|operand| operand.span()),
+                    );
+
+                    let kind = (instruction.kind(), src).try_into().ok();
+
+                    let encoding =
+                        match ASTInstruction::try_from((instruction.clone(), src, &resolved)) {
+                            Ok(ast) => match PseudoInstruction::try_from(ast) {
+                                Ok(pseudo) => Some(encode_instruction(Instruction::from(pseudo))),
+                                Err(error) => {
+                                    diagnostics::from_asm(&error, src, &mut self.diagnostics);
+                                    None
+                                }
+                            },
+                            Err(error) => {
+                                diagnostics::from_ast(&error, src, &mut self.diagnostics);
+                                None
+                            }
+                        };
+
+                    self.lines.push(Line {
+                        span: instruction.span(),
+                        mnemonic: instruction.kind().span(),
+                        kind,
+                        operands,
+                        address,
+                        encoding,
+                    });
+
+                    address += INSTRUCTION_SIZE;
+                }
+            }
+        }
+    }
+}
+
+fn contains(span: Span, offset: usize) -> bool {
+    span.start() <= offset && offset <= span.end()
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::{Position, Range};
+
+    use super::Document;
+
+    #[test]
+    fn labels_and_references() {
+        let document = Document::new(String::from("@Loop\nsubi 1, 1, 1\nbne 1, 0, @Loop\n"));
+
+        assert_eq!(document.labels().len(), 1);
+        assert_eq!(document.labels()[0].address, 0);
+        assert_eq!(document.references(document.labels()[0].span).count(), 1);
+        assert!(document.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn forward_label() {
+        let document = Document::new(String::from("be 0, 0, @Main\nret\n@Main\ncall 2\n"));
+
+        assert!(document.diagnostics().is_empty());
+        assert_eq!(document.labels()[0].address, 4);
+        assert!(document.lines()[0].encoding.is_some());
+    }
+
+    #[test]
+    fn edit() {
+        let mut document = Document::new(String::from("@Loop\nsubi 1, 1, 1\nbne 1, 0, @Lop\n"));
+
+        assert_eq!(document.diagnostics().len(), 1);
+
+        let start = Position::new(2, 12);
+        document.edit(Range::new(start, start), "o");
+
+        assert_eq!(document.text(), "@Loop\nsubi 1, 1, 1\nbne 1, 0, @Loop\n");
+        assert!(document.diagnostics().is_empty());
+        assert_eq!(document.references(document.labels()[0].span).count(), 1);
+    }
+
+    #[test]
+    fn keeps_going_after_errors() {
+        let document = Document::new(String::from(
+            "foo 1, 2, 3\nadd 1, 2\nbe 0, 0, @end\n# é\nADD 1, $2, 3\n",
+        ));
+
+        let messages: Vec<_> = document
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.message())
+            .collect();
+
+        assert_eq!(
+            messages,
+            [
+                "unknown instruction `foo`",
+                "wrong number of operands",
+                "label `@end` is not defined",
+                "unknown character `$`",
+            ]
+        );
+        assert_eq!(document.lines().len(), 3);
+    }
+}
@@ -0,0 +1,167 @@
+use blaast::ASTInstructionKind;
+use blib::Register;
+
+use crate::document::Line;
+
+/// Short description of an instruction: its format, its semantics and a sentence.
+pub fn summary(kind: ASTInstructionKind) -> (&'static str, &'static str, &'static str) {
+    use ASTInstructionKind::*;
+
+    match kind {
+        // R-Type Instructions
+        ADD => ("R", "rd = rs1 + rs2", "Adds two registers."),
+        SUB => (
+            "R",
+            "rd = rs1 - rs2",
+            "Subtracts a register from another register.",
+        ),
+        OR => ("R", "rd = rs1 | rs2", "Bitwise OR between two registers."),
+        AND => ("R", "rd = rs1 & rs2", "Bitwise AND between two registers."),
+        XOR => ("R", "rd = rs1 ^ rs2", "Bitwise XOR between two registers."),
+        SLL => ("R", "rd = rs1 << rs2", "Shifts a register to the left."),
+        SRL => ("R", "rd = rs1 >> rs2", "Shifts a register to the right."),
+        // I-Type Instructions
+        ADDI => ("I", "rd = rs1 + imm", "Adds an immediate to a register."),
+        SUBI => (
+            "I",
+            "rd = rs1 - imm",
+            "Subtracts an immediate from a register.",
+        ),
+        ORI => (
+            "I",
+            "rd = rs1 | imm",
+            "Bitwise OR between a register and an immediate.",
+        ),
+        ANDI => (
+            "I",
+            "rd = rs1 & imm",
+            "Bitwise AND between a register and an immediate.",
+        ),
+        XORI => (
+            "I",
+            "rd = rs1 ^ imm",
+            "Bitwise XOR between a register and an immediate.",
+        ),
+        SLLI => (
+            "I",
+            "rd = rs1 << imm",
+            "Shifts a register to the left by an immediate.",
+        ),
+        SRLI => (
+            "I",
+            "rd = rs1 >> imm",
+            "Shifts a register to the right by an immediate.",
+        ),
+        JAL => (
+            "I",
+            "rd = pc + 2; goto rs1 + imm",
+            "Jumps and links the return address.",
+        ),
+        RETI => (
+            "I",
+            "pop status; pop pc",
+            "Returns from an interrupt or exception handler.",
+        ),
+        HALT => ("I", "stop", "Stops the CPU for good."),
+        WFI => (
+            "I",
+            "wait for an interrupt",
+            "Sleeps until an enabled interrupt is pending.",
+        ),
+        // Load/Store Instructions
+        LD => ("I", "rd = *(rs1 + imm)", "Loads a word from memory."),
+        STR => ("I", "*(rd + imm) = rs1", "Stores a word to memory."),
+        PUSH => (
+            "I",
+            "S = S - 1; *S = rs1",
+            "Pushes a register on the stack.",
+        ),
+        POP => ("I", "rd = *S; S = S + 1", "Pops a register from the stack."),
+        // B-Type Instructions
+        BE => (
+            "B",
+            "if rs1 == rs2: goto imm",
+            "Branches if both registers are equal.",
+        ),
+        BNE => (
+            "B",
+            "if rs1 != rs2: goto imm",
+            "Branches if the registers differ.",
+        ),
+        BLT => (
+            "B",
+            "if rs1 < rs2: goto imm",
+            "Branches if rs1 is lower (signed).",
+        ),
+        BGE => (
+            "B",
+            "if rs1 >= rs2: goto imm",
+            "Branches if rs1 is greater or equal (signed).",
+        ),
+        BLTU => (
+            "B",
+            "if rs1 < rs2: goto imm",
+            "Branches if rs1 is lower (unsigned).",
+        ),
+        BGEU => (
+            "B",
+            "if rs1 >= rs2: goto imm",
+            "Branches if rs1 is greater or equal (unsigned).",
+        ),
+        // Subroutine Instructions
+        CALL => (
+            "I",
+            "N = pc + 2; goto imm",
+            "Calls a subroutine, `jal 14, 0, imm`.",
+        ),
+        RET => ("I", "goto N", "Returns from a subroutine, `jal 0, 14, 0`."),
+    }
+}
+
+/// Bit layout of an instruction format.
+fn layout(format: &str) -> &'static str {
+    match format {
+        "R" => "rs2[19:16] rs1[15:12] rd[11:8] opcode[7:0]",
+        "I" => "imm[31:16] rs1[15:12] rd[11:8] opcode[7:0]",
+        _ => "offset[31:20] rs2[19:16] rs1[15:12] offset[11:8] opcode[7:0]",
+    }
+}
+
+/// Markdown documentation of an instruction kind.
+pub fn instruction(kind: ASTInstructionKind) -> String {
+    let (format, semantics, description) = summary(kind);
+
+    format!(
+        "**{}** ({format}-type)\n\n`{semantics}`\n\n{description}\n\nLayout: `{}`",
+        kind.mnemonic(),
+        layout(format),
+    )
+}
+
+/// Markdown documentation of an instruction line, with its encoding when it assembles.
+pub fn line(line: &Line) -> Option<String> {
+    let mut text = instruction(line.kind?);
+
+    text.push_str(&format!("\n\nAddress: `{}`", line.address));
+
+    if let Some(encoding) = line.encoding {
+        text.push_str(&format!(
+            "\n\nEncoding: `0x{encoding:08X}` (opcode `0b{:08b}`)",
+            encoding & 0xFF
+        ));
+    }
+
+    Some(text)
+}
+
+/// Markdown documentation of a register operand.
+pub fn register(value: u16) -> Option<String> {
+    let register = Register::try_from(value).ok()?;
+
+    Some(format!("Register `{value}` (`{register:?}`)"))
+}
+
+/// Markdown documentation of a label and the address it resolves to.
+pub fn label(name: &str, address: u16) -> String {
+    format!("Label `{name}` at address `{address}`")
+}
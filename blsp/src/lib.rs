@@ -0,0 +1,24 @@
+//! Language server for blasm sources.
+//!
+//! The server is built on top of the assembler front-end (`blex`, `blarse`,
+//! `blaast` and `blas`) and provides:
+//! - diagnostics from the error spans of every stage,
+//! - go-to-definition and find-references for labels,
+//! - hover with the semantics and the encoding of instructions,
+//! - completion for mnemonics, registers and labels,
+//! - document symbols for labels,
+//! - formatting with `blafmt`.
+
+mod completion;
+mod diagnostics;
+mod document;
+mod hover;
+mod line_index;
+mod server;
+
+#[cfg(test)]
+mod test;
+
+pub use document::Document;
+pub use line_index::LineIndex;
+pub use server::Server;
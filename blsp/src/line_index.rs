@@ -0,0 +1,103 @@
+use blib::Span;
+use lsp_types::{Position, Range};
+
+/// Conversion table between byte offsets and LSP positions.
+///
+/// LSP positions count characters in UTF-16 code units, so every conversion
+/// needs the text the index was built from.
+#[derive(Clone, Debug)]
+pub struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+
+        Self { starts }
+    }
+
+    /// Return the LSP position of the byte `offset` in `text`.
+    pub fn position(&self, text: &str, offset: usize) -> Position {
+        let offset = offset.min(text.len());
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.starts[line];
+
+        let character = text
+            .get(start..offset)
+            .map(|prefix| prefix.encode_utf16().count())
+            .unwrap_or(offset - start);
+
+        Position::new(line as u32, character as u32)
+    }
+
+    /// Return the byte offset in `text` of the LSP `position`.
+    pub fn offset(&self, text: &str, position: Position) -> usize {
+        let line = position.line as usize;
+
+        let Some(&start) = self.starts.get(line) else {
+            return text.len();
+        };
+
+        let end = self.starts.get(line + 1).copied().unwrap_or(text.len());
+
+        // Positions past the end of the line stay before its line break.
+        let content = text[start..end]
+            .strip_suffix('\n')
+            .unwrap_or(&text[start..end]);
+        let content = content.strip_suffix('\r').unwrap_or(content);
+
+        let mut units = 0;
+
+        for (index, c) in content.char_indices() {
+            if units >= position.character as usize {
+                return start + index;
+            }
+
+            units += c.len_utf16();
+        }
+
+        start + content.len()
+    }
+
+    pub fn range(&self, text: &str, span: Span) -> Range {
+        Range::new(
+            self.position(text, span.start()),
+            self.position(text, span.end()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lsp_types::Position;
+
+    use super::LineIndex;
+
+    #[test]
+    fn round_trip() {
+        let text = "add 1, 2, 3\n# é\n@Loop\n";
+        let index = LineIndex::new(text);
+
+        assert_eq!(index.position(text, 0), Position::new(0, 0));
+        assert_eq!(index.position(text, 12), Position::new(1, 0));
+        assert_eq!(index.position(text, 16), Position::new(1, 3));
+        assert_eq!(index.position(text, 18), Position::new(2, 1));
+
+        assert_eq!(index.offset(text, Position::new(1, 3)), 16);
+        assert_eq!(index.offset(text, Position::new(2, 1)), 18);
+        assert_eq!(index.offset(text, Position::new(9, 0)), text.len());
+    }
+
+    #[test]
+    fn past_the_end_of_a_line() {
+        let text = "add 1, 2, 3\r\n# é\n@Loop";
+        let index = LineIndex::new(text);
+
+        assert_eq!(index.offset(text, Position::new(0, 99)), 11);
+        assert_eq!(index.offset(text, Position::new(1, 99)), 17);
+        assert_eq!(index.offset(text, Position::new(2, 99)), text.len());
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use blex::highlight::find_mnemonic;
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, Formatting, GotoDefinition, HoverRequest, References,
+    Request as _,
+};
+use lsp_types::{
+    CompletionOptions, CompletionParams, CompletionResponse, DiagnosticSeverity,
+    DidChangeTextDocumentParams, DidCloseTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, DocumentSymbol, DocumentSymbolParams, DocumentSymbolResponse,
+    GotoDefinitionParams, GotoDefinitionResponse, Hover, HoverContents, HoverParams,
+    HoverProviderCapability, Location, MarkupContent, MarkupKind, OneOf, PublishDiagnosticsParams,
+    Range, ReferenceParams, ServerCapabilities, SymbolKind, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextEdit, Url,
+};
+
+use crate::{completion, hover, Document};
+
+pub type ServerResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// The blasm language server.
+///
+/// Example
+///
+/// ```no_run
+/// use lsp_server::Connection;
+///
+/// let (connection, io_threads) = Connection::stdio();
+///
+/// blsp::Server::run(connection).unwrap();
+/// io_threads.join().unwrap();
+/// ```
+pub struct Server {
+    connection: Connection,
+    documents: HashMap<Url, Document>,
+}
+
+impl Server {
+    /// Initialize the connection with the client and serve it until it shuts down.
+    pub fn run(connection: Connection) -> ServerResult<()> {
+        let capabilities = serde_json::to_value(Self::capabilities())?;
+
+        connection.initialize(capabilities)?;
+
+        let mut server = Self {
+            connection,
+            documents: HashMap::new(),
+        };
+
+        server.main_loop()
+    }
+
+    pub fn capabilities() -> ServerCapabilities {
+        ServerCapabilities {
+            text_document_sync: Some(TextDocumentSyncCapability::Kind(
+                TextDocumentSyncKind::INCREMENTAL,
+            )),
+            hover_provider: Some(HoverProviderCapability::Simple(true)),
+            definition_provider: Some(OneOf::Left(true)),
+            references_provider: Some(OneOf::Left(true)),
+            completion_provider: Some(CompletionOptions {
+                trigger_characters: Some(vec![String::from("@")]),
+                ..Default::default()
+            }),
+            document_symbol_provider: Some(OneOf::Left(true)),
+            document_formatting_provider: Some(OneOf::Left(true)),
+            ..Default::default()
+        }
+    }
+
+    fn main_loop(&mut self) -> ServerResult<()> {
+        let receiver = self.connection.receiver.clone();
+
+        for message in &receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request)? {
+                        return Ok(());
+                    }
+
+                    let response = self.request(request);
+
+                    self.connection.sender.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.notification(notification)?,
+                Message::Response(_) => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn request(&mut self, request: Request) -> Response {
+        match request.method.as_str() {
+            HoverRequest::METHOD => self.handle::<HoverRequest>(request, Self::hover),
+            GotoDefinition::METHOD => self.handle::<GotoDefinition>(request, Self::definition),
+            References::METHOD => self.handle::<References>(request, Self::references),
+            Completion::METHOD => self.handle::<Completion>(request, Self::completion),
+            DocumentSymbolRequest::METHOD => {
+                self.handle::<DocumentSymbolRequest>(request, Self::symbols)
+            }
+            Formatting::METHOD => self.handle::<Formatting>(request, Self::formatting),
+            method => Response::new_err(
+                request.id,
+                ErrorCode::MethodNotFound as i32,
+                format!("unhandled method {method}"),
+            ),
+        }
+    }
+
+    fn handle<R>(&self, request: Request, handler: fn(&Self, R::Params) -> R::Result) -> Response
+    where
+        R: lsp_types::request::Request,
+    {
+        let id = request.id.clone();
+
+        match request.extract(R::METHOD) {
+            Ok((id, params)) => Response::new_ok(id, handler(self, params)),
+            Err(error) => {
+                Response::new_err(id, ErrorCode::InvalidParams as i32, format!("{error:?}"))
+            }
+        }
+    }
+
+    fn notification(&mut self, notification: Notification) -> ServerResult<()> {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let params: DidOpenTextDocumentParams =
+                    notification.extract(DidOpenTextDocument::METHOD)?;
+
+                let document = params.text_document;
+
+                self.update(document.uri, document.text, Some(document.version))
+            }
+            DidChangeTextDocument::METHOD => {
+                let params: DidChangeTextDocumentParams =
+                    notification.extract(DidChangeTextDocument::METHOD)?;
+
+                let uri = params.text_document.uri;
+                let version = Some(params.text_document.version);
+
+                for change in params.content_changes {
+                    match (change.range, self.documents.get_mut(&uri)) {
+                        (Some(range), Some(document)) => document.edit(range, &change.text),
+                        _ => {
+                            self.documents
+                                .insert(uri.clone(), Document::new(change.text));
+                        }
+                    }
+                }
+
+                self.publish_document(uri, version)
+            }
+            DidCloseTextDocument::METHOD => {
+                let params: DidCloseTextDocumentParams =
+                    notification.extract(DidCloseTextDocument::METHOD)?;
+
+                let uri = params.text_document.uri;
+
+                self.documents.remove(&uri);
+                self.publish(uri, Vec::new(), None)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn update(&mut self, uri: Url, text: String, version: Option<i32>) -> ServerResult<()> {
+        self.documents.insert(uri.clone(), Document::new(text));
+
+        self.publish_document(uri, version)
+    }
+
+    fn publish_document(&self, uri: Url, version: Option<i32>) -> ServerResult<()> {
+        let Some(document) = self.documents.get(&uri) else {
+            return Ok(());
+        };
+
+        let diagnostics = document
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| lsp_types::Diagnostic {
+                range: document.range(diagnostic.span()),
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some(String::from("blsp")),
+                message: diagnostic.message().to_string(),
+                ..Default::default()
+            })
+            .collect();
+
+        self.publish(uri, diagnostics, version)
+    }
+
+    fn publish(
+        &self,
+        uri: Url,
+        diagnostics: Vec<lsp_types::Diagnostic>,
+        version: Option<i32>,
+    ) -> ServerResult<()> {
+        let params = PublishDiagnosticsParams {
+            uri,
+            diagnostics,
+            version,
+        };
+
+        let notification = Notification::new(PublishDiagnostics::METHOD.to_string(), params);
+
+        self.connection
+            .sender
+            .send(Message::Notification(notification))?;
+
+        Ok(())
+    }
+}
+
+impl Server {
+    fn hover(&self, params: HoverParams) -> Option<Hover> {
+        let position = params.text_document_position_params;
+        let document = self.documents.get(&position.text_document.uri)?;
+        let offset = document.offset(position.position);
+
+        let (span, value) = if let Some(span) = document.label_at(offset) {
+            let label = document.definition(span)?;
+
+            (span, hover::label(document.slice(span), label.address))
+        } else {
+            let line = document.line_at(offset)?;
+            let registers = find_mnemonic(document.slice(line.mnemonic))?
+                .format
+                .registers();
+
+            match line
+                .operands
+                .iter()
+                .position(|operand| operand.span().range().contains(&offset))
+            {
+                // The leading operands are registers, as many as the format
+                // of the instruction has.
+                Some(index) if index < registers => {
+                    let operand = line.operands[index];
+                    let value = document.slice(operand.span()).parse().ok()?;
+
+                    (operand.span(), hover::register(value)?)
+                }
+                _ => (line.mnemonic, hover::line(line)?),
+            }
+        };
+
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: Some(document.range(span)),
+        })
+    }
+
+    fn definition(&self, params: GotoDefinitionParams) -> Option<GotoDefinitionResponse> {
+        let position = params.text_document_position_params;
+        let uri = position.text_document.uri;
+        let document = self.documents.get(&uri)?;
+
+        let span = document.label_at(document.offset(position.position))?;
+        let label = document.definition(span)?;
+
+        let location = Location::new(uri, document.range(label.span));
+
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    fn references(&self, params: ReferenceParams) -> Option<Vec<Location>> {
+        let position = params.text_document_position;
+        let uri = position.text_document.uri;
+        let document = self.documents.get(&uri)?;
+
+        let span = document.label_at(document.offset(position.position))?;
+
+        let declaration = params
+            .context
+            .include_declaration
+            .then(|| document.definition(span))
+            .flatten()
+            .map(|label| label.span);
+
+        let locations = declaration
+            .into_iter()
+            .chain(document.references(span))
+            .map(|span| Location::new(uri.clone(), document.range(span)))
+            .collect();
+
+        Some(locations)
+    }
+
+    fn completion(&self, params: CompletionParams) -> Option<CompletionResponse> {
+        let position = params.text_document_position;
+        let document = self.documents.get(&position.text_document.uri)?;
+
+        let items = completion::complete(document, document.offset(position.position));
+
+        Some(CompletionResponse::Array(items))
+    }
+
+    fn symbols(&self, params: DocumentSymbolParams) -> Option<DocumentSymbolResponse> {
+        let document = self.documents.get(&params.text_document.uri)?;
+
+        #[allow(deprecated)]
+        let symbols = document
+            .labels()
+            .iter()
+            .map(|label| DocumentSymbol {
+                name: document.slice(label.span).to_string(),
+                detail: Some(format!("address {}", label.address)),
+                kind: SymbolKind::FUNCTION,
+                tags: None,
+                deprecated: None,
+                range: document.range(label.span),
+                selection_range: document.range(label.span),
+                children: None,
+            })
+            .collect();
+
+        Some(DocumentSymbolResponse::Nested(symbols))
+    }
+
+    fn formatting(&self, params: DocumentFormattingParams) -> Option<Vec<TextEdit>> {
+        let document = self.documents.get(&params.text_document.uri)?;
+        let text = document.text();
+
+        let formatted = blafmt::format(text).ok()?;
+
+        let end = document.range(blib::Span::new(text.len(), text.len())).end;
+
+        Some(vec![TextEdit::new(
+            Range::new(Default::default(), end),
+            formatted,
+        )])
+    }
+}
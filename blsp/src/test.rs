@@ -0,0 +1,328 @@
+use std::thread::{self, JoinHandle};
+
+use lsp_server::{Connection, Message, Notification, Request, RequestId};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidOpenTextDocument, Exit, Initialized, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{
+    Completion, DocumentSymbolRequest, Formatting, GotoDefinition, HoverRequest, Initialize,
+    References, Shutdown,
+};
+use lsp_types::{
+    CompletionParams, CompletionResponse, DidChangeTextDocumentParams, DidOpenTextDocumentParams,
+    DocumentFormattingParams, DocumentSymbolParams, DocumentSymbolResponse, FormattingOptions,
+    GotoDefinitionParams, GotoDefinitionResponse, HoverContents, HoverParams, InitializeParams,
+    Position, PublishDiagnosticsParams, Range, ReferenceContext, ReferenceParams,
+    TextDocumentContentChangeEvent, TextDocumentIdentifier, TextDocumentItem,
+    TextDocumentPositionParams, Url, VersionedTextDocumentIdentifier,
+};
+
+use crate::Server;
+
+/// In-process LSP client talking to a [Server] running on another thread.
+struct Client {
+    connection: Connection,
+    server: Option<JoinHandle<()>>,
+    id: i32,
+}
+
+impl Client {
+    fn new() -> Self {
+        let (server, connection) = Connection::memory();
+
+        let server = thread::spawn(move || Server::run(server).unwrap());
+
+        let mut client = Self {
+            connection,
+            server: Some(server),
+            id: 0,
+        };
+
+        client.request::<Initialize>(InitializeParams::default());
+        client.notify::<Initialized>(lsp_types::InitializedParams {});
+
+        client
+    }
+
+    fn request<R: lsp_types::request::Request>(&mut self, params: R::Params) -> R::Result {
+        self.id += 1;
+
+        let id = RequestId::from(self.id);
+        let request = Request::new(id.clone(), R::METHOD.to_string(), params);
+
+        self.connection
+            .sender
+            .send(Message::Request(request))
+            .unwrap();
+
+        loop {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Response(response) if response.id == id => {
+                    assert!(response.error.is_none(), "{:?}", response.error);
+
+                    return serde_json::from_value(response.result.unwrap()).unwrap();
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn notify<N: lsp_types::notification::Notification>(&self, params: N::Params) {
+        let notification = Notification::new(N::METHOD.to_string(), params);
+
+        self.connection
+            .sender
+            .send(Message::Notification(notification))
+            .unwrap();
+    }
+
+    fn diagnostics(&self) -> PublishDiagnosticsParams {
+        loop {
+            match self.connection.receiver.recv().unwrap() {
+                Message::Notification(notification)
+                    if notification.method == PublishDiagnostics::METHOD =>
+                {
+                    return serde_json::from_value(notification.params).unwrap();
+                }
+                _ => continue,
+            }
+        }
+    }
+
+    fn open(&self, text: &str) -> PublishDiagnosticsParams {
+        self.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri(), String::from("blasm"), 1, text.to_string()),
+        });
+
+        self.diagnostics()
+    }
+
+    fn position(&self, line: u32, character: u32) -> TextDocumentPositionParams {
+        TextDocumentPositionParams::new(
+            TextDocumentIdentifier::new(uri()),
+            Position::new(line, character),
+        )
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.request::<Shutdown>(());
+        self.notify::<Exit>(());
+
+        self.server.take().unwrap().join().unwrap();
+    }
+}
+
+fn uri() -> Url {
+    Url::parse("file:///fibo.blasm").unwrap()
+}
+
+const FIBO: &str = "@Fibo\naddi 1, 0, 10\n@Loop\nsubi 1, 1, 1\nbne 1, 0, @Loop\n";
+
+#[test]
+fn diagnostics() {
+    let client = Client::new();
+
+    let published = client.open("addi 1, 0, 10\nfoo 1, 2, 3\nbe 1, 0, @end\n");
+
+    let diagnostics: Vec<_> = published
+        .diagnostics
+        .iter()
+        .map(|diagnostic| (diagnostic.range, diagnostic.message.as_str()))
+        .collect();
+
+    assert_eq!(
+        diagnostics,
+        [
+            (
+                Range::new(Position::new(1, 0), Position::new(1, 3)),
+                "unknown instruction `foo`"
+            ),
+            (
+                Range::new(Position::new(2, 9), Position::new(2, 13)),
+                "label `@end` is not defined"
+            ),
+        ]
+    );
+
+    client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier::new(uri(), 2),
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: String::from(FIBO),
+        }],
+    });
+
+    let published = client.diagnostics();
+
+    assert_eq!(published.version, Some(2));
+    assert!(published.diagnostics.is_empty());
+
+    client.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier::new(uri(), 3),
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: Some(Range::new(Position::new(4, 11), Position::new(4, 15))),
+            range_length: None,
+            text: String::from("Lop"),
+        }],
+    });
+
+    let published = client.diagnostics();
+    let messages: Vec<_> = published
+        .diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.message.as_str())
+        .collect();
+
+    assert_eq!(published.version, Some(3));
+    assert_eq!(messages, ["label `@Lop` is not defined"]);
+}
+
+#[test]
+fn definition_and_references() {
+    let mut client = Client::new();
+    client.open(FIBO);
+
+    let definition = client.request::<GotoDefinition>(GotoDefinitionParams {
+        text_document_position_params: client.position(4, 12),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    });
+
+    let Some(GotoDefinitionResponse::Scalar(location)) = definition else {
+        panic!("expected a single location, got {definition:?}");
+    };
+
+    assert_eq!(
+        location.range,
+        Range::new(Position::new(2, 0), Position::new(2, 5))
+    );
+
+    let references = client
+        .request::<References>(ReferenceParams {
+            text_document_position: client.position(2, 2),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: ReferenceContext {
+                include_declaration: true,
+            },
+        })
+        .unwrap();
+
+    let ranges: Vec<_> = references.iter().map(|location| location.range).collect();
+
+    assert_eq!(
+        ranges,
+        [
+            Range::new(Position::new(2, 0), Position::new(2, 5)),
+            Range::new(Position::new(4, 10), Position::new(4, 15)),
+        ]
+    );
+}
+
+#[test]
+fn hover() {
+    let mut client = Client::new();
+    client.open(FIBO);
+
+    let hover = |client: &mut Client, line, character| {
+        let hover = client.request::<HoverRequest>(HoverParams {
+            text_document_position_params: client.position(line, character),
+            work_done_progress_params: Default::default(),
+        });
+
+        match hover.map(|hover| hover.contents) {
+            Some(HoverContents::Markup(content)) => content.value,
+            contents => panic!("unexpected hover {contents:?}"),
+        }
+    };
+
+    let mnemonic = hover(&mut client, 1, 1);
+
+    assert!(mnemonic.contains("**addi** (I-type)"), "{mnemonic}");
+    assert!(mnemonic.contains("`rd = rs1 + imm`"), "{mnemonic}");
+    assert!(mnemonic.contains("Encoding: `0x000A0101`"), "{mnemonic}");
+
+    assert_eq!(hover(&mut client, 3, 5), "Register `1` (`A`)");
+    assert_eq!(hover(&mut client, 4, 12), "Label `@Loop` at address `2`");
+
+    let mut client = Client::new();
+    client.open("add 1, 2, 3\n");
+
+    assert_eq!(hover(&mut client, 0, 10), "Register `3` (`C`)");
+}
+
+#[test]
+fn completion() {
+    let mut client = Client::new();
+    client.open("@Loop\nad\nbne 1, 0, @\n  ad\n\tbne\t1,\t");
+
+    let mut complete = |line, character| {
+        let completion = client.request::<Completion>(CompletionParams {
+            text_document_position: client.position(line, character),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: None,
+        });
+
+        match completion {
+            Some(CompletionResponse::Array(items)) => {
+                items.into_iter().map(|item| item.label).collect::<Vec<_>>()
+            }
+            completion => panic!("unexpected completion {completion:?}"),
+        }
+    };
+
+    let mnemonics = complete(1, 2);
+
+    assert_eq!(mnemonics.len(), 30);
+    assert!(mnemonics.contains(&String::from("addi")));
+
+    assert_eq!(complete(2, 11), ["@Loop"]);
+
+    let operands = complete(2, 4);
+
+    assert_eq!(operands.len(), 17);
+    assert!(operands.contains(&String::from("15")));
+
+    // Indented lines
+    assert_eq!(complete(3, 4).len(), 30);
+    assert_eq!(complete(4, 8).len(), 17);
+}
+
+#[test]
+fn symbols_and_formatting() {
+    let mut client = Client::new();
+    client.open("@Fibo\naddi 1,0,10\n\n\n@Loop\nbne 1 ,0, @Loop # again\n");
+
+    let symbols = client.request::<DocumentSymbolRequest>(DocumentSymbolParams {
+        text_document: TextDocumentIdentifier::new(uri()),
+        work_done_progress_params: Default::default(),
+        partial_result_params: Default::default(),
+    });
+
+    let Some(DocumentSymbolResponse::Nested(symbols)) = symbols else {
+        panic!("expected nested symbols, got {symbols:?}");
+    };
+
+    let names: Vec<_> = symbols.iter().map(|symbol| symbol.name.as_str()).collect();
+
+    assert_eq!(names, ["@Fibo", "@Loop"]);
+
+    let edits = client
+        .request::<Formatting>(DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri()),
+            options: FormattingOptions::default(),
+            work_done_progress_params: Default::default(),
+        })
+        .unwrap();
+
+    assert_eq!(edits.len(), 1);
+    assert_eq!(
+        edits[0].new_text,
+        "@Fibo\naddi 1, 0, 10\n\n@Loop\nbne 1, 0, @Loop # again\n"
+    );
+}
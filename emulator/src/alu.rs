@@ -0,0 +1,166 @@
+//! Arithmetic and logic of the Blask CPU.
+//!
+//! Arithmetic wraps around on 16 bits and shift amounts only use their 4 low
+//! bits, so no operand can make an instruction fail.
+
+use asmlib::instruction::OpCode;
+
+/// Status register, written by every arithmetic and logic instruction.
+///
+/// - `zero`: the result is 0.
+/// - `negative`: bit 15 of the result is set.
+/// - `carry`: an addition carried out of bit 15, or a subtraction borrowed,
+///   which is an unsigned overflow. Cleared by logic instructions and shifts.
+/// - `overflow`: the result does not fit in a signed 16-bit integer.
+///   Cleared by logic instructions and shifts.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub negative: bool,
+    pub zero: bool,
+    pub carry: bool,
+    pub overflow: bool,
+}
+
+impl Flags {
+    pub const NEGATIVE: u16 = 1 << 3;
+    pub const ZERO: u16 = 1 << 2;
+    pub const CARRY: u16 = 1 << 1;
+    pub const OVERFLOW: u16 = 1;
+
+    /// Return the flags packed as `NZCV` in the low bits of a word.
+    pub fn bits(&self) -> u16 {
+        let mut bits = 0;
+
+        for (set, bit) in [
+            (self.negative, Self::NEGATIVE),
+            (self.zero, Self::ZERO),
+            (self.carry, Self::CARRY),
+            (self.overflow, Self::OVERFLOW),
+        ] {
+            if set {
+                bits |= bit;
+            }
+        }
+
+        bits
+    }
+
+    /// Unpack flags from the low bits of a word, as returned by [Flags::bits].
+    pub fn from_bits(bits: u16) -> Self {
+        Self {
+            negative: bits & Self::NEGATIVE != 0,
+            zero: bits & Self::ZERO != 0,
+            carry: bits & Self::CARRY != 0,
+            overflow: bits & Self::OVERFLOW != 0,
+        }
+    }
+
+    fn result(result: u16, carry: bool, overflow: bool) -> Self {
+        Self {
+            negative: (result as i16) < 0,
+            zero: result == 0,
+            carry,
+            overflow,
+        }
+    }
+}
+
+impl std::fmt::Display for Flags {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (set, name) in [
+            (self.negative, 'N'),
+            (self.zero, 'Z'),
+            (self.carry, 'C'),
+            (self.overflow, 'V'),
+        ] {
+            write!(f, "{}", if set { name } else { '-' })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Compute `a <op> b` for an arithmetic or logic opcode, register or immediate.
+///
+/// Return `None` if `opcode` is not an arithmetic or logic one.
+pub fn execute(opcode: OpCode, a: u16, b: u16) -> Option<(u16, Flags)> {
+    let (result, carry, overflow) = match opcode {
+        OpCode::ADD | OpCode::ADDI => {
+            let (result, carry) = a.overflowing_add(b);
+            let (_, overflow) = (a as i16).overflowing_add(b as i16);
+            (result, carry, overflow)
+        }
+        OpCode::SUB | OpCode::SUBI => {
+            let (result, carry) = a.overflowing_sub(b);
+            let (_, overflow) = (a as i16).overflowing_sub(b as i16);
+            (result, carry, overflow)
+        }
+        OpCode::OR | OpCode::ORI => (a | b, false, false),
+        OpCode::AND | OpCode::ANDI => (a & b, false, false),
+        OpCode::XOR | OpCode::XORI => (a ^ b, false, false),
+        OpCode::SLL | OpCode::SLLI => (a << (b & 0xF), false, false),
+        OpCode::SRL | OpCode::SRLI => (a >> (b & 0xF), false, false),
+        _ => return None,
+    };
+
+    Some((result, Flags::result(result, carry, overflow)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flags(bits: u16) -> Flags {
+        Flags::from_bits(bits)
+    }
+
+    #[test]
+    fn add() {
+        assert_eq!(execute(OpCode::ADD, 40, 2), Some((42, flags(0))));
+        assert_eq!(
+            execute(OpCode::ADD, 0xFFFF, 1),
+            Some((0, flags(Flags::ZERO | Flags::CARRY)))
+        );
+        assert_eq!(
+            execute(OpCode::ADDI, 0x7FFF, 1),
+            Some((0x8000, flags(Flags::NEGATIVE | Flags::OVERFLOW)))
+        );
+    }
+
+    #[test]
+    fn sub() {
+        assert_eq!(execute(OpCode::SUB, 42, 42), Some((0, flags(Flags::ZERO))));
+        assert_eq!(
+            execute(OpCode::SUBI, 0, 1),
+            Some((0xFFFF, flags(Flags::NEGATIVE | Flags::CARRY)))
+        );
+        assert_eq!(
+            execute(OpCode::SUB, 0x8000, 1),
+            Some((0x7FFF, flags(Flags::OVERFLOW)))
+        );
+    }
+
+    #[test]
+    fn shifts_are_masked() {
+        assert_eq!(execute(OpCode::SLL, 1, 16), Some((1, flags(0))));
+        assert_eq!(
+            execute(OpCode::SLLI, 1, 15),
+            Some((0x8000, flags(Flags::NEGATIVE)))
+        );
+        assert_eq!(execute(OpCode::SRL, 0x8000, 17), Some((0x4000, flags(0))));
+    }
+
+    #[test]
+    fn not_alu() {
+        assert_eq!(execute(OpCode::LD, 0, 0), None);
+        assert_eq!(execute(OpCode::BE, 0, 0), None);
+    }
+
+    #[test]
+    fn bits() {
+        let all = Flags::NEGATIVE | Flags::ZERO | Flags::CARRY | Flags::OVERFLOW;
+
+        assert_eq!(flags(all).bits(), all);
+        assert_eq!(flags(Flags::CARRY).to_string(), "--C-");
+    }
+}
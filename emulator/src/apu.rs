@@ -0,0 +1,394 @@
+//! Audio processing unit of the Blask handheld.
+//!
+//! The APU mixes two square wave channels and a noise channel to mono 16-bit
+//! samples at [SAMPLE_RATE]. It counts time in CPU cycles, so a program makes
+//! the same sound on every run. Each channel has four MMIO registers, from
+//! `0xF050` for square 0, `0xF054` for square 1 and `0xF058` for noise:
+//!
+//! | Offset | Register    | Access                                                   |
+//! |--------|-------------|----------------------------------------------------------|
+//! | `0`    | `FREQUENCY` | tone in Hz, or for noise the rate its generator shifts   |
+//! | `1`    | `VOLUME`    | starting volume, 0 to 15                                 |
+//! | `2`    | `ENVELOPE`  | bits 0-7 step period in 1/64 s, bit 8 rises, 0 is steady |
+//! | `3`    | `CONTROL`   | bit 0 plays, bits 1-2 duty cycle of a square wave        |
+//!
+//! Writing `CONTROL` with bit 0 set starts a note: the volume restarts from
+//! `VOLUME` and the envelope then lowers or raises it by one every period.
+//! The duty cycle is 12.5%, 25%, 50% or 75% of the period high.
+
+use core::ops::Range;
+use std::io::{self, Write};
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Cycles per second of the CPU, the APU counts time in them.
+pub const CLOCK: u32 = 300_000;
+
+/// Samples per second of the output.
+pub const SAMPLE_RATE: u32 = 22_050;
+
+/// Square wave channels and the noise channel, mixed together.
+pub const CHANNELS: usize = 3;
+
+/// MMIO registers of the APU, four per channel.
+pub const REGISTERS: Range<usize> = 0xF050..0xF050 + 4 * CHANNELS;
+
+/// First register of each channel.
+pub const SQUARE: [usize; 2] = [REGISTERS.start, REGISTERS.start + 4];
+pub const NOISE: usize = REGISTERS.start + 8;
+
+/// Offsets of the registers of a channel.
+pub const FREQUENCY: usize = 0;
+pub const VOLUME: usize = 1;
+pub const ENVELOPE: usize = 2;
+pub const CONTROL: usize = 3;
+
+/// Bit of `CONTROL` playing the channel.
+pub const PLAY: u16 = 1;
+/// Bit of `ENVELOPE` raising the volume instead of lowering it.
+pub const RISE: u16 = 1 << 8;
+
+const MAX_VOLUME: u16 = 15;
+
+/// Eighths of the period a square wave is high, by duty cycle.
+const DUTY: [u32; 4] = [1, 2, 4, 6];
+
+/// Mixed channels at full volume reach the range of a sample.
+const SCALE: i32 = i16::MAX as i32 / (MAX_VOLUME as i32 * CHANNELS as i32);
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Channel {
+    registers: [u16; 4],
+    volume: u16,
+    /// Position in the period, in 1/[SAMPLE_RATE] of a period.
+    phase: u32,
+    /// Samples until the next envelope step.
+    envelope: u32,
+}
+
+impl Channel {
+    fn playing(&self) -> bool {
+        self.registers[CONTROL] & PLAY != 0
+    }
+
+    /// Restart the note from `VOLUME`.
+    fn start(&mut self) {
+        self.volume = self.registers[VOLUME].min(MAX_VOLUME);
+        self.envelope = self.envelope_period();
+    }
+
+    fn envelope_period(&self) -> u32 {
+        (self.registers[ENVELOPE] & 0xFF) as u32 * SAMPLE_RATE / 64
+    }
+
+    /// Step the envelope by one sample.
+    fn step_envelope(&mut self) {
+        if self.envelope_period() == 0 {
+            return;
+        }
+
+        // `ENVELOPE` was set while the note was playing steady
+        if self.envelope == 0 {
+            self.envelope = self.envelope_period();
+        }
+
+        self.envelope -= 1;
+
+        if self.envelope == 0 {
+            self.envelope = self.envelope_period();
+            self.volume = if self.registers[ENVELOPE] & RISE != 0 {
+                (self.volume + 1).min(MAX_VOLUME)
+            } else {
+                self.volume.saturating_sub(1)
+            };
+        }
+    }
+
+    /// Advance the phase by one sample, return the number of periods completed.
+    fn advance(&mut self) -> u32 {
+        self.phase += self.registers[FREQUENCY] as u32;
+        let periods = self.phase / SAMPLE_RATE;
+        self.phase %= SAMPLE_RATE;
+
+        periods
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Apu {
+    channels: [Channel; CHANNELS],
+    /// Linear feedback shift register of the noise channel.
+    lfsr: u16,
+    /// Cycles since the last sample, in 1/[SAMPLE_RATE] of a cycle.
+    clock: u32,
+    samples: Vec<i16>,
+}
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Apu {
+    pub fn new() -> Self {
+        Self {
+            channels: Default::default(),
+            lfsr: 0x7FFF,
+            clock: 0,
+            samples: Vec::new(),
+        }
+    }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        for channel in &self.channels {
+            writer.words(&channel.registers);
+            writer.u16(channel.volume);
+            writer.u32(channel.phase);
+            writer.u32(channel.envelope);
+        }
+        writer.u16(self.lfsr);
+        writer.u32(self.clock);
+    }
+
+    /// Restore the state saved by [Apu::save], dropping the samples not taken.
+    pub fn restore(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        for channel in &mut self.channels {
+            reader.words(&mut channel.registers)?;
+            channel.volume = reader.u16()?;
+            channel.phase = reader.u32()?;
+            channel.envelope = reader.u32()?;
+        }
+        self.lfsr = reader.u16()?;
+        self.clock = reader.u32()?;
+        self.samples.clear();
+
+        Ok(())
+    }
+
+    /// Return the channel and the offset of the register at `address`.
+    fn register(address: usize) -> (usize, usize) {
+        let offset = address - REGISTERS.start;
+
+        (offset / 4, offset % 4)
+    }
+
+    /// Read the register at `address`, one of [REGISTERS].
+    pub fn read(&self, address: usize) -> u16 {
+        let (channel, register) = Self::register(address);
+
+        self.channels[channel].registers[register]
+    }
+
+    /// Write the register at `address`, one of [REGISTERS].
+    pub fn write(&mut self, address: usize, value: u16) {
+        let (channel, register) = Self::register(address);
+        let channel = &mut self.channels[channel];
+
+        channel.registers[register] = value;
+
+        if register == CONTROL && value & PLAY != 0 {
+            channel.start();
+        }
+    }
+
+    /// Advance the APU by one cycle, producing a sample every
+    /// [CLOCK] / [SAMPLE_RATE] cycles.
+    pub fn tick(&mut self) {
+        self.clock += SAMPLE_RATE;
+
+        if self.clock >= CLOCK {
+            self.clock -= CLOCK;
+
+            let sample = self.sample();
+            self.samples.push(sample);
+        }
+    }
+
+    /// Return the samples produced since the last call.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        std::mem::take(&mut self.samples)
+    }
+
+    /// Mix the next sample of every channel.
+    fn sample(&mut self) -> i16 {
+        let mut mix = 0;
+
+        for (index, channel) in self.channels.iter_mut().enumerate() {
+            if !channel.playing() {
+                continue;
+            }
+
+            let periods = channel.advance();
+
+            let high = if index == CHANNELS - 1 {
+                for _ in 0..periods {
+                    // 15-bit generator, feeding back the XOR of its two low bits.
+                    let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+                    self.lfsr = (self.lfsr >> 1) | (bit << 14);
+                }
+
+                self.lfsr & 1 == 0
+            } else {
+                let duty = DUTY[(channel.registers[CONTROL] >> 1 & 0b11) as usize];
+
+                channel.phase * 8 < duty * SAMPLE_RATE
+            };
+
+            let level = channel.volume as i32;
+            mix += if high { level } else { -level };
+
+            channel.step_envelope();
+        }
+
+        (mix * SCALE) as i16
+    }
+}
+
+/// Write `samples` as a mono 16-bit WAV file at [SAMPLE_RATE].
+pub fn write_wav(mut writer: impl Write, samples: &[i16]) -> io::Result<()> {
+    let data = (samples.len() * 2) as u32;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data).to_le_bytes())?;
+    writer.write_all(b"WAVEfmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    // PCM, mono
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?;
+    writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+    // 2 bytes per frame, 16 bits per sample
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data.to_le_bytes())?;
+
+    for sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run the APU for `samples` samples.
+    fn run(apu: &mut Apu, samples: usize) -> Vec<i16> {
+        while apu.samples.len() < samples {
+            apu.tick();
+        }
+
+        apu.take_samples()
+    }
+
+    fn play(apu: &mut Apu, channel: usize, frequency: u16, volume: u16, control: u16) {
+        apu.write(channel + FREQUENCY, frequency);
+        apu.write(channel + VOLUME, volume);
+        apu.write(channel + CONTROL, PLAY | control);
+    }
+
+    #[test]
+    fn silence() {
+        let mut apu = Apu::new();
+
+        assert_eq!(run(&mut apu, 100), vec![0; 100]);
+    }
+
+    #[test]
+    fn sample_rate() {
+        let mut apu = Apu::new();
+
+        for _ in 0..CLOCK {
+            apu.tick();
+        }
+
+        assert_eq!(apu.take_samples().len(), SAMPLE_RATE as usize);
+    }
+
+    #[test]
+    fn square_duty() {
+        // A period of 9 samples.
+        let frequency = (SAMPLE_RATE / 9) as u16;
+
+        let high = |control: u16| {
+            let mut apu = Apu::new();
+            play(&mut apu, SQUARE[1], frequency, 15, control << 1);
+
+            let samples = run(&mut apu, 18);
+            assert_eq!(samples[..9], samples[9..]);
+
+            samples[..9]
+                .iter()
+                .filter(|sample| **sample == 15 * SCALE as i16)
+                .count()
+        };
+
+        assert_eq!(high(0), 2);
+        assert_eq!(high(1), 3);
+        assert_eq!(high(2), 5);
+        assert_eq!(high(3), 7);
+    }
+
+    #[test]
+    fn envelope() {
+        let mut apu = Apu::new();
+        apu.write(SQUARE[0] + ENVELOPE, 1);
+        play(&mut apu, SQUARE[0], 0, 2, 0b11 << 1);
+
+        // Steady high, lowered by one every 1/64 s.
+        let step = (SAMPLE_RATE / 64) as usize;
+        let samples = run(&mut apu, 3 * step);
+
+        assert_eq!(samples[0], 2 * SCALE as i16);
+        assert_eq!(samples[step], SCALE as i16);
+        assert_eq!(samples[2 * step], 0);
+
+        apu.write(SQUARE[0] + ENVELOPE, RISE | 1);
+        apu.write(SQUARE[0] + CONTROL, PLAY);
+        assert_eq!(run(&mut apu, 2 * step)[step], 3 * SCALE as i16);
+    }
+
+    #[test]
+    fn envelope_set_while_playing() {
+        let mut apu = Apu::new();
+        play(&mut apu, SQUARE[0], 0, 2, 0b11 << 1);
+        run(&mut apu, 10);
+
+        apu.write(SQUARE[0] + ENVELOPE, 1);
+        let step = (SAMPLE_RATE / 64) as usize;
+        let samples = run(&mut apu, 2 * step);
+
+        assert_eq!(samples[0], 2 * SCALE as i16);
+        assert_eq!(samples[step], SCALE as i16);
+    }
+
+    #[test]
+    fn noise_is_deterministic() {
+        let noise = || {
+            let mut apu = Apu::new();
+            play(&mut apu, NOISE, 4000, 15, 0);
+            run(&mut apu, 1000)
+        };
+
+        let samples = noise();
+
+        assert_eq!(samples, noise());
+        assert!(samples.contains(&(15 * SCALE as i16)));
+        assert!(samples.contains(&(-15 * SCALE as i16)));
+    }
+
+    #[test]
+    fn wav_header() {
+        let mut wav = Vec::new();
+        write_wav(&mut wav, &[1, -1]).unwrap();
+
+        assert_eq!(wav.len(), 44 + 4);
+        assert_eq!(&wav[..4], b"RIFF");
+        assert_eq!(&wav[8..16], b"WAVEfmt ");
+        assert_eq!(&wav[24..28], &SAMPLE_RATE.to_le_bytes());
+        assert_eq!(&wav[40..], &[4, 0, 0, 0, 1, 0, 0xFF, 0xFF]);
+    }
+}
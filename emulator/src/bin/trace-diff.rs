@@ -0,0 +1,41 @@
+use clap::Parser;
+use emulator::trace::{self, Field};
+
+/// Report the first instruction two traces of `emulator --trace` differ on.
+///
+/// Exits with 0 when the traces are the same, 1 when they diverge and 2 when
+/// a trace cannot be read.
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Trace of the reference run, as JSON lines or in the binary form.
+    left: String,
+
+    /// Trace of the run compared with it.
+    right: String,
+
+    /// Fields not compared, among cycle, pc, word, registers and memory, such
+    /// as `cycle,pc` to compare the effects of programs of different lengths.
+    #[arg(long, value_delimiter = ',')]
+    ignore: Vec<Field>,
+}
+
+fn main() {
+    let args = Args::parse();
+
+    let read = |path: &str| {
+        trace::read(path).unwrap_or_else(|error| {
+            eprintln!("[trace-diff] {}: {}", path, error);
+            std::process::exit(2);
+        })
+    };
+    let (left, right) = (read(&args.left), read(&args.right));
+
+    match trace::diff(&left, &right, &args.ignore) {
+        Some(divergence) => {
+            print!("{}", divergence);
+            std::process::exit(1);
+        }
+        None => println!("the traces are the same, {} instructions", left.len()),
+    }
+}
@@ -0,0 +1,361 @@
+//! Instruction-level conformance suite.
+//!
+//! Every case is assembled from blasm, executed from the start of ROM and
+//! checked against the registers and memory words it must leave behind.
+
+use asmlib::instruction::Instruction;
+use blas::ASM;
+use blex::Lexer;
+
+use crate::memory::{Bus, RAM};
+use crate::Emulator;
+
+/// First RAM address, as written in the sources.
+const DATA: usize = RAM.start;
+
+struct Case {
+    name: &'static str,
+    source: &'static str,
+    /// Registers set before running.
+    registers: &'static [(usize, u16)],
+    /// Registers expected after running.
+    expected: &'static [(usize, u16)],
+    /// Memory words expected after running.
+    memory: &'static [(usize, u16)],
+}
+
+const fn case(
+    name: &'static str,
+    source: &'static str,
+    registers: &'static [(usize, u16)],
+    expected: &'static [(usize, u16)],
+) -> Case {
+    Case {
+        name,
+        source,
+        registers,
+        expected,
+        memory: &[],
+    }
+}
+
+const CASES: &[Case] = &[
+    // R
+    case("add", "add 3, 1, 2\n", &[(1, 40), (2, 2)], &[(3, 42)]),
+    case("sub", "sub 3, 1, 2\n", &[(1, 44), (2, 2)], &[(3, 42)]),
+    case(
+        "or",
+        "or 3, 1, 2\n",
+        &[(1, 0b1010), (2, 0b0110)],
+        &[(3, 0b1110)],
+    ),
+    case(
+        "and",
+        "and 3, 1, 2\n",
+        &[(1, 0b1010), (2, 0b0110)],
+        &[(3, 0b0010)],
+    ),
+    case(
+        "xor",
+        "xor 3, 1, 2\n",
+        &[(1, 0b1010), (2, 0b0110)],
+        &[(3, 0b1100)],
+    ),
+    case(
+        "sll",
+        "sll 3, 1, 2\n",
+        &[(1, 0x0101), (2, 4)],
+        &[(3, 0x1010)],
+    ),
+    case(
+        "srl",
+        "srl 3, 1, 2\n",
+        &[(1, 0x8010), (2, 4)],
+        &[(3, 0x0801)],
+    ),
+    // I
+    case("addi", "addi 2, 1, 2\n", &[(1, 40)], &[(2, 42)]),
+    case("subi", "subi 2, 1, 2\n", &[(1, 44)], &[(2, 42)]),
+    case("ori", "ori 2, 1, 6\n", &[(1, 0b1010)], &[(2, 0b1110)]),
+    case("andi", "andi 2, 1, 6\n", &[(1, 0b1010)], &[(2, 0b0010)]),
+    case("xori", "xori 2, 1, 6\n", &[(1, 0b1010)], &[(2, 0b1100)]),
+    case("slli", "slli 2, 1, 4\n", &[(1, 0x0101)], &[(2, 0x1010)]),
+    case("srli", "srli 2, 1, 4\n", &[(1, 0x8010)], &[(2, 0x0801)]),
+    // Wrapping arithmetic and masked shift amounts
+    case(
+        "add wraps",
+        "add 3, 1, 2\n",
+        &[(1, 0xFFFF), (2, 2)],
+        &[(3, 1)],
+    ),
+    case(
+        "sub wraps",
+        "sub 3, 1, 2\n",
+        &[(1, 1), (2, 2)],
+        &[(3, 0xFFFF)],
+    ),
+    case("addi wraps", "addi 2, 1, 65535\n", &[(1, 2)], &[(2, 1)]),
+    case("subi wraps", "subi 1, 1, 1\n", &[(1, 0)], &[(1, 0xFFFF)]),
+    case("sll masks", "sll 3, 1, 2\n", &[(1, 1), (2, 17)], &[(3, 2)]),
+    case(
+        "srl masks",
+        "srl 3, 1, 2\n",
+        &[(1, 0x8000), (2, 31)],
+        &[(3, 1)],
+    ),
+    case("slli masks", "slli 2, 1, 16\n", &[(1, 3)], &[(2, 3)]),
+    case("srli masks", "srli 2, 1, 20\n", &[(1, 0x80)], &[(2, 0x8)]),
+    // Register 0 reads as zero and ignores writes
+    case("zero move", "add 1, 2, 0\n", &[(2, 7)], &[(1, 7)]),
+    case("zero load", "addi 1, 0, 42\n", &[], &[(1, 42)]),
+    case(
+        "zero write",
+        "addi 0, 0, 5\nadd 1, 0, 0\n",
+        &[],
+        &[(0, 0), (1, 0)],
+    ),
+    case(
+        "simple.blasm",
+        include_str!("../../demos/blasm/simple.blasm"),
+        &[],
+        &[(0, 0), (1, 1)],
+    ),
+    // Load/Store
+    Case {
+        name: "str",
+        source: "str 1, 2, 3\n",
+        registers: &[(1, DATA as u16), (2, 42)],
+        expected: &[],
+        memory: &[(DATA + 3, 42)],
+    },
+    Case {
+        name: "ld",
+        source: "str 1, 2, 3\nld 3, 1, 3\n",
+        registers: &[(1, DATA as u16), (2, 42)],
+        expected: &[(3, 42)],
+        memory: &[(DATA + 3, 42)],
+    },
+    // B, jumping over the write to `3` when taken.
+    case("be taken", BRANCH_BE, &[(1, 7), (2, 7)], &[(3, 0), (4, 1)]),
+    case(
+        "be not taken",
+        BRANCH_BE,
+        &[(1, 7), (2, 8)],
+        &[(3, 1), (4, 1)],
+    ),
+    case(
+        "bne taken",
+        BRANCH_BNE,
+        &[(1, 7), (2, 8)],
+        &[(3, 0), (4, 1)],
+    ),
+    case(
+        "bne not taken",
+        BRANCH_BNE,
+        &[(1, 7), (2, 7)],
+        &[(3, 1), (4, 1)],
+    ),
+    case(
+        "blt taken",
+        BRANCH_BLT,
+        &[(1, 0xFFFF), (2, 1)],
+        &[(3, 0), (4, 1)],
+    ),
+    case(
+        "blt not taken",
+        BRANCH_BLT,
+        &[(1, 1), (2, 0xFFFF)],
+        &[(3, 1), (4, 1)],
+    ),
+    case(
+        "blt equal",
+        BRANCH_BLT,
+        &[(1, 5), (2, 5)],
+        &[(3, 1), (4, 1)],
+    ),
+    case(
+        "bge taken",
+        BRANCH_BGE,
+        &[(1, 1), (2, 0xFFFF)],
+        &[(3, 0), (4, 1)],
+    ),
+    case(
+        "bge equal",
+        BRANCH_BGE,
+        &[(1, 5), (2, 5)],
+        &[(3, 0), (4, 1)],
+    ),
+    case(
+        "bge not taken",
+        BRANCH_BGE,
+        &[(1, 0xFFFF), (2, 1)],
+        &[(3, 1), (4, 1)],
+    ),
+    case(
+        "bltu taken",
+        BRANCH_BLTU,
+        &[(1, 1), (2, 0xFFFF)],
+        &[(3, 0), (4, 1)],
+    ),
+    case(
+        "bltu not taken",
+        BRANCH_BLTU,
+        &[(1, 0xFFFF), (2, 1)],
+        &[(3, 1), (4, 1)],
+    ),
+    case(
+        "bltu equal",
+        BRANCH_BLTU,
+        &[(1, 5), (2, 5)],
+        &[(3, 1), (4, 1)],
+    ),
+    case(
+        "bgeu taken",
+        BRANCH_BGEU,
+        &[(1, 0xFFFF), (2, 1)],
+        &[(3, 0), (4, 1)],
+    ),
+    case(
+        "bgeu equal",
+        BRANCH_BGEU,
+        &[(1, 5), (2, 5)],
+        &[(3, 0), (4, 1)],
+    ),
+    case(
+        "bgeu not taken",
+        BRANCH_BGEU,
+        &[(1, 1), (2, 0xFFFF)],
+        &[(3, 1), (4, 1)],
+    ),
+    // Subroutines, `S` starts at the end of RAM.
+    case(
+        "jal",
+        "jal 3, 1, 2\naddi 4, 0, 1\n",
+        &[(1, 2)],
+        &[(3, 2), (4, 0)],
+    ),
+    case("call and ret", CALL, &[], &[(1, 1), (14, 8)]),
+    Case {
+        name: "push",
+        source: "push 1\n",
+        registers: &[(1, 42)],
+        expected: &[(15, RAM.end as u16 - 1)],
+        memory: &[(RAM.end - 1, 42)],
+    },
+    case(
+        "pop",
+        "push 1\npop 2\n",
+        &[(1, 42)],
+        &[(2, 42), (15, RAM.end as u16)],
+    ),
+    case(
+        "push pop order",
+        PUSH_POP,
+        &[(1, 1), (2, 2)],
+        &[(3, 2), (4, 1)],
+    ),
+    // Return to `8`, the end, with the status word in `1`.
+    case(
+        "reti",
+        "push 2\npush 1\nreti\naddi 3, 0, 1\n",
+        &[(1, 4), (2, 8)],
+        &[(3, 0), (15, RAM.end as u16)],
+    ),
+    case(
+        "halt",
+        "addi 1, 0, 1\nhalt\naddi 2, 0, 1\n",
+        &[],
+        &[(1, 1), (2, 0)],
+    ),
+    // Nothing can wake the CPU, execution stops on `wfi`.
+    case("wfi", "wfi\naddi 1, 0, 1\n", &[], &[(1, 0)]),
+    case(
+        "fibo_recursive.blasm",
+        include_str!("../../demos/blasm/fibo_recursive.blasm"),
+        &[],
+        &[(1, 55), (15, RAM.end as u16)],
+    ),
+];
+
+const BRANCH_BE: &str = "be 1, 2, 4\naddi 3, 0, 1\naddi 4, 0, 1\n";
+const BRANCH_BNE: &str = "bne 1, 2, 4\naddi 3, 0, 1\naddi 4, 0, 1\n";
+const BRANCH_BLT: &str = "blt 1, 2, 4\naddi 3, 0, 1\naddi 4, 0, 1\n";
+const BRANCH_BGE: &str = "bge 1, 2, 4\naddi 3, 0, 1\naddi 4, 0, 1\n";
+const BRANCH_BLTU: &str = "bltu 1, 2, 4\naddi 3, 0, 1\naddi 4, 0, 1\n";
+const BRANCH_BGEU: &str = "bgeu 1, 2, 4\naddi 3, 0, 1\naddi 4, 0, 1\n";
+
+/// Call the subroutine at `2`, which sets `1`, from the end of the program.
+const CALL: &str = "be 0, 0, 6\naddi 1, 0, 1\nret\ncall 2\n";
+const PUSH_POP: &str = "push 1\npush 2\npop 3\npop 4\n";
+
+pub(crate) fn assemble(source: &str) -> Vec<Instruction> {
+    let lexer = Lexer::new(source);
+
+    ASM::new(source, &lexer)
+        .map(|instruction| instruction.expect("valid source").into())
+        .collect()
+}
+
+fn run(case: &Case) -> Emulator {
+    let mut emulator = Emulator::new();
+    emulator.load_program(assemble(case.source)).unwrap();
+
+    for &(register, value) in case.registers {
+        emulator.registers_mut()[register] = value;
+    }
+
+    emulator
+        .execute_all()
+        .unwrap_or_else(|fault| panic!("{}: {}", case.name, fault));
+
+    emulator
+}
+
+#[test]
+fn conformance() {
+    for case in CASES {
+        let mut emulator = run(case);
+
+        for &(register, value) in case.expected {
+            assert_eq!(
+                emulator.registers[register], value,
+                "{}: register {}",
+                case.name, register
+            );
+        }
+
+        for &(address, value) in case.memory {
+            assert_eq!(
+                emulator.memory_mut().read(address),
+                Ok(value),
+                "{}: address 0x{:04X}",
+                case.name,
+                address
+            );
+        }
+    }
+}
+
+#[test]
+fn every_opcode_is_covered() {
+    let covered: Vec<_> = CASES
+        .iter()
+        .flat_map(|case| assemble(case.source))
+        .map(|instruction| instruction.get_opcode() as u8)
+        .collect();
+
+    for mnemonic in [
+        "add", "sub", "or", "and", "xor", "sll", "srl", "addi", "subi", "ori", "andi", "xori",
+        "slli", "srli", "jal", "ld", "str", "be", "bne", "blt", "bge", "bltu", "bgeu",
+    ] {
+        let opcode = assemble(&format!("{mnemonic} 0, 0, 0\n"))[0].get_opcode() as u8;
+
+        assert!(covered.contains(&opcode), "`{mnemonic}` has no case");
+    }
+
+    for source in ["push 0", "pop 0", "reti", "halt", "wfi"] {
+        let opcode = assemble(&format!("{source}\n"))[0].get_opcode() as u8;
+
+        assert!(covered.contains(&opcode), "`{source}` has no case");
+    }
+}
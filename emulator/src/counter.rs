@@ -0,0 +1,118 @@
+//! Cycle counter of the Blask handheld.
+//!
+//! The counter holds the cycles run since power-on as 64 bits, read through
+//! four MMIO registers, lowest word first:
+//!
+//! | Address  | Register  | Access                                              |
+//! |----------|-----------|-----------------------------------------------------|
+//! | `0xF060` | `CYCLES0` | bits 0 to 15, reading it latches the other words    |
+//! | `0xF061` | `CYCLES1` | bits 16 to 31, as latched                           |
+//! | `0xF062` | `CYCLES2` | bits 32 to 47, as latched                           |
+//! | `0xF063` | `CYCLES3` | bits 48 to 63, as latched                           |
+//!
+//! Reading `CYCLES0` first, a program gets the four words of the same count
+//! even though the counter advances between the reads. The registers are
+//! read-only. How many cycles each instruction takes is set by the
+//! [timing model](crate::timing).
+
+use core::ops::Range;
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// MMIO registers of the counter.
+pub const REGISTERS: Range<usize> = 0xF060..0xF064;
+
+pub const CYCLES0: usize = REGISTERS.start;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Counter {
+    cycles: u64,
+    /// The count when `CYCLES0` was last read.
+    latched: u64,
+}
+
+impl Counter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.u64(self.cycles);
+        writer.u64(self.latched);
+    }
+
+    pub fn restore(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.cycles = reader.u64()?;
+        self.latched = reader.u64()?;
+
+        Ok(())
+    }
+
+    /// Return the cycles run.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Advance the counter by one cycle.
+    pub fn tick(&mut self) {
+        self.cycles += 1;
+    }
+
+    /// Read the register at `address`, one of [REGISTERS].
+    pub fn read(&mut self, address: usize) -> u16 {
+        if address == CYCLES0 {
+            self.latched = self.cycles;
+        }
+
+        (self.latched >> (16 * (address - CYCLES0))) as u16
+    }
+
+    /// Return what reading the register at `address` would, without latching.
+    pub fn peek(&self, address: usize) -> u16 {
+        let count = if address == CYCLES0 {
+            self.cycles
+        } else {
+            self.latched
+        };
+
+        (count >> (16 * (address - CYCLES0))) as u16
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reading_the_low_word_latches() {
+        let mut counter = Counter::new();
+        for _ in 0..0x1_FFFF {
+            counter.tick();
+        }
+
+        assert_eq!(counter.read(CYCLES0), 0xFFFF);
+        counter.tick();
+        assert_eq!(counter.read(CYCLES0 + 1), 1);
+        assert_eq!(counter.read(CYCLES0 + 3), 0);
+        assert_eq!(counter.read(CYCLES0), 0);
+        assert_eq!(counter.read(CYCLES0 + 1), 2);
+    }
+
+    #[test]
+    fn peeking_does_not_latch() {
+        let mut counter = Counter::new();
+        for _ in 0..0x1_FFFF {
+            counter.tick();
+        }
+
+        assert_eq!(counter.peek(CYCLES0), 0xFFFF);
+        assert_eq!(counter.peek(CYCLES0 + 1), 0);
+        assert_eq!(
+            counter,
+            Counter {
+                cycles: 0x1_FFFF,
+                latched: 0
+            }
+        );
+    }
+}
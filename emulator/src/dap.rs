@@ -0,0 +1,754 @@
+//! Debug Adapter Protocol server.
+//!
+//! Editors such as VS Code debug a `.blasm` program running on the emulator
+//! with the Debug Adapter Protocol, over the standard input and output of
+//! `--dap`. The requests are served by a [Debugger], and the spans the
+//! assembler keeps for each instruction give their line in the source:
+//!
+//! | Request                                 | Action                                        |
+//! |-----------------------------------------|-----------------------------------------------|
+//! | `initialize`                            | the capabilities of the adapter               |
+//! | `launch`                                | assemble and load `program`                   |
+//! | `setBreakpoints`                        | stop at lines, with conditions such as `r1 == 5` |
+//! | `configurationDone`                     | run, or stop on entry with `stopOnEntry`      |
+//! | `continue`, `pause`                     | run until something stops the program, stop it |
+//! | `next`, `stepIn`, `stepOut`             | step a line over or into a call, finish it    |
+//! | `threads`, `stackTrace`, `scopes`       | the single thread and its single frame        |
+//! | `variables`, `setVariable`              | read or write the registers and `pc`          |
+//! | `readMemory`                            | read memory, for the hex view                 |
+//! | `terminate`, `disconnect`               | end the session                               |
+//!
+//! Each instruction is on a line of its own, so stepping a line steps an
+//! instruction. Lines count from 1. Memory is addressed in bytes as in the
+//! [GDB server](crate::gdb): twice the word address, the low byte first.
+
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+
+use blib::INSTRUCTION_SIZE;
+use files::Program;
+use serde_json::{json, Value};
+
+use crate::debugger::{Debugger, Stop, Target};
+use crate::{Emulator, Exception};
+
+/// Instructions run between two checks for a request, such as `pause`.
+const SLICE: usize = 10_000;
+
+/// Id of the only thread.
+const THREAD: u64 = 1;
+
+/// Reference of the registers, the only scope.
+const REGISTERS: u64 = 1;
+
+/// Serve the requests read from `input`, until the client disconnects.
+pub fn serve(input: impl BufRead + Send + 'static, mut output: impl Write) -> io::Result<()> {
+    // Requests are read meanwhile the program runs
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut input = input;
+
+        while let Some(message) = read_message(&mut input).transpose() {
+            let error = message.is_err();
+
+            if sender.send(message).is_err() || error {
+                break;
+            }
+        }
+    });
+
+    let mut adapter = Adapter::new();
+
+    while !adapter.done() {
+        let message = if adapter.running() {
+            match receiver.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return Ok(()),
+            }
+        } else {
+            match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return Ok(()),
+            }
+        };
+
+        let messages = match message {
+            Some(message) => adapter.handle(&message?),
+            None => adapter.run_slice(),
+        };
+
+        for message in messages {
+            write_message(&mut output, &message)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read a message after its `Content-Length` header, `None` at the end of
+/// the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() && length.is_some() {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut body = vec![0; length.unwrap_or_default()];
+    input.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+/// A program launched by the client.
+struct Session {
+    emulator: Emulator,
+    debugger: Debugger,
+    program: Program,
+    /// Path of the program, as the client gave it.
+    path: String,
+    stop_on_entry: bool,
+    /// Ids of the breakpoints set by `setBreakpoints`.
+    breakpoints: Vec<usize>,
+}
+
+/// Answers the requests of a client, independent of how they are sent.
+pub struct Adapter {
+    session: Option<Session>,
+    /// Sequence number of the next message.
+    seq: u64,
+    /// Events sent after the response to the request handled.
+    events: Vec<(&'static str, Value)>,
+    running: bool,
+    done: bool,
+}
+
+impl Default for Adapter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Adapter {
+    pub fn new() -> Self {
+        Self {
+            session: None,
+            seq: 1,
+            events: Vec::new(),
+            running: false,
+            done: false,
+        }
+    }
+
+    /// Indicates if the program runs, [Adapter::run_slice] running it on.
+    pub fn running(&self) -> bool {
+        self.running
+    }
+
+    /// Indicates if the client disconnected.
+    pub fn done(&self) -> bool {
+        self.done
+    }
+
+    /// Answer `request`, returning the response and the events following it.
+    pub fn handle(&mut self, request: &Value) -> Vec<Value> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let arguments = &request["arguments"];
+
+        let body = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsConditionalBreakpoints": true,
+                "supportsSetVariable": true,
+                "supportsReadMemoryRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "launch" => self.launch(arguments),
+            "setBreakpoints" => self.set_breakpoints(arguments),
+            "configurationDone" => self.configuration_done(),
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD, "name": "main" }] })),
+            "stackTrace" => self.stack_trace(),
+            "scopes" => Ok(json!({
+                "scopes": [{
+                    "name": "Registers",
+                    "presentationHint": "registers",
+                    "variablesReference": REGISTERS,
+                    "expensive": false,
+                }]
+            })),
+            "variables" => self.variables(arguments),
+            "setVariable" => self.set_variable(arguments),
+            "readMemory" => self.read_memory(arguments),
+            "continue" | "next" | "stepIn" | "stepOut" => self.resume(command),
+            "pause" => self.pause(),
+            "terminate" => {
+                self.running = false;
+                self.events.push(("terminated", json!({})));
+                Ok(json!({}))
+            }
+            "disconnect" => {
+                self.done = true;
+                Ok(json!({}))
+            }
+            _ => Err(format!("unsupported request `{command}`")),
+        };
+
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": command,
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+
+        let mut messages = vec![self.message(response)];
+        for (event, body) in std::mem::take(&mut self.events) {
+            messages.push(self.event(event, body));
+        }
+
+        messages
+    }
+
+    /// Run the program on for a while, returning the events of its stop, if any.
+    pub fn run_slice(&mut self) -> Vec<Value> {
+        let Some(session) = &mut self.session else {
+            self.running = false;
+            return Vec::new();
+        };
+
+        let stop = session.debugger.run_slice(&mut session.emulator, SLICE);
+
+        let events = if stop == Ok(Stop::Step) {
+            session.output()
+        } else {
+            self.running = false;
+            session.stopped(stop)
+        };
+
+        events
+            .into_iter()
+            .map(|(event, body)| self.event(event, body))
+            .collect()
+    }
+
+    /// Number `message` with the next sequence number.
+    fn message(&mut self, mut message: Value) -> Value {
+        message["seq"] = self.seq.into();
+        self.seq += 1;
+
+        message
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> Value {
+        self.message(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn session(&mut self) -> Result<&mut Session, String> {
+        self.session
+            .as_mut()
+            .ok_or_else(|| "no program was launched".to_string())
+    }
+
+    fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+        let Some(path) = arguments["program"].as_str() else {
+            return Err("expected the path of the program in `program`".to_string());
+        };
+        if !path.ends_with(".blasm") {
+            return Err(format!("{path} is not a .blasm program"));
+        }
+        if !Path::new(path).is_file() {
+            return Err(format!("{path} does not exist"));
+        }
+
+        let program = files::try_blasm_to_program(path)?;
+
+        let mut emulator = Emulator::new();
+        emulator.set_strict(arguments["strict"].as_bool().unwrap_or_default());
+        emulator.set_cycle_limit(arguments["maxCycles"].as_u64());
+        emulator
+            .load_program(program.instructions.clone())
+            .map_err(|error| error.to_string())?;
+
+        self.session = Some(Session {
+            emulator,
+            debugger: Debugger::new(program.labels.clone()),
+            program,
+            path: path.to_string(),
+            stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or_default(),
+            breakpoints: Vec::new(),
+        });
+
+        // The client sets the breakpoints once the program is loaded
+        self.events.push(("initialized", json!({})));
+
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+
+        let source = arguments["source"]["path"].as_str().unwrap_or_default();
+        let same = |path: &str| Path::new(path).canonicalize().ok();
+        let in_program = same(source).is_some() && same(source) == same(&session.path);
+
+        // The breakpoints of other sources leave those of the program alone
+        if in_program {
+            for id in session.breakpoints.drain(..) {
+                session.debugger.delete(id);
+            }
+        }
+
+        let requested = arguments["breakpoints"].as_array().cloned();
+        let breakpoints: Vec<Value> = requested
+            .unwrap_or_default()
+            .iter()
+            .map(|breakpoint| {
+                let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+                let condition = breakpoint["condition"]
+                    .as_str()
+                    .filter(|condition| !condition.trim().is_empty());
+
+                let address = match in_program {
+                    true => session
+                        .address(line)
+                        .ok_or("no instruction on or after this line"),
+                    false => Err("not a line of the program"),
+                };
+
+                let added = address.map_err(str::to_string).and_then(|address| {
+                    let condition = condition
+                        .map(|condition| session.debugger.condition(condition))
+                        .transpose()?;
+
+                    Ok((address, session.debugger.add_breakpoint(address, condition)))
+                });
+
+                match added {
+                    Ok((address, id)) => {
+                        session.breakpoints.push(id);
+                        json!({ "id": id, "verified": true, "line": session.program.line(address) })
+                    }
+                    Err(message) => json!({ "verified": false, "line": line, "message": message }),
+                }
+            })
+            .collect();
+
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn configuration_done(&mut self) -> Result<Value, String> {
+        let session = self.session()?;
+
+        if session.stop_on_entry {
+            self.events.push((
+                "stopped",
+                json!({ "reason": "entry", "threadId": THREAD, "allThreadsStopped": true }),
+            ));
+        } else {
+            self.running = true;
+        }
+
+        Ok(json!({}))
+    }
+
+    /// Answer `continue`, `next`, `stepIn` or `stepOut`.
+    fn resume(&mut self, command: &str) -> Result<Value, String> {
+        if self.running {
+            return Err("the program is running".to_string());
+        }
+
+        let session = self.session()?;
+        let (debugger, emulator) = (&mut session.debugger, &mut session.emulator);
+
+        let stop = match command {
+            "next" => debugger.next(emulator),
+            "stepIn" => debugger.step(emulator, 1),
+            "stepOut" => debugger.finish(emulator),
+            // Leave the breakpoint at `pc` first, the slices go on from there
+            _ => debugger.step(emulator, 1),
+        };
+
+        let running = command == "continue" && stop == Ok(Stop::Step);
+        let events = if running {
+            session.output()
+        } else {
+            session.stopped(stop)
+        };
+        self.running = running;
+        self.events.extend(events);
+
+        Ok(json!({ "allThreadsContinued": true }))
+    }
+
+    fn pause(&mut self) -> Result<Value, String> {
+        if self.running {
+            self.running = false;
+            self.events.push((
+                "stopped",
+                json!({ "reason": "pause", "threadId": THREAD, "allThreadsStopped": true }),
+            ));
+        }
+
+        Ok(json!({}))
+    }
+
+    fn stack_trace(&mut self) -> Result<Value, String> {
+        let session = self.session()?;
+        let pc = session.emulator.pc();
+
+        let mut frame = json!({
+            "id": 1,
+            "name": session.debugger.location(pc),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": reference(pc),
+        });
+
+        // Past the program, there is no source to show
+        if let Some(line) = session.program.line(pc) {
+            let name = Path::new(&session.path).file_name();
+
+            frame["line"] = line.into();
+            frame["column"] = 1.into();
+            frame["source"] = json!({
+                "name": name.map(|name| name.to_string_lossy()),
+                "path": session.path,
+            });
+        }
+
+        Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+    }
+
+    fn variables(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+
+        if arguments["variablesReference"] != REGISTERS {
+            return Ok(json!({ "variables": [] }));
+        }
+
+        let emulator = &session.emulator;
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+
+        let mut variables = Vec::new();
+
+        for (register, value) in emulator.registers().iter().enumerate() {
+            let mut register = variable(format!("r{register}"), format!("0x{value:04X}"));
+            register["memoryReference"] = reference(*value).into();
+            variables.push(register);
+        }
+
+        let mut pc = variable("pc".to_string(), session.debugger.location(emulator.pc()));
+        pc["memoryReference"] = reference(emulator.pc()).into();
+        variables.push(pc);
+
+        variables.push(variable("flags".to_string(), emulator.flags().to_string()));
+        variables.push(variable(
+            "cycles".to_string(),
+            emulator.cycles().to_string(),
+        ));
+
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+
+        let name = arguments["name"].as_str().unwrap_or_default();
+        let target = match name {
+            "pc" => Target::Pc,
+            _ => match name
+                .strip_prefix('r')
+                .and_then(|number| number.parse().ok())
+            {
+                Some(register) if register < 16 => Target::Register(register),
+                _ => return Err(format!("{name} cannot be set")),
+            },
+        };
+
+        let value = session
+            .debugger
+            .value(arguments["value"].as_str().unwrap_or_default())?;
+        Debugger::set(&mut session.emulator, target, value)?;
+
+        let value = match target {
+            Target::Pc => session.debugger.location(value),
+            _ => format!("0x{value:04X}"),
+        };
+
+        Ok(json!({ "value": value }))
+    }
+
+    fn read_memory(&mut self, arguments: &Value) -> Result<Value, String> {
+        let session = self.session()?;
+
+        let reference = arguments["memoryReference"].as_str().unwrap_or_default();
+        let offset = arguments["offset"].as_i64().unwrap_or_default();
+        let start = parse_reference(reference)
+            .and_then(|start| start.checked_add_signed(offset))
+            .ok_or_else(|| format!("invalid memory reference `{reference}`"))?;
+        let count = arguments["count"].as_u64().unwrap_or_default();
+
+        let bytes: Vec<u8> = (start..start + count)
+            .map_while(|address| session.read_byte(address))
+            .collect();
+
+        Ok(json!({
+            "address": format!("0x{start:X}"),
+            "data": base64(&bytes),
+            "unreadableBytes": count - bytes.len() as u64,
+        }))
+    }
+}
+
+impl Session {
+    /// Return the address of the first instruction on `line` or after it.
+    fn address(&self, line: usize) -> Option<u16> {
+        (0..self.program.spans.len() as u16)
+            .map(|index| index * INSTRUCTION_SIZE)
+            .find(|address| self.program.line(*address) >= Some(line))
+    }
+
+    /// Read the byte at `address`, addressing bytes.
+    fn read_byte(&mut self, address: u64) -> Option<u8> {
+        let word = u16::try_from(address / 2).ok()?;
+        let bytes = Debugger::peek(&mut self.emulator, word).ok()?.to_le_bytes();
+
+        Some(bytes[address as usize % 2])
+    }
+
+    /// Events of the warnings reported since the last call.
+    fn output(&mut self) -> Vec<(&'static str, Value)> {
+        self.emulator
+            .take_warnings()
+            .into_iter()
+            .map(|warning| {
+                let output = format!("warning: {warning}\n");
+                ("output", json!({ "category": "stderr", "output": output }))
+            })
+            .collect()
+    }
+
+    /// Events of the program stopping with `stop`.
+    fn stopped(&mut self, stop: Result<Stop, Exception>) -> Vec<(&'static str, Value)> {
+        let mut events = self.output();
+
+        let mut body = json!({ "threadId": THREAD, "allThreadsStopped": true });
+        match stop {
+            Ok(Stop::End) => {
+                events.push(("exited", json!({ "exitCode": 0 })));
+                events.push(("terminated", json!({})));
+                return events;
+            }
+            Ok(stop) => {
+                body["reason"] = match stop {
+                    Stop::Breakpoint(id) => {
+                        body["hitBreakpointIds"] = json!([id]);
+                        "breakpoint"
+                    }
+                    Stop::Watchpoint { .. } => "data breakpoint",
+                    Stop::Stalled | Stop::CycleLimit => "pause",
+                    _ => "step",
+                }
+                .into();
+                body["description"] = stop.to_string().into();
+            }
+            Err(exception) => {
+                body["reason"] = "exception".into();
+                body["description"] = "exception".into();
+                body["text"] = exception.to_string().into();
+            }
+        }
+
+        events.push(("stopped", body));
+        events
+    }
+}
+
+/// Reference of the word at `address`, as a byte address.
+fn reference(address: u16) -> String {
+    format!("0x{:X}", address as u32 * 2)
+}
+
+/// Parse a memory reference, hexadecimal after `0x` or decimal.
+fn parse_reference(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+/// Encode `bytes` in base 64, as `readMemory` answers.
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut text = String::new();
+
+    for chunk in bytes.chunks(3) {
+        let bits = chunk.iter().enumerate().fold(0, |bits, (index, byte)| {
+            bits | (*byte as u32) << (16 - 8 * index)
+        });
+
+        // A character for each 6 bits, padded to 4 characters
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(bits >> (18 - 6 * index) & 0x3F) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Write as _;
+    use std::io::Cursor;
+
+    /// Replay the recorded session `name`, the requests of its `->` lines,
+    /// and compare the responses and events with its `<-` lines, or write
+    /// them when `UPDATE_GOLDEN` is set.
+    ///
+    /// The program runs to its stop after each request.
+    fn replay(name: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("golden")
+            .join("dap")
+            .join(name);
+        let recorded = std::fs::read_to_string(&path).unwrap();
+
+        let mut adapter = Adapter::new();
+        let mut session = String::new();
+
+        for line in recorded.lines() {
+            if line.starts_with("<- ") {
+                continue;
+            }
+            writeln!(session, "{line}").unwrap();
+
+            let Some(request) = line.strip_prefix("-> ") else {
+                continue;
+            };
+
+            let mut messages = adapter.handle(&serde_json::from_str(request).unwrap());
+            while adapter.running() {
+                messages.extend(adapter.run_slice());
+            }
+
+            for message in messages {
+                writeln!(session, "<- {message}").unwrap();
+            }
+        }
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &session).unwrap();
+        }
+
+        for (recorded, replayed) in recorded.lines().zip(session.lines()) {
+            assert_eq!(
+                recorded,
+                replayed,
+                "the session differs from {}",
+                path.display()
+            );
+        }
+        assert_eq!(recorded.lines().count(), session.lines().count());
+    }
+
+    #[test]
+    fn breakpoints() {
+        replay("breakpoints.txt");
+    }
+
+    #[test]
+    fn stepping() {
+        replay("stepping.txt");
+    }
+
+    #[test]
+    fn invalid_program() {
+        replay("invalid_program.txt");
+    }
+
+    fn request(seq: u64, command: &str, arguments: Value) -> Value {
+        json!({ "seq": seq, "type": "request", "command": command, "arguments": arguments })
+    }
+
+    #[test]
+    fn pause() {
+        let mut adapter = Adapter::new();
+        let program = "../demos/blasm/fibo_recursive.blasm";
+
+        adapter.handle(&request(1, "launch", json!({ "program": program })));
+        adapter.handle(&request(2, "configurationDone", json!({})));
+        assert!(adapter.running());
+        assert!(adapter.handle(&request(3, "next", json!({})))[0]["success"] == false);
+
+        let messages = adapter.handle(&request(4, "pause", json!({})));
+        assert!(!adapter.running());
+        assert_eq!(messages[1]["event"], "stopped");
+        assert_eq!(messages[1]["body"]["reason"], "pause");
+    }
+
+    #[test]
+    fn framing() {
+        let mut input = String::new();
+        for message in [
+            request(1, "initialize", json!({ "adapterID": "blask" })),
+            request(2, "disconnect", json!({})),
+        ] {
+            let body = message.to_string();
+            write!(input, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        }
+
+        let mut output = Vec::new();
+        serve(Cursor::new(input.into_bytes()), &mut output).unwrap();
+
+        let mut output = Cursor::new(output);
+        let initialize = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(initialize["request_seq"], 1);
+        assert_eq!(initialize["body"]["supportsConfigurationDoneRequest"], true);
+
+        let disconnect = read_message(&mut output).unwrap().unwrap();
+        assert_eq!(disconnect["seq"], 2);
+        assert_eq!(disconnect["command"], "disconnect");
+        assert_eq!(read_message(&mut output).unwrap(), None);
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(&[0xFF, 0xFE]), "//4=");
+    }
+}
@@ -0,0 +1,1238 @@
+//! Debugger for the Blask CPU, independent of any frontend.
+//!
+//! A [Debugger] runs an [Emulator] instruction by instruction and stops it at
+//! breakpoints, when a watched word changes, after a number of steps, when
+//! the running subroutine returns or when the program runs out of cycles. It knows the labels of the program, so a
+//! location is an address or a label, and the code is disassembled with the
+//! labels it was written with.
+//!
+//! Text frontends share the commands of [Debugger::parse]:
+//!
+//! | Command                             | Action                                         |
+//! |-------------------------------------|------------------------------------------------|
+//! | `break <location> [if <condition>]` | stop before the instruction at `location`      |
+//! | `watch <address>`                   | stop when the word at `address` changes        |
+//! | `delete <id>`                       | remove a breakpoint or a watchpoint            |
+//! | `info`                              | list the breakpoints and the watchpoints       |
+//! | `continue`                          | run until something stops the program          |
+//! | `step [count]`                      | run `count` instructions, 1 by default         |
+//! | `next`                              | run one instruction, a call until it returns   |
+//! | `finish`                            | run until the current subroutine returns       |
+//! | `reverse-step [count]`              | go back `count` steps, 1 by default            |
+//! | `reverse-continue`                  | go back to the previous breakpoint             |
+//! | `who <register>`                    | show the step that last wrote a register       |
+//! | `who <address>`                     | show the step that last wrote a word           |
+//! | `print`                             | show the registers and the cycles run          |
+//! | `set <register> <value>`            | write a register, `r0` to `r15` or `pc`        |
+//! | `set <address> <value>`             | write a word of memory, ROM included           |
+//! | `x <address> [count]`               | dump `count` words of memory, 16 by default    |
+//! | `disassemble [location] [count]`    | disassemble around `pc` or from `location`     |
+//!
+//! Commands may be shortened to their first letter, `dis` for `disassemble`,
+//! `rs` for `reverse-step` and `rc` for `reverse-continue`. Going back needs
+//! the [history] of the emulator, see [Emulator::set_recording].
+//! Values are decimal, hexadecimal after `0x` or labels such as `@Loop`, and a
+//! condition compares a register with a value, unsigned: `r1 == 5`.
+
+use core::fmt;
+use std::collections::HashMap;
+
+use asmlib::instruction::*;
+use blib::{Register, INSTRUCTION_SIZE};
+
+use crate::history::{LastWrite, Location};
+use crate::memory::{Bus, BusFault, ADDRESS_SPACE, ROM};
+use crate::{Emulator, Exception};
+
+/// Instructions shown before and after `pc` by `disassemble`.
+const CONTEXT: u16 = 4;
+
+/// Error of the commands going back when the history is not recorded.
+const NOT_RECORDING: &str = "the history is not recorded";
+
+/// Comparison of a register with a value, as unsigned numbers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    const OPERATORS: [(&'static str, Comparison); 6] = [
+        ("==", Comparison::Equal),
+        ("!=", Comparison::NotEqual),
+        ("<=", Comparison::LessOrEqual),
+        (">=", Comparison::GreaterOrEqual),
+        ("<", Comparison::Less),
+        (">", Comparison::Greater),
+    ];
+
+    fn operator(&self) -> &'static str {
+        Self::OPERATORS
+            .iter()
+            .find(|(_, comparison)| comparison == self)
+            .map(|(operator, _)| *operator)
+            .unwrap()
+    }
+}
+
+/// Condition of a breakpoint, comparing a register with a value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Condition {
+    pub register: usize,
+    pub comparison: Comparison,
+    pub value: u16,
+}
+
+impl Condition {
+    /// Indicates if the condition holds for `registers`.
+    pub fn holds(&self, registers: &[u16; 16]) -> bool {
+        let register = registers[self.register];
+
+        match self.comparison {
+            Comparison::Equal => register == self.value,
+            Comparison::NotEqual => register != self.value,
+            Comparison::Less => register < self.value,
+            Comparison::LessOrEqual => register <= self.value,
+            Comparison::Greater => register > self.value,
+            Comparison::GreaterOrEqual => register >= self.value,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "r{} {} {}",
+            self.register,
+            self.comparison.operator(),
+            self.value
+        )
+    }
+}
+
+/// Stop before the instruction at `address`, when `condition` holds if any.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Breakpoint {
+    pub id: usize,
+    pub address: u16,
+    pub condition: Option<Condition>,
+}
+
+/// Stop when the word at `address` changes from `value`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub id: usize,
+    pub address: u16,
+    pub value: u16,
+}
+
+/// Why the debugger stopped the program.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    /// The steps asked for ran.
+    Step,
+    /// The breakpoint with this id was reached.
+    Breakpoint(usize),
+    /// The word watched by watchpoint `id` changed from `old` to `new`.
+    Watchpoint {
+        id: usize,
+        address: u16,
+        old: u16,
+        new: u16,
+    },
+    /// The subroutine running at `finish` returned.
+    Return,
+    /// The program ended or halted.
+    End,
+    /// The CPU waits for an interrupt that only the host can raise.
+    Stalled,
+    /// The cycle limit of the emulator was reached.
+    CycleLimit,
+    /// Going back reached the first step still recorded.
+    HistoryStart,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stop::Step => write!(f, "stepped"),
+            Stop::Breakpoint(id) => write!(f, "breakpoint {id}"),
+            Stop::Watchpoint {
+                id,
+                address,
+                old,
+                new,
+            } => {
+                write!(
+                    f,
+                    "watchpoint {id}: 0x{address:04X} changed from {old} to {new}"
+                )
+            }
+            Stop::Return => write!(f, "returned"),
+            Stop::End => write!(f, "the program ended"),
+            Stop::Stalled => write!(f, "waiting for an interrupt"),
+            Stop::CycleLimit => write!(f, "reached the cycle limit"),
+            Stop::HistoryStart => write!(f, "reached the start of the history"),
+        }
+    }
+}
+
+/// What `set` writes: a register, `pc` or a word of memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Register(usize),
+    Pc,
+    Memory(u16),
+}
+
+/// A command of a text frontend, see the [module](self) for the syntax.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Break(u16, Option<Condition>),
+    Watch(u16),
+    Delete(usize),
+    Info,
+    Continue,
+    Step(usize),
+    Next,
+    Finish,
+    ReverseStep(usize),
+    ReverseContinue,
+    LastWrite(Location),
+    Print,
+    Set(Target, u16),
+    Examine(u16, usize),
+    Disassemble(Option<u16>, usize),
+    History,
+    Help,
+    Quit,
+}
+
+/// A call or a return, followed by `finish`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Jump {
+    Call,
+    Return,
+}
+
+/// Breakpoints, watchpoints and labels of a program run by an [Emulator].
+#[derive(Clone, Debug)]
+pub struct Debugger {
+    labels: HashMap<String, u16>,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// Id of the next breakpoint or watchpoint.
+    next: usize,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+impl Debugger {
+    /// Create a debugger for a program with `labels`, by name with the `@`.
+    pub fn new(labels: HashMap<String, u16>) -> Self {
+        Self {
+            labels,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            next: 1,
+        }
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Return the address of the label `name`, with or without its `@`.
+    pub fn label(&self, name: &str) -> Option<u16> {
+        let name = name.strip_prefix('@').unwrap_or(name);
+
+        self.labels.get(&format!("@{name}")).copied()
+    }
+
+    /// Return `address` with the closest label before it, such as `0x0006 <@Loop+2>`.
+    pub fn location(&self, address: u16) -> String {
+        let label = self
+            .labels
+            .iter()
+            .filter(|(_, &start)| start <= address)
+            .max_by_key(|(name, &start)| (start, core::cmp::Reverse(*name)));
+
+        match label {
+            Some((name, &start)) if start == address => format!("0x{address:04X} <{name}>"),
+            Some((name, &start)) => format!("0x{address:04X} <{name}+{}>", address - start),
+            None => format!("0x{address:04X}"),
+        }
+    }
+
+    /// Add a breakpoint at `address`, return its id.
+    pub fn add_breakpoint(&mut self, address: u16, condition: Option<Condition>) -> usize {
+        let id = self.id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            address,
+            condition,
+        });
+
+        id
+    }
+
+    /// Watch the word at `address`, return the id of the watchpoint.
+    pub fn add_watchpoint(
+        &mut self,
+        emulator: &mut Emulator,
+        address: u16,
+    ) -> Result<usize, BusFault> {
+        let value = Self::peek(emulator, address)?;
+
+        let id = self.id();
+        self.watchpoints.push(Watchpoint { id, address, value });
+
+        Ok(id)
+    }
+
+    /// Remove the breakpoint or the watchpoint `id`, return whether it existed.
+    pub fn delete(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len() + self.watchpoints.len();
+
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+
+        self.breakpoints.len() + self.watchpoints.len() != count
+    }
+
+    /// Remove the breakpoints at `address`, return whether there was one.
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.address != address);
+
+        self.breakpoints.len() != count
+    }
+
+    /// Remove the watchpoints on `address`, return whether there was one.
+    pub fn remove_watchpoint(&mut self, address: u16) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints
+            .retain(|watchpoint| watchpoint.address != address);
+
+        self.watchpoints.len() != count
+    }
+
+    fn id(&mut self) -> usize {
+        self.next += 1;
+        self.next - 1
+    }
+
+    /// Run `count` instructions, unless something stops the program first.
+    pub fn step(&mut self, emulator: &mut Emulator, count: usize) -> Result<Stop, Exception> {
+        self.run(emulator, Some(count), false, true)
+    }
+
+    /// Run one instruction, or when it calls a subroutine, run until the
+    /// subroutine returns.
+    pub fn next(&mut self, emulator: &mut Emulator) -> Result<Stop, Exception> {
+        let call = jump(emulator).filter(|(jump, _)| *jump == Jump::Call);
+
+        match self.step(emulator, 1)? {
+            Stop::Step if call.is_some_and(|(_, target)| emulator.pc == target) => {
+                match self.finish(emulator)? {
+                    Stop::Return => Ok(Stop::Step),
+                    stop => Ok(stop),
+                }
+            }
+            stop => Ok(stop),
+        }
+    }
+
+    /// Run until a breakpoint, a watchpoint or the end of the program.
+    pub fn resume(&mut self, emulator: &mut Emulator) -> Result<Stop, Exception> {
+        self.run(emulator, None, false, true)
+    }
+
+    /// Run at most `count` instructions, for frontends checking for an
+    /// interruption in between.
+    ///
+    /// Unlike [Debugger::step], a breakpoint at `pc` stops the program at
+    /// once, so a slice picks up where the previous one stopped.
+    pub fn run_slice(&mut self, emulator: &mut Emulator, count: usize) -> Result<Stop, Exception> {
+        self.run(emulator, Some(count), false, false)
+    }
+
+    /// Run until the current subroutine returns with `ret`.
+    ///
+    /// Calls made meanwhile are followed, so a recursive subroutine stops
+    /// when the call running at `finish` returns.
+    pub fn finish(&mut self, emulator: &mut Emulator) -> Result<Stop, Exception> {
+        self.run(emulator, None, true, true)
+    }
+
+    /// Go back `count` steps, or to the first step still recorded.
+    pub fn reverse_step(&mut self, emulator: &mut Emulator, count: usize) -> Result<Stop, String> {
+        let history = emulator.history().ok_or(NOT_RECORDING)?;
+        let (step, first) = (history.step(), history.first());
+
+        let stop = match step.checked_sub(count as u64) {
+            Some(step) if step >= first => {
+                emulator.rewind(step);
+                Stop::Step
+            }
+            _ => {
+                emulator.rewind(first);
+                Stop::HistoryStart
+            }
+        };
+        self.rewatch(emulator);
+
+        Ok(stop)
+    }
+
+    /// Go back to the last time the program stopped at a breakpoint, or to
+    /// the first step still recorded.
+    pub fn reverse_resume(&mut self, emulator: &mut Emulator) -> Result<Stop, String> {
+        let first = emulator.history().ok_or(NOT_RECORDING)?.first();
+
+        // A breakpoint found in the log is checked once back there, as the
+        // CPU may have been waiting then.
+        let stop = loop {
+            let Some(step) = self.previous_breakpoint(emulator) else {
+                emulator.rewind(first);
+                break Stop::HistoryStart;
+            };
+
+            emulator.rewind(step);
+            if let Some(id) = self.breakpoint(emulator) {
+                break Stop::Breakpoint(id);
+            }
+        };
+        self.rewatch(emulator);
+
+        Ok(stop)
+    }
+
+    /// Return the last step before the current one that started at a
+    /// breakpoint, whose condition held then.
+    ///
+    /// The registers of each step are found by undoing the writes of the
+    /// steps after it, without running anything.
+    fn previous_breakpoint(&self, emulator: &Emulator) -> Option<u64> {
+        let history = emulator.history()?;
+        let mut registers = emulator.registers;
+
+        (history.first()..history.step()).rev().find(|&step| {
+            let logged = history.get(step).unwrap();
+
+            for write in logged.writes.iter().rev() {
+                if let Location::Register(register) = write.location {
+                    registers[register] = write.old;
+                }
+            }
+
+            self.breakpoints.iter().any(|breakpoint| {
+                breakpoint.address == logged.pc
+                    && breakpoint
+                        .condition
+                        .is_none_or(|condition| condition.holds(&registers))
+            })
+        })
+    }
+
+    /// Remember the current value of the watched words, after going back.
+    fn rewatch(&mut self, emulator: &mut Emulator) {
+        for watchpoint in self.watchpoints.iter_mut() {
+            if let Ok(value) = Self::peek(emulator, watchpoint.address) {
+                watchpoint.value = value;
+            }
+        }
+    }
+
+    /// Return the last write to `location` still recorded, if any.
+    pub fn last_write(
+        emulator: &Emulator,
+        location: Location,
+    ) -> Result<Option<LastWrite>, String> {
+        let history = emulator.history().ok_or(NOT_RECORDING)?;
+
+        Ok(history.last_write(location))
+    }
+
+    fn run(
+        &mut self,
+        emulator: &mut Emulator,
+        mut count: Option<usize>,
+        finish: bool,
+        leave: bool,
+    ) -> Result<Stop, Exception> {
+        let mut depth = 0;
+        let mut first = leave;
+
+        loop {
+            if emulator.finished() {
+                return Ok(Stop::End);
+            }
+
+            if emulator.stalled() {
+                return Ok(Stop::Stalled);
+            }
+
+            if emulator.out_of_cycles() {
+                return Ok(Stop::CycleLimit);
+            }
+
+            if count == Some(0) {
+                return Ok(Stop::Step);
+            }
+
+            // Leave the breakpoint the program stopped at
+            if !first {
+                if let Some(id) = self.breakpoint(emulator) {
+                    return Ok(Stop::Breakpoint(id));
+                }
+            }
+
+            let jump = if finish { jump(emulator) } else { None };
+
+            emulator.execute_next_line()?;
+            first = false;
+
+            if let Some(count) = &mut count {
+                *count -= 1;
+            }
+
+            if let Some(stop) = self.watchpoint(emulator) {
+                return Ok(stop);
+            }
+
+            match jump {
+                Some((Jump::Call, target)) if emulator.pc == target => depth += 1,
+                Some((Jump::Return, target)) if emulator.pc == target => {
+                    if depth == 0 {
+                        return Ok(Stop::Return);
+                    }
+                    depth -= 1;
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Return the breakpoint reached before the next instruction, if any.
+    fn breakpoint(&self, emulator: &Emulator) -> Option<usize> {
+        if emulator.waiting() {
+            return None;
+        }
+
+        self.breakpoints
+            .iter()
+            .filter(|breakpoint| breakpoint.address == emulator.pc)
+            .find(|breakpoint| {
+                breakpoint
+                    .condition
+                    .is_none_or(|condition| condition.holds(&emulator.registers))
+            })
+            .map(|breakpoint| breakpoint.id)
+    }
+
+    /// Return the first watched word that changed, remembering its new value.
+    fn watchpoint(&mut self, emulator: &mut Emulator) -> Option<Stop> {
+        for watchpoint in self.watchpoints.iter_mut() {
+            let Ok(value) = Self::peek(emulator, watchpoint.address) else {
+                continue;
+            };
+
+            if value != watchpoint.value {
+                let old = std::mem::replace(&mut watchpoint.value, value);
+
+                return Some(Stop::Watchpoint {
+                    id: watchpoint.id,
+                    address: watchpoint.address,
+                    old,
+                    new: value,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Read the word at `address`, as the program would but without latching
+    /// the cycle counter.
+    pub fn peek(emulator: &mut Emulator, address: u16) -> Result<u16, BusFault> {
+        emulator.memory.peek(address as usize)
+    }
+
+    /// Write `value` to `target`, a register or a word of memory.
+    ///
+    /// Unlike a store of the program, writing ROM is allowed to patch code.
+    pub fn set(emulator: &mut Emulator, target: Target, value: u16) -> Result<(), String> {
+        match target {
+            Target::Register(0) => return Err("r0 is always 0".to_string()),
+            Target::Register(register) => emulator.registers_mut()[register] = value,
+            Target::Pc => emulator.set_pc(value),
+            Target::Memory(address) => {
+                let address = address as usize;
+                let memory = emulator.memory_mut();
+
+                let written = if ROM.contains(&address) {
+                    memory.load(address, &[value])
+                } else {
+                    memory.write(address, value)
+                };
+
+                written.map_err(|fault| fault.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Dump `count` words from `address`, 8 per line, unmapped words as `----`.
+    ///
+    /// The dump stops at the end of the address space.
+    pub fn examine(emulator: &mut Emulator, address: u16, count: usize) -> String {
+        let mut dump = String::new();
+        let end = address as usize + count.min(ADDRESS_SPACE - address as usize);
+
+        for (line, start) in (address as usize..end).step_by(8).enumerate() {
+            if line > 0 {
+                dump.push('\n');
+            }
+            dump.push_str(&format!("0x{start:04X}:"));
+
+            for address in start..(start + 8).min(end) {
+                match emulator.memory.peek(address) {
+                    Ok(word) => dump.push_str(&format!(" {word:04X}")),
+                    Err(_) => dump.push_str(" ----"),
+                }
+            }
+        }
+
+        dump
+    }
+
+    /// Disassemble `count` instructions from `start`, around `pc` without it.
+    ///
+    /// Each line shows the address with its label, `=>` marks `pc` and `*` a
+    /// breakpoint. At most the whole address space is disassembled, wrapping
+    /// around its end.
+    pub fn disassemble(&self, emulator: &mut Emulator, start: Option<u16>, count: usize) -> String {
+        let pc = emulator.pc;
+        let start = start.unwrap_or(pc.saturating_sub(CONTEXT * INSTRUCTION_SIZE));
+        let count = count.min(ADDRESS_SPACE / INSTRUCTION_SIZE as usize);
+
+        (0..count as u16)
+            .map(|index| start.wrapping_add(index.wrapping_mul(INSTRUCTION_SIZE)))
+            .map(|address| {
+                let marker = if address == pc { "=>" } else { "  " };
+                let breakpoint = self.breakpoints.iter().any(|b| b.address == address);
+
+                format!(
+                    "{marker}{} {:<16} {}",
+                    if breakpoint { '*' } else { ' ' },
+                    self.location(address),
+                    self.instruction_at(emulator, address)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Disassemble the instruction at `address`.
+    pub fn instruction_at(&self, emulator: &mut Emulator, address: u16) -> String {
+        let words = emulator
+            .memory
+            .peek(address as usize)
+            .and_then(|low| Ok((low, emulator.memory.peek(address as usize + 1)?)));
+
+        let Ok((low, high)) = words else {
+            return "(unmapped)".to_string();
+        };
+
+        let word = u32::from(high) << 16 | u32::from(low);
+
+        if OpCode::decode(word as u8).is_none() {
+            return format!("(illegal 0x{word:08X})");
+        }
+
+        self.instruction(&decode_instruction(word))
+    }
+
+    /// Disassemble `instruction` to the syntax of the assembler, its target
+    /// written as a label when there is one at that address.
+    pub fn instruction(&self, instruction: &Instruction) -> String {
+        let opcode = instruction.get_opcode();
+        let name = opcode.to_string().to_lowercase();
+        let link = Register::N as u32;
+
+        match instruction.instruction {
+            InstructionEnum::RInstruction(i) => {
+                format!("{name} {}, {}, {}", i.get_rd(), i.get_rs1(), i.get_rs2())
+            }
+            InstructionEnum::IInstruction(i) => match opcode {
+                OpCode::RETI | OpCode::HALT | OpCode::WFI => name,
+                OpCode::PUSH => format!("push {}", i.get_rs1()),
+                OpCode::POP => format!("pop {}", i.get_rd()),
+                OpCode::JAL if i.get_rd() == link && i.get_rs1() == 0 => {
+                    format!("call {}", self.target(i.get_immediate() as u16))
+                }
+                OpCode::JAL if i.get_rd() == 0 && i.get_rs1() == link && i.get_immediate() == 0 => {
+                    "ret".to_string()
+                }
+                _ => format!(
+                    "{name} {}, {}, {}",
+                    i.get_rd(),
+                    i.get_rs1(),
+                    i.get_immediate()
+                ),
+            },
+            InstructionEnum::BInstruction(i) => {
+                let target = (i.get_upper() << 4 | i.get_lower()) as u16;
+
+                format!(
+                    "{name} {}, {}, {}",
+                    i.get_rs1(),
+                    i.get_rs2(),
+                    self.target(target)
+                )
+            }
+        }
+    }
+
+    /// Write `address` as a label when one is defined there.
+    fn target(&self, address: u16) -> String {
+        let mut names: Vec<_> = self
+            .labels
+            .iter()
+            .filter(|(_, &start)| start == address)
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        match names.first() {
+            Some(name) => name.to_string(),
+            None => address.to_string(),
+        }
+    }
+
+    /// Parse a command, see the [module](self) for the syntax.
+    pub fn parse(&self, line: &str) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return Err("expected a command".to_string());
+        };
+        let arguments: Vec<&str> = words.collect();
+
+        let count = |index: usize, default: usize| -> Result<usize, String> {
+            match arguments.get(index) {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| format!("expected a count, got `{count}`")),
+                None => Ok(default),
+            }
+        };
+
+        let command = match name {
+            "break" | "b" => {
+                let location = self.value(argument(&arguments, 0, "a location")?)?;
+
+                let condition = match arguments.get(1) {
+                    Some(&"if") => Some(self.condition(&arguments[2..].join(" "))?),
+                    Some(word) => return Err(format!("expected `if`, got `{word}`")),
+                    None => None,
+                };
+
+                Command::Break(location, condition)
+            }
+            "watch" | "w" => Command::Watch(self.value(argument(&arguments, 0, "an address")?)?),
+            "delete" | "d" => {
+                let id = argument(&arguments, 0, "an id")?;
+                Command::Delete(
+                    id.parse()
+                        .map_err(|_| format!("expected an id, got `{id}`"))?,
+                )
+            }
+            "info" | "i" => Command::Info,
+            "continue" | "c" => Command::Continue,
+            "step" | "s" => Command::Step(count(0, 1)?),
+            "next" | "n" => Command::Next,
+            "finish" | "f" => Command::Finish,
+            "reverse-step" | "rs" => Command::ReverseStep(count(0, 1)?),
+            "reverse-continue" | "rc" => Command::ReverseContinue,
+            "who" => {
+                let location = argument(&arguments, 0, "a register or an address")?;
+                match register(location) {
+                    Some(register) => Command::LastWrite(Location::Register(register)),
+                    None if location == "pc" => {
+                        return Err("pc is written by every step".to_string())
+                    }
+                    None => Command::LastWrite(Location::Memory(self.value(location)?)),
+                }
+            }
+            "print" | "p" => Command::Print,
+            "set" => {
+                let target = argument(&arguments, 0, "a register or an address")?;
+                let target = match register(target) {
+                    Some(register) => Target::Register(register),
+                    None if target == "pc" => Target::Pc,
+                    None => Target::Memory(self.value(target)?),
+                };
+
+                Command::Set(target, self.value(argument(&arguments, 1, "a value")?)?)
+            }
+            "x" => Command::Examine(
+                self.value(argument(&arguments, 0, "an address")?)?,
+                count(1, 16)?,
+            ),
+            "disassemble" | "dis" => match arguments.first() {
+                Some(location) => Command::Disassemble(Some(self.value(location)?), count(1, 8)?),
+                None => Command::Disassemble(None, 2 * CONTEXT as usize + 1),
+            },
+            "history" => Command::History,
+            "help" | "h" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            _ => return Err(format!("unknown command `{name}`")),
+        };
+
+        Ok(command)
+    }
+
+    /// Parse a value: a decimal or `0x` hexadecimal number, or a label.
+    pub fn value(&self, text: &str) -> Result<u16, String> {
+        let value = if text.starts_with('@') {
+            self.label(text)
+        } else if let Some(hex) = text.strip_prefix("0x") {
+            u16::from_str_radix(hex, 16).ok()
+        } else if let Some(negative) = text.strip_prefix('-') {
+            negative.parse::<u16>().ok().map(u16::wrapping_neg)
+        } else {
+            text.parse().ok()
+        };
+
+        value.ok_or_else(|| format!("expected a value, got `{text}`"))
+    }
+
+    /// Parse a condition, `<register> <comparison> <value>`.
+    pub fn condition(&self, text: &str) -> Result<Condition, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let [register_name, operator, value] = words[..] else {
+            return Err("expected a condition such as `r1 == 5`".to_string());
+        };
+
+        let register =
+            register(register_name).ok_or_else(|| format!("unknown register `{register_name}`"))?;
+
+        let comparison = Comparison::OPERATORS
+            .iter()
+            .find(|(name, _)| *name == operator)
+            .map(|(_, comparison)| *comparison)
+            .ok_or_else(|| format!("unknown comparison `{operator}`"))?;
+
+        Ok(Condition {
+            register,
+            comparison,
+            value: self.value(value)?,
+        })
+    }
+}
+
+/// Return the argument at `index`, or an error naming the `expected` one.
+fn argument<'a>(arguments: &[&'a str], index: usize, expected: &str) -> Result<&'a str, String> {
+    arguments
+        .get(index)
+        .copied()
+        .ok_or_else(|| format!("expected {expected}"))
+}
+
+/// Parse a register, `r0` to `r15` or `$0` to `$15`.
+fn register(name: &str) -> Option<usize> {
+    let number = name.strip_prefix('r').or_else(|| name.strip_prefix('$'))?;
+
+    number.parse().ok().filter(|register| *register < 16)
+}
+
+/// Return the jump the next instruction makes when it is a call or a return,
+/// with the address it jumps to.
+fn jump(emulator: &mut Emulator) -> Option<(Jump, u16)> {
+    if emulator.waiting() {
+        return None;
+    }
+
+    let InstructionEnum::IInstruction(instruction) = emulator.decode(emulator.pc).ok()?.instruction
+    else {
+        return None;
+    };
+
+    if instruction.get_opcode() != OpCode::JAL {
+        return None;
+    }
+
+    let link = Register::N as u32;
+    let base = emulator.registers[instruction.get_rs1() as usize];
+    let target = base.wrapping_add(instruction.get_immediate() as u16);
+
+    if instruction.get_rd() == link {
+        Some((Jump::Call, target))
+    } else if instruction.get_rs1() == link {
+        Some((Jump::Return, target))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use blas::ASM;
+    use blex::Lexer;
+
+    /// Assemble `source` and load it, with a debugger knowing its labels.
+    fn load(source: &str) -> (Emulator, Debugger) {
+        let lexer = Lexer::new(source);
+        let mut asm = ASM::new(source, &lexer);
+
+        let program: Vec<Instruction> = asm
+            .by_ref()
+            .map(|instruction| instruction.expect("valid source").into())
+            .collect();
+
+        let mut emulator = Emulator::new();
+        emulator.load_program(program).unwrap();
+
+        (emulator, Debugger::new(asm.labels().clone()))
+    }
+
+    const COUNT: &str = "addi 1, 0, 0\n@Loop\naddi 1, 1, 1\nbne 1, 2, @Loop\naddi 3, 0, 7\n";
+
+    #[test]
+    fn step_and_breakpoint() {
+        let (mut emulator, mut debugger) = load(COUNT);
+        emulator.registers_mut()[2] = 3;
+
+        assert_eq!(debugger.step(&mut emulator, 2), Ok(Stop::Step));
+        assert_eq!(emulator.pc(), 4);
+
+        let id = debugger.add_breakpoint(debugger.label("Loop").unwrap(), None);
+
+        // Continuing from the breakpoint leaves it first
+        for count in 2..=3 {
+            assert_eq!(debugger.resume(&mut emulator), Ok(Stop::Breakpoint(id)));
+            assert_eq!(emulator.registers_mut()[1], count - 1);
+        }
+
+        assert!(debugger.delete(id));
+        assert!(!debugger.delete(id));
+        assert_eq!(debugger.resume(&mut emulator), Ok(Stop::End));
+        assert_eq!(emulator.registers_mut()[3], 7);
+    }
+
+    #[test]
+    fn slice_stops_at_breakpoint_at_pc() {
+        let (mut emulator, mut debugger) = load(COUNT);
+        emulator.registers_mut()[2] = 3;
+
+        let id = debugger.add_breakpoint(2, None);
+        assert_eq!(debugger.step(&mut emulator, 1), Ok(Stop::Step));
+
+        assert_eq!(
+            debugger.run_slice(&mut emulator, 10),
+            Ok(Stop::Breakpoint(id))
+        );
+        assert_eq!(emulator.pc(), 2);
+        assert_eq!(emulator.registers_mut()[1], 0);
+
+        assert!(debugger.remove_breakpoint(2));
+        assert_eq!(debugger.run_slice(&mut emulator, 10), Ok(Stop::End));
+    }
+
+    #[test]
+    fn cycle_limit() {
+        // Without r2 set, the loop runs 65536 times
+        let (mut emulator, mut debugger) = load(COUNT);
+        emulator.set_cycle_limit(Some(11));
+
+        assert_eq!(debugger.resume(&mut emulator), Ok(Stop::CycleLimit));
+        assert_eq!(emulator.cycles(), 11);
+        assert_eq!(emulator.registers_mut()[1], 5);
+        assert_eq!(debugger.step(&mut emulator, 1), Ok(Stop::CycleLimit));
+
+        emulator.set_cycle_limit(None);
+        assert_eq!(debugger.step(&mut emulator, 1), Ok(Stop::Step));
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        let (mut emulator, mut debugger) = load(COUNT);
+        emulator.registers_mut()[2] = 10;
+
+        let command = debugger.parse("break @Loop if r1 == 5").unwrap();
+        let Command::Break(address, condition) = command else {
+            panic!("expected a breakpoint, got {command:?}");
+        };
+        let id = debugger.add_breakpoint(address, condition);
+
+        assert_eq!(debugger.resume(&mut emulator), Ok(Stop::Breakpoint(id)));
+        assert_eq!(emulator.registers_mut()[1], 5);
+        assert_eq!(debugger.resume(&mut emulator), Ok(Stop::End));
+    }
+
+    #[test]
+    fn watchpoint() {
+        let source =
+            "addi 1, 0, 16384\naddi 2, 0, 5\nstr 1, 2, 3\nstr 1, 2, 3\naddi 2, 0, 6\nstr 1, 2, 3\n";
+        let (mut emulator, mut debugger) = load(source);
+
+        let id = debugger.add_watchpoint(&mut emulator, 0x4003).unwrap();
+
+        let stop = Stop::Watchpoint {
+            id,
+            address: 0x4003,
+            old: 0,
+            new: 5,
+        };
+        assert_eq!(debugger.resume(&mut emulator), Ok(stop));
+        assert_eq!(emulator.pc(), 6);
+
+        // Writing the same value again is no change
+        let stop = Stop::Watchpoint {
+            id,
+            address: 0x4003,
+            old: 5,
+            new: 6,
+        };
+        assert_eq!(debugger.resume(&mut emulator), Ok(stop));
+        assert_eq!(emulator.pc(), 12);
+    }
+
+    #[test]
+    fn finish_recursive_call() {
+        let source = include_str!("../../demos/blasm/fibo_recursive.blasm");
+        let (mut emulator, mut debugger) = load(source);
+
+        // Stop in the second call, fibo(n - 1), then finish it
+        let fibo = debugger.label("Fibo").unwrap();
+        let id = debugger.add_breakpoint(fibo, None);
+        assert_eq!(debugger.resume(&mut emulator), Ok(Stop::Breakpoint(id)));
+        assert_eq!(debugger.resume(&mut emulator), Ok(Stop::Breakpoint(id)));
+        assert!(debugger.delete(id));
+
+        let n = emulator.registers_mut()[1];
+        assert_eq!(debugger.finish(&mut emulator), Ok(Stop::Return));
+
+        let fibo = |n: u16| (0..n).fold((0, 1), |(a, b), _| (b, a + b)).0;
+        assert_eq!(emulator.registers_mut()[1], fibo(n));
+    }
+
+    #[test]
+    fn next_runs_calls() {
+        let source = include_str!("../../demos/blasm/fibo_recursive.blasm");
+        let (mut emulator, mut debugger) = load(source);
+
+        // Stop at the first call, then run it as a single instruction
+        let fibo = debugger.label("Fibo").unwrap();
+        while jump(&mut emulator).is_none_or(|(_, target)| target != fibo) {
+            assert_eq!(debugger.next(&mut emulator), Ok(Stop::Step));
+        }
+
+        let call = emulator.pc;
+        assert_eq!(debugger.next(&mut emulator), Ok(Stop::Step));
+        assert_eq!(emulator.pc, call + INSTRUCTION_SIZE);
+
+        // A breakpoint in the subroutine still stops it
+        Debugger::set(&mut emulator, Target::Pc, call).unwrap();
+        let id = debugger.add_breakpoint(fibo, None);
+        assert_eq!(debugger.next(&mut emulator), Ok(Stop::Breakpoint(id)));
+    }
+
+    #[test]
+    fn reverse_execution() {
+        let (mut emulator, mut debugger) = load(COUNT);
+        emulator.registers_mut()[2] = 5;
+
+        assert_eq!(
+            debugger.reverse_step(&mut emulator, 1),
+            Err(NOT_RECORDING.to_string())
+        );
+        emulator.set_recording(true);
+
+        let id = debugger.add_breakpoint(debugger.label("Loop").unwrap(), None);
+        let watch = debugger.add_watchpoint(&mut emulator, 0x4000).unwrap();
+        for _ in 0..4 {
+            assert_eq!(debugger.resume(&mut emulator), Ok(Stop::Breakpoint(id)));
+        }
+        assert_eq!(emulator.registers[1], 3);
+
+        assert_eq!(debugger.reverse_step(&mut emulator, 1), Ok(Stop::Step));
+        assert_eq!(emulator.pc(), 4);
+        assert_eq!(
+            debugger.reverse_resume(&mut emulator),
+            Ok(Stop::Breakpoint(id))
+        );
+        assert_eq!(emulator.registers[1], 2);
+
+        // Going forward again runs the same steps
+        assert_eq!(debugger.resume(&mut emulator), Ok(Stop::Breakpoint(id)));
+        assert_eq!(emulator.registers[1], 3);
+
+        assert_eq!(
+            Debugger::last_write(&emulator, Location::Register(1)),
+            Ok(Some(LastWrite {
+                step: 5,
+                pc: 2,
+                old: 2,
+                new: 3
+            }))
+        );
+        assert_eq!(
+            Debugger::last_write(&emulator, Location::Memory(0x4000)),
+            Ok(None)
+        );
+
+        // A condition is checked against the registers of the time
+        debugger.delete(id);
+        let id = debugger.add_breakpoint(2, debugger.condition("r1 == 1").ok());
+        assert_eq!(
+            debugger.reverse_resume(&mut emulator),
+            Ok(Stop::Breakpoint(id))
+        );
+        assert_eq!(emulator.registers[1], 1);
+        assert_eq!(
+            debugger.reverse_resume(&mut emulator),
+            Ok(Stop::HistoryStart)
+        );
+        assert_eq!((emulator.pc(), emulator.registers[1]), (0, 0));
+        assert_eq!(
+            debugger.reverse_step(&mut emulator, 1),
+            Ok(Stop::HistoryStart)
+        );
+
+        // Changes made meanwhile are kept until going back past them
+        Debugger::set(&mut emulator, Target::Memory(0x4000), 9).unwrap();
+        assert_eq!(
+            debugger.resume(&mut emulator),
+            Ok(Stop::Watchpoint {
+                id: watch,
+                address: 0x4000,
+                old: 0,
+                new: 9
+            })
+        );
+        assert!(debugger.delete(watch));
+        assert_eq!(debugger.resume(&mut emulator), Ok(Stop::Breakpoint(id)));
+        assert_eq!(debugger.reverse_step(&mut emulator, 1), Ok(Stop::Step));
+        assert_eq!(Debugger::peek(&mut emulator, 0x4000), Ok(9));
+        assert_eq!(debugger.parse("rs 3"), Ok(Command::ReverseStep(3)));
+        assert_eq!(
+            debugger.parse("reverse-continue"),
+            Ok(Command::ReverseContinue)
+        );
+        assert_eq!(
+            debugger.parse("who r4"),
+            Ok(Command::LastWrite(Location::Register(4)))
+        );
+        assert_eq!(
+            debugger.parse("who 0x4000"),
+            Ok(Command::LastWrite(Location::Memory(0x4000)))
+        );
+        assert!(debugger.parse("who pc").is_err());
+    }
+
+    #[test]
+    fn set_and_examine() {
+        let (mut emulator, debugger) = load(COUNT);
+
+        for command in ["set r3 0x2A", "set pc @Loop", "set 0x4001 -1", "set 0 7"] {
+            let Command::Set(target, value) = debugger.parse(command).unwrap() else {
+                panic!("expected `set`");
+            };
+            Debugger::set(&mut emulator, target, value).unwrap();
+        }
+
+        assert_eq!(emulator.registers_mut()[3], 42);
+        assert_eq!(emulator.pc(), 2);
+        assert_eq!(
+            Debugger::examine(&mut emulator, 0x4000, 10),
+            "0x4000: 0000 FFFF 0000 0000 0000 0000 0000 0000\n0x4008: 0000 0000"
+        );
+        assert_eq!(
+            Debugger::examine(&mut emulator, 0xDDFF, 2),
+            "0xDDFF: 0000 ----"
+        );
+
+        // The ROM was patched
+        assert_eq!(Debugger::examine(&mut emulator, 0, 1), "0x0000: 0007");
+
+        // The dump stops at the end of the address space
+        let command = debugger.parse("x 0xFFFE 18446744073709551615").unwrap();
+        assert_eq!(command, Command::Examine(0xFFFE, usize::MAX));
+        assert_eq!(
+            Debugger::examine(&mut emulator, 0xFFFE, usize::MAX),
+            "0xFFFE: ---- ----"
+        );
+
+        // Peeking the cycle counter does not latch it
+        emulator.execute_next_line().unwrap();
+        let hash = emulator.state_hash();
+        assert_eq!(Debugger::peek(&mut emulator, 0xF060), Ok(1));
+        assert_eq!(
+            Debugger::examine(&mut emulator, 0xF060, 4),
+            "0xF060: 0001 0000 0000 0000"
+        );
+        assert_eq!(emulator.state_hash(), hash);
+        assert!(Debugger::set(&mut emulator, Target::Register(0), 1).is_err());
+    }
+
+    #[test]
+    fn disassemble() {
+        let source = "@Sub\nret\n@Start\ncall @Sub\nbne 1, 2, @Start\nstr 1, 2, 3\npush 14\nwfi\n";
+        let (mut emulator, debugger) = load(source);
+        emulator.set_pc(4);
+
+        assert_eq!(
+            debugger.disassemble(&mut emulator, None, 7),
+            [
+                "    0x0000 <@Sub>    ret",
+                "    0x0002 <@Start>  call @Sub",
+                "=>  0x0004 <@Start+2> bne 1, 2, @Start",
+                "    0x0006 <@Start+4> str 1, 2, 3",
+                "    0x0008 <@Start+6> push 14",
+                "    0x000A <@Start+8> wfi",
+                "    0x000C <@Start+10> add 0, 0, 0",
+            ]
+            .join("\n")
+        );
+
+        // A count past the address space wraps around once
+        let Command::Disassemble(start, count) = debugger.parse("dis 0 40000").unwrap() else {
+            panic!("expected `disassemble`");
+        };
+        let listing = debugger.disassemble(&mut emulator, start, count);
+        assert_eq!(listing.lines().count(), ADDRESS_SPACE / 2);
+        assert!(listing.ends_with("0xFFFE <@Start+65532> (unmapped)"));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let debugger = Debugger::default();
+
+        assert_eq!(debugger.parse("step 3"), Ok(Command::Step(3)));
+        assert_eq!(debugger.parse("x 0x4000"), Ok(Command::Examine(0x4000, 16)));
+        assert!(debugger.parse("jump").is_err());
+        assert!(debugger.parse("break @Missing").is_err());
+        assert!(debugger.parse("break 4 if r16 == 1").is_err());
+        assert!(debugger.parse("break 4 if r1 ~ 1").is_err());
+        assert!(debugger.parse("step many").is_err());
+    }
+}
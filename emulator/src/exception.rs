@@ -0,0 +1,71 @@
+use core::fmt;
+
+use crate::interrupt::VECTORS;
+use crate::memory::BusFault;
+
+/// An instruction that could not complete.
+///
+/// The CPU jumps to the handler of the exception if the program installed
+/// one, otherwise execution stops with the exception.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Exception {
+    pc: u16,
+    kind: ExceptionKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExceptionKind {
+    /// The word at `pc` does not encode an instruction.
+    IllegalInstruction(u32),
+    /// A fetch, load or store failed.
+    BusFault(BusFault),
+    /// `pc` is odd, instructions are 2 words long and start at even addresses.
+    Misaligned,
+}
+
+impl From<BusFault> for ExceptionKind {
+    fn from(fault: BusFault) -> Self {
+        ExceptionKind::BusFault(fault)
+    }
+}
+
+impl Exception {
+    pub fn new(pc: u16, kind: ExceptionKind) -> Self {
+        Self { pc, kind }
+    }
+
+    /// Address of the instruction that raised the exception.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn kind(&self) -> ExceptionKind {
+        self.kind
+    }
+
+    /// Address of the word holding the handler of this exception.
+    pub fn vector(&self) -> usize {
+        VECTORS.start
+            + match self.kind {
+                ExceptionKind::IllegalInstruction(_) => 0,
+                ExceptionKind::BusFault(_) => 1,
+                ExceptionKind::Misaligned => 2,
+            }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            ExceptionKind::IllegalInstruction(word) => {
+                write!(f, "illegal instruction 0x{:08X}", word)?
+            }
+            ExceptionKind::BusFault(fault) => write!(f, "{}", fault)?,
+            ExceptionKind::Misaligned => write!(f, "misaligned instruction")?,
+        }
+
+        write!(f, " (pc 0x{:04X})", self.pc)
+    }
+}
+
+impl std::error::Error for Exception {}
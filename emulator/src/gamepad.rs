@@ -0,0 +1,204 @@
+//! Gamepad of the Blask handheld.
+//!
+//! The buttons held are read from the `GAMEPAD` MMIO register at `0xF010`,
+//! one bit per [Button]. The register is read-only. Pressing or releasing a
+//! button raises the [gamepad interrupt](crate::interrupt::Interrupt::Gamepad).
+
+use core::fmt;
+use core::str::FromStr;
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// MMIO register holding the buttons held.
+pub const GAMEPAD: usize = 0xF010;
+
+/// A button, its value is its bit in the `GAMEPAD` register.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right = 0,
+    Left = 1,
+    Up = 2,
+    Down = 3,
+    A = 4,
+    B = 5,
+    Select = 6,
+    Start = 7,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    pub fn bit(&self) -> u16 {
+        1 << *self as u16
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Button::Right => "right",
+            Button::Left => "left",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::A => "a",
+            Button::B => "b",
+            Button::Select => "select",
+            Button::Start => "start",
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    /// Parse a button from its [Button::name], ignoring case.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Button::ALL
+            .into_iter()
+            .find(|button| button.name().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("unknown button `{name}`"))
+    }
+}
+
+/// A set of buttons held at the same time.
+///
+/// Written as the button names joined by `+`, such as `a+right`, or `-` when
+/// no button is held.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Buttons(u16);
+
+impl Buttons {
+    pub const NONE: Buttons = Buttons(0);
+
+    pub fn from_bits(bits: u16) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u16 {
+        self.0
+    }
+
+    pub fn contains(&self, button: Button) -> bool {
+        self.0 & button.bit() != 0
+    }
+
+    pub fn insert(&mut self, button: Button) {
+        self.0 |= button.bit();
+    }
+}
+
+impl FromIterator<Button> for Buttons {
+    fn from_iter<I: IntoIterator<Item = Button>>(iter: I) -> Self {
+        let mut buttons = Buttons::NONE;
+
+        for button in iter {
+            buttons.insert(button);
+        }
+
+        buttons
+    }
+}
+
+impl fmt::Display for Buttons {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = Button::ALL
+            .into_iter()
+            .filter(|button| self.contains(*button))
+            .map(|button| button.name())
+            .collect();
+
+        if names.is_empty() {
+            write!(f, "-")
+        } else {
+            write!(f, "{}", names.join("+"))
+        }
+    }
+}
+
+impl FromStr for Buttons {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text == "-" {
+            return Ok(Buttons::NONE);
+        }
+
+        text.split('+').map(str::parse).collect()
+    }
+}
+
+/// State of the gamepad, as seen by the program.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Gamepad {
+    buttons: Buttons,
+}
+
+impl Gamepad {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.u16(self.buttons.bits());
+    }
+
+    pub fn restore(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.buttons = Buttons::from_bits(reader.u16()?);
+
+        Ok(())
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// Hold exactly `buttons`, return whether any button was pressed or released.
+    pub fn set_buttons(&mut self, buttons: Buttons) -> bool {
+        let changed = self.buttons != buttons;
+        self.buttons = buttons;
+
+        changed
+    }
+
+    /// Read the `GAMEPAD` register.
+    pub fn read(&self) -> u16 {
+        self.buttons.bits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display() {
+        let buttons: Buttons = "A+right".parse().unwrap();
+
+        assert!(buttons.contains(Button::A));
+        assert!(buttons.contains(Button::Right));
+        assert_eq!(buttons.bits(), 0b0001_0001);
+        assert_eq!(buttons.to_string(), "right+a");
+
+        assert_eq!("-".parse(), Ok(Buttons::NONE));
+        assert_eq!(Buttons::NONE.to_string(), "-");
+        assert!("a+turbo".parse::<Buttons>().is_err());
+    }
+
+    #[test]
+    fn changes() {
+        let mut gamepad = Gamepad::new();
+        let start = Buttons::from_iter([Button::Start]);
+
+        assert!(gamepad.set_buttons(start));
+        assert!(!gamepad.set_buttons(start));
+        assert_eq!(gamepad.read(), 0b1000_0000);
+        assert!(gamepad.set_buttons(Buttons::NONE));
+    }
+}
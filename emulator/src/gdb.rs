@@ -0,0 +1,718 @@
+//! GDB remote serial protocol server.
+//!
+//! GDB, or any client of its remote protocol, debugs a program running on
+//! the emulator over TCP, with `target remote localhost:<port>`. The packets
+//! are served by a [Debugger], so breakpoints and watchpoints behave as in the
+//! debugger of `--debug`:
+//!
+//! | Packet                   | Action                                         |
+//! |--------------------------|------------------------------------------------|
+//! | `?`                      | why the program last stopped                   |
+//! | `g`, `G`                 | read or write the 16 registers and `pc`        |
+//! | `p`, `P`                 | read or write a register, `pc` is number 16    |
+//! | `m`, `M`                 | read or write memory, in hexadecimal           |
+//! | `s`, `c`                 | step one instruction, continue                 |
+//! | `Z0`, `Z1`, `z0`, `z1`   | add or remove a breakpoint                     |
+//! | `Z2`, `z2`               | add or remove a write watchpoint               |
+//! | `qXfer:features:read`    | the [target description](TARGET_XML)          |
+//! | `QStartNoAckMode`        | stop acknowledging packets                     |
+//! | `D`, `k`                 | detach or kill, which end the session          |
+//!
+//! Other packets get the empty reply of the unsupported ones. A `Ctrl-C`
+//! from GDB interrupts a `c`.
+//!
+//! GDB addresses bytes while the CPU addresses 16-bit words: an address seen
+//! by GDB is twice the word address, the low byte of each word first. `pc` is
+//! shown the same way, on 32 bits to reach the whole address space, while the
+//! other registers hold their raw 16-bit value.
+
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+use crate::debugger::{Debugger, Stop, Target};
+use crate::{Emulator, Exception, ExceptionKind};
+
+/// Description of the Blask registers, sent to GDB.
+pub const TARGET_XML: &str = include_str!("../gdb/blask.xml");
+
+/// Instructions run by `c` between two checks for an interruption.
+const SLICE: usize = 10_000;
+
+/// Byte sent by GDB to interrupt the program.
+const INTERRUPT: u8 = 0x03;
+
+/// Signals reported to GDB, in its own numbering.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGBUS: u8 = 10;
+
+/// Reply of a packet that failed, `EFAULT` or `EINVAL`.
+const FAULT: &str = "E0E";
+const INVALID: &str = "E16";
+
+/// Serve the first GDB client connecting to `listener`, until it detaches,
+/// kills the program or disconnects.
+pub fn serve(
+    emulator: &mut Emulator,
+    debugger: &mut Debugger,
+    listener: &TcpListener,
+) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+
+    let mut session = Session {
+        emulator,
+        debugger,
+        stream,
+        ack: true,
+        stop: format!("S{SIGTRAP:02x}"),
+        done: false,
+    };
+
+    session.run()
+}
+
+struct Session<'a> {
+    emulator: &'a mut Emulator,
+    debugger: &'a mut Debugger,
+    stream: TcpStream,
+    /// Whether packets are acknowledged, until `QStartNoAckMode`.
+    ack: bool,
+    /// Reply of the last stop, for `?`.
+    stop: String,
+    /// Set by `D` and `k`.
+    done: bool,
+}
+
+impl Session<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while !self.done {
+            let Some(packet) = self.receive()? else {
+                return Ok(());
+            };
+
+            if let Some(reply) = self.handle(&packet)? {
+                self.send(&reply)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Return the next byte from GDB, `None` when it disconnected.
+    fn byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Return the next packet, `None` when GDB disconnected.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // Skip the acknowledgements, and interruptions of a stopped program
+            match self.byte()? {
+                None => return Ok(None),
+                Some(b'$') => (),
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+
+            let valid = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+                == Some(self::checksum(&data));
+
+            if self.ack {
+                self.stream.write_all(if valid { b"+" } else { b"-" })?;
+            }
+
+            if valid || !self.ack {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+        }
+    }
+
+    /// Send `reply`, again until GDB acknowledges it.
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        let packet = format!("${reply}#{:02x}", checksum(reply.as_bytes()));
+
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+
+            if !self.ack {
+                return Ok(());
+            }
+
+            loop {
+                match self.byte()? {
+                    None | Some(b'+') => return Ok(()),
+                    Some(b'-') => break,
+                    Some(_) => continue,
+                }
+            }
+        }
+    }
+
+    /// Answer `packet`, `None` when it takes no reply.
+    fn handle(&mut self, packet: &str) -> io::Result<Option<String>> {
+        let Some(kind) = packet.chars().next() else {
+            return Ok(Some(String::new()));
+        };
+        let arguments = &packet[kind.len_utf8()..];
+
+        let reply = match kind {
+            '?' => self.stop.clone(),
+            'g' => (0..=16).map(|register| self.register(register)).collect(),
+            'G' => self.write_registers(arguments),
+            'p' => match usize::from_str_radix(arguments, 16) {
+                Ok(register) if register <= 16 => self.register(register),
+                _ => INVALID.to_string(),
+            },
+            'P' => self.write_register(arguments),
+            'm' => self.read_memory(arguments),
+            'M' => self.write_memory(arguments),
+            's' | 'c' => {
+                if let Some(address) = hex(arguments) {
+                    self.emulator.set_pc((address / 2) as u16);
+                }
+
+                let stop = if kind == 's' {
+                    Some(self.debugger.step(self.emulator, 1))
+                } else {
+                    self.resume()?
+                };
+
+                self.stop = match stop {
+                    Some(stop) => stop_reply(stop),
+                    None => format!("S{SIGINT:02x}"),
+                };
+                self.stop.clone()
+            }
+            'Z' | 'z' => self.point(kind == 'Z', arguments),
+            'q' => self.query(arguments),
+            'Q' if arguments == "StartNoAckMode" => {
+                self.send("OK")?;
+                self.ack = false;
+                return Ok(None);
+            }
+            'H' => "OK".to_string(),
+            'D' => {
+                self.done = true;
+                "OK".to_string()
+            }
+            'k' => {
+                self.done = true;
+                return Ok(None);
+            }
+            _ => String::new(),
+        };
+
+        Ok(Some(reply))
+    }
+
+    /// Continue until the program stops, `None` when GDB interrupts it.
+    fn resume(&mut self) -> io::Result<Option<Result<Stop, Exception>>> {
+        // Leave the breakpoint at `pc` first
+        let mut stop = self.debugger.step(self.emulator, 1);
+
+        while stop == Ok(Stop::Step) {
+            if self.interrupted()? {
+                return Ok(None);
+            }
+
+            stop = self.debugger.run_slice(self.emulator, SLICE);
+        }
+
+        Ok(Some(stop))
+    }
+
+    /// Indicates if GDB sent an interruption, or disconnected.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        let mut byte = [0];
+
+        self.stream.set_nonblocking(true)?;
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+
+        match read {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(byte[0] == INTERRUPT),
+            Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Return register `register` in hexadecimal, `pc` as a byte address.
+    fn register(&self, register: usize) -> String {
+        match register {
+            16 => encode(&(u32::from(self.emulator.pc()) * 2).to_le_bytes()),
+            register => encode(&self.emulator.registers()[register].to_le_bytes()),
+        }
+    }
+
+    /// Write register `register` from its little-endian `bytes`, 4 for `pc`
+    /// and 2 for the others.
+    fn set_register(&mut self, register: usize, bytes: &[u8]) {
+        let value = bytes
+            .iter()
+            .rev()
+            .fold(0, |value, &byte| (value << 8) | u32::from(byte));
+
+        match register {
+            // Writing `pc` as it is keeps a halted program halted
+            16 if value / 2 != u32::from(self.emulator.pc()) => {
+                self.emulator.set_pc((value / 2) as u16)
+            }
+            16 => (),
+            // The zero register stays 0
+            0 => (),
+            register => self.emulator.registers_mut()[register] = value as u16,
+        }
+    }
+
+    /// Size in bytes of register `register`.
+    fn register_size(register: usize) -> usize {
+        if register == 16 {
+            4
+        } else {
+            2
+        }
+    }
+
+    fn write_registers(&mut self, arguments: &str) -> String {
+        let Some(bytes) = decode(arguments).filter(|bytes| bytes.len() == 16 * 2 + 4) else {
+            return INVALID.to_string();
+        };
+
+        let mut start = 0;
+        for register in 0..=16 {
+            let end = start + Self::register_size(register);
+            self.set_register(register, &bytes[start..end]);
+            start = end;
+        }
+
+        "OK".to_string()
+    }
+
+    fn write_register(&mut self, arguments: &str) -> String {
+        let Some((register, value)) = arguments.split_once('=') else {
+            return INVALID.to_string();
+        };
+
+        let register = usize::from_str_radix(register, 16).ok();
+        let value = decode(value);
+
+        match (register, value) {
+            (Some(register), Some(value))
+                if register <= 16 && value.len() == Self::register_size(register) =>
+            {
+                self.set_register(register, &value);
+                "OK".to_string()
+            }
+            _ => INVALID.to_string(),
+        }
+    }
+
+    /// Read the byte at `address`, as GDB addresses them.
+    fn read_byte(&mut self, address: usize) -> Option<u8> {
+        let word = u16::try_from(address / 2).ok()?;
+        let bytes = Debugger::peek(self.emulator, word).ok()?.to_le_bytes();
+
+        Some(bytes[address % 2])
+    }
+
+    /// Answer `m<address>,<length>`, with the bytes up to the first unmapped one.
+    fn read_memory(&mut self, arguments: &str) -> String {
+        let Some((address, length)) = range(arguments) else {
+            return INVALID.to_string();
+        };
+
+        let bytes: Vec<u8> = (address..address + length)
+            .map_while(|address| self.read_byte(address))
+            .collect();
+
+        if bytes.is_empty() && length > 0 {
+            return FAULT.to_string();
+        }
+
+        encode(&bytes)
+    }
+
+    /// Answer `M<address>,<length>:<bytes>`, writing whole words at once.
+    fn write_memory(&mut self, arguments: &str) -> String {
+        let Some((range, data)) = arguments.split_once(':') else {
+            return INVALID.to_string();
+        };
+        let (Some((start, length)), Some(bytes)) = (self::range(range), decode(data)) else {
+            return INVALID.to_string();
+        };
+        if bytes.len() != length {
+            return INVALID.to_string();
+        }
+
+        let byte = |address: usize| {
+            (start..start + length)
+                .contains(&address)
+                .then(|| bytes[address - start])
+        };
+
+        let mut address = start & !1;
+        while address < start + length {
+            let (low, high) = (byte(address), byte(address + 1));
+
+            let value = match (low, high) {
+                (Some(low), Some(high)) => [low, high],
+                _ => match (self.read_byte(address), self.read_byte(address + 1)) {
+                    (Some(old_low), Some(old_high)) => {
+                        [low.unwrap_or(old_low), high.unwrap_or(old_high)]
+                    }
+                    _ => return FAULT.to_string(),
+                },
+            };
+
+            let Ok(word) = u16::try_from(address / 2) else {
+                return FAULT.to_string();
+            };
+            if Debugger::set(
+                self.emulator,
+                Target::Memory(word),
+                u16::from_le_bytes(value),
+            )
+            .is_err()
+            {
+                return FAULT.to_string();
+            }
+
+            address += 2;
+        }
+
+        "OK".to_string()
+    }
+
+    /// Answer `Z<type>,<address>,<kind>` or `z...`, adding or removing a point.
+    fn point(&mut self, add: bool, arguments: &str) -> String {
+        let mut fields = arguments.split(',');
+        let (Some(kind), Some(address), Some(length)) = (
+            fields.next(),
+            fields.next().and_then(hex),
+            fields.next().and_then(hex),
+        ) else {
+            return INVALID.to_string();
+        };
+
+        let Ok(word) = u16::try_from(address / 2) else {
+            return FAULT.to_string();
+        };
+
+        match kind {
+            // Software and hardware breakpoints are alike here
+            "0" | "1" => {
+                if add {
+                    self.debugger.add_breakpoint(word, None);
+                } else {
+                    self.debugger.remove_breakpoint(word);
+                }
+            }
+            "2" => {
+                let last = (address + length.max(1) - 1) / 2;
+
+                for word in word..=last as u16 {
+                    if !add {
+                        self.debugger.remove_watchpoint(word);
+                    } else if self.debugger.add_watchpoint(self.emulator, word).is_err() {
+                        return FAULT.to_string();
+                    }
+                }
+            }
+            _ => return String::new(),
+        }
+
+        "OK".to_string()
+    }
+
+    fn query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            return "PacketSize=1000;qXfer:features:read+;swbreak+;QStartNoAckMode+".to_string();
+        }
+
+        if query == "Attached" {
+            return "1".to_string();
+        }
+
+        let Some(request) = query.strip_prefix("Xfer:features:read:") else {
+            return String::new();
+        };
+        let Some(("target.xml", range)) = request.split_once(':') else {
+            return FAULT.to_string();
+        };
+        let Some((offset, length)) = self::range(range) else {
+            return INVALID.to_string();
+        };
+
+        // `m` when there is more to read, `l` for the last part
+        let start = offset.min(TARGET_XML.len());
+        let end = (start + length).min(TARGET_XML.len());
+        let more = if end < TARGET_XML.len() { 'm' } else { 'l' };
+
+        format!("{more}{}", &TARGET_XML[start..end])
+    }
+}
+
+/// The reply to GDB when the program stopped with `stop`.
+fn stop_reply(stop: Result<Stop, Exception>) -> String {
+    match stop {
+        Ok(Stop::Breakpoint(_)) => format!("T{SIGTRAP:02x}swbreak:;"),
+        Ok(Stop::Watchpoint { address, .. }) => {
+            format!("T{SIGTRAP:02x}watch:{:x};", address as u32 * 2)
+        }
+        Ok(Stop::End) => "W00".to_string(),
+        Ok(_) => format!("S{SIGTRAP:02x}"),
+        Err(exception) => match exception.kind() {
+            ExceptionKind::IllegalInstruction(_) => format!("S{SIGILL:02x}"),
+            ExceptionKind::BusFault(_) | ExceptionKind::Misaligned => format!("S{SIGBUS:02x}"),
+        },
+    }
+}
+
+/// Sum of the bytes of a packet, modulo 256.
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(text: &str) -> Option<usize> {
+    usize::from_str_radix(text, 16).ok()
+}
+
+/// Parse `<address>,<length>` in hexadecimal.
+fn range(text: &str) -> Option<(usize, usize)> {
+    let (start, length) = text.split_once(',')?;
+
+    Some((hex(start)?, hex(length)?))
+}
+
+/// Write `bytes` in hexadecimal.
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Parse bytes written in hexadecimal.
+fn decode(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conformance::assemble;
+    use std::thread;
+    use std::time::Duration;
+
+    /// A scripted GDB client.
+    struct Client {
+        stream: TcpStream,
+        ack: bool,
+    }
+
+    impl Client {
+        fn read(&mut self) -> u8 {
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        /// Send `packet`, without waiting for a reply.
+        fn write(&mut self, packet: &str) {
+            write!(self.stream, "${packet}#{:02x}", checksum(packet.as_bytes())).unwrap();
+
+            if self.ack {
+                assert_eq!(self.read(), b'+', "{packet} was not acknowledged");
+            }
+        }
+
+        /// Send `packet`, return the reply.
+        fn send(&mut self, packet: &str) -> String {
+            self.write(packet);
+            self.reply()
+        }
+
+        fn reply(&mut self) -> String {
+            assert_eq!(self.read(), b'$');
+
+            let mut reply = Vec::new();
+            loop {
+                match self.read() {
+                    b'#' => break,
+                    byte => reply.push(byte),
+                }
+            }
+
+            let checksum = [self.read(), self.read()];
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16);
+            assert_eq!(checksum, Ok(self::checksum(&reply)));
+
+            if self.ack {
+                self.stream.write_all(b"+").unwrap();
+            }
+
+            String::from_utf8(reply).unwrap()
+        }
+    }
+
+    /// Serve `source` to `script`, run by a client in another thread.
+    fn session(source: &str, script: impl FnOnce(&mut Client) + Send + 'static) -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator.load_program(assemble(source)).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let client = thread::spawn(move || {
+            let mut client = Client {
+                stream: TcpStream::connect(address).unwrap(),
+                ack: true,
+            };
+            script(&mut client);
+        });
+
+        serve(&mut emulator, &mut Debugger::default(), &listener).unwrap();
+        client.join().unwrap();
+
+        emulator
+    }
+
+    const COUNT: &str = "addi 1, 0, 0\n@Loop\naddi 1, 1, 1\nbne 1, 2, @Loop\naddi 3, 0, 7\n";
+
+    #[test]
+    fn registers_and_memory() {
+        let emulator = session(COUNT, |gdb| {
+            assert!(gdb
+                .send("qSupported:swbreak+")
+                .contains("qXfer:features:read+"));
+            assert_eq!(gdb.send("?"), "S05");
+
+            // 16 registers and a 32-bit pc, the stack pointer at 0xC000
+            let registers = gdb.send("g");
+            assert_eq!(registers.len(), 16 * 4 + 8);
+            assert_eq!(&registers[15 * 4..], "00c000000000");
+
+            assert_eq!(gdb.send("P2=3412"), "OK");
+            assert_eq!(gdb.send("p2"), "3412");
+            assert_eq!(gdb.send("P10=04000000"), "OK");
+            assert_eq!(gdb.send("p10"), "04000000");
+            assert_eq!(gdb.send("P10=0400"), "E16");
+
+            // RAM starts at word 0x4000, byte 0x8000
+            assert_eq!(gdb.send("M8001,3:aabbcc"), "OK");
+            assert_eq!(gdb.send("m8000,4"), "00aabbcc");
+            assert_eq!(gdb.send("m1bbfe,4"), "0000");
+            assert_eq!(gdb.send("m1bc00,2"), "E0E");
+
+            assert_eq!(gdb.send("QStartNoAckMode"), "OK");
+            gdb.ack = false;
+
+            let xml = gdb.send("qXfer:features:read:target.xml:0,ffff");
+            assert_eq!(xml, format!("l{TARGET_XML}"));
+            let xml = gdb.send("qXfer:features:read:target.xml:0,10");
+            assert_eq!(xml, format!("m{}", &TARGET_XML[..16]));
+
+            assert_eq!(gdb.send("vMustReplyEmpty"), "");
+            assert_eq!(gdb.send("é1"), "");
+            assert_eq!(gdb.send("D"), "OK");
+        });
+
+        assert_eq!(emulator.registers()[2], 0x1234);
+        assert_eq!(emulator.pc(), 2);
+    }
+
+    #[test]
+    fn breakpoints_and_stepping() {
+        let emulator = session(COUNT, |gdb| {
+            assert_eq!(gdb.send("P2=0300"), "OK");
+
+            // @Loop is at word 2, byte 4
+            assert_eq!(gdb.send("Z0,4,4"), "OK");
+            assert_eq!(gdb.send("c"), "T05swbreak:;");
+            assert_eq!(gdb.send("p10"), "04000000");
+            assert_eq!(gdb.send("c"), "T05swbreak:;");
+            assert_eq!(gdb.send("p1"), "0100");
+
+            assert_eq!(gdb.send("s"), "S05");
+            assert_eq!(gdb.send("p10"), "08000000");
+            assert_eq!(gdb.send("?"), "S05");
+
+            assert_eq!(gdb.send("z0,4,4"), "OK");
+            assert_eq!(gdb.send("c"), "W00");
+            gdb.write("k");
+        });
+
+        assert_eq!(emulator.registers()[3], 7);
+    }
+
+    #[test]
+    fn breakpoint_in_upper_ram() {
+        session(COUNT, |gdb| {
+            // Zeroed RAM runs as `add 0, 0, 0`, from word 0x8000, byte 0x10000
+            assert_eq!(gdb.send("P10=00000100"), "OK");
+            assert_eq!(gdb.send("Z0,10004,4"), "OK");
+            assert_eq!(gdb.send("c"), "T05swbreak:;");
+            assert_eq!(gdb.send("p10"), "04000100");
+            assert!(gdb.send("g").ends_with("04000100"));
+            gdb.write("k");
+        });
+    }
+
+    #[test]
+    fn watchpoint() {
+        let source = "addi 1, 0, 16384\naddi 2, 0, 5\nstr 1, 2, 3\naddi 3, 0, 1\n";
+
+        session(source, |gdb| {
+            // Word 0x4003, byte 0x8006
+            assert_eq!(gdb.send("Z2,8006,2"), "OK");
+            assert_eq!(gdb.send("c"), "T05watch:8006;");
+            assert_eq!(gdb.send("m8006,2"), "0500");
+            assert_eq!(gdb.send("z2,8006,2"), "OK");
+            assert_eq!(gdb.send("c"), "W00");
+            gdb.write("k");
+        });
+    }
+
+    #[test]
+    fn interrupt_and_exception() {
+        let source = "@Loop\nbe 0, 0, @Loop\n";
+
+        session(source, |gdb| {
+            gdb.write("c");
+
+            thread::sleep(Duration::from_millis(50));
+            gdb.stream.write_all(&[INTERRUPT]).unwrap();
+            assert_eq!(gdb.reply(), "S02");
+
+            // Jump to an odd address
+            assert_eq!(gdb.send("P10=03000000"), "OK");
+            assert_eq!(gdb.send("s"), "S0a");
+            gdb.write("k");
+        });
+    }
+}
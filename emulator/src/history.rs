@@ -0,0 +1,245 @@
+//! Execution history, to run a program backwards.
+//!
+//! While it records, the [Emulator](crate::Emulator) logs each step it runs,
+//! an instruction, the entry of a handler or a cycle spent waiting: the `pc`
+//! it started from and the registers and words of memory it wrote, with their
+//! old and new values. Every [SNAPSHOT_INTERVAL] steps, and before the first
+//! step after the host changed the machine, it also takes a
+//! [save state](crate::state) of the whole machine.
+//!
+//! [Emulator::rewind](crate::Emulator::rewind) goes back to an earlier step by
+//! restoring the last snapshot before it and running the steps in between
+//! again, which the CPU does exactly as the first time. The log tells who last
+//! wrote a register or a word, and lets the [debugger](crate::debugger) find
+//! the previous breakpoint without running anything.
+//!
+//! At most [MAX_SNAPSHOTS] snapshots are kept, the steps before the oldest
+//! one are forgotten.
+
+use core::fmt;
+use std::collections::VecDeque;
+
+/// Steps between two snapshots.
+pub const SNAPSHOT_INTERVAL: u64 = 1024;
+
+/// Snapshots kept, going back at least this many times [SNAPSHOT_INTERVAL]
+/// steps unless the host changes the machine in between.
+pub const MAX_SNAPSHOTS: usize = 64;
+
+/// A register or a word of memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Location {
+    Register(usize),
+    Memory(u16),
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Location::Register(register) => write!(f, "r{register}"),
+            Location::Memory(address) => write!(f, "0x{address:04X}"),
+        }
+    }
+}
+
+/// A write of a step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Write {
+    pub location: Location,
+    pub old: u16,
+    pub new: u16,
+}
+
+/// A step run by the CPU, with the writes it made in order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Step {
+    /// `pc` before the step.
+    pub pc: u16,
+    pub writes: Vec<Write>,
+}
+
+/// The last write to a location, see [History::last_write].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LastWrite {
+    /// Number of the step that wrote it.
+    pub step: u64,
+    /// `pc` before that step.
+    pub pc: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    /// Steps run since the history started.
+    step: u64,
+    /// The steps from the oldest snapshot on.
+    log: VecDeque<Step>,
+    /// Save states of the machine by step, in increasing order.
+    snapshots: VecDeque<(u64, Vec<u8>)>,
+    /// The host changed the machine since the last step.
+    dirty: bool,
+}
+
+impl History {
+    /// Start a history from the save state of the machine.
+    pub fn new(state: Vec<u8>) -> Self {
+        Self {
+            snapshots: VecDeque::from([(0, state)]),
+            ..Self::default()
+        }
+    }
+
+    /// Return the number of steps run since the history started.
+    pub fn step(&self) -> u64 {
+        self.step
+    }
+
+    /// Return the first step the machine can go back to.
+    pub fn first(&self) -> u64 {
+        self.snapshots.front().map_or(self.step, |(step, _)| *step)
+    }
+
+    /// Return the step `step`, if it is still logged and already ran.
+    pub fn get(&self, step: u64) -> Option<&Step> {
+        let index = step.checked_sub(self.first())?;
+
+        self.log.get(index as usize)
+    }
+
+    /// Return the last write to `location`, if it is still logged.
+    pub fn last_write(&self, location: Location) -> Option<LastWrite> {
+        (self.first()..self.step).rev().find_map(|step| {
+            let logged = self.get(step)?;
+
+            logged
+                .writes
+                .iter()
+                .rev()
+                .find(|write| write.location == location)
+                .map(|write| LastWrite {
+                    step,
+                    pc: logged.pc,
+                    old: write.old,
+                    new: write.new,
+                })
+        })
+    }
+
+    /// Record that the host changed the machine, which the steps cannot replay.
+    pub fn touch(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Indicates if a snapshot must be taken before the next step.
+    pub fn due(&self) -> bool {
+        let last = self.snapshots.back().map_or(0, |(step, _)| *step);
+
+        self.dirty || self.step - last >= SNAPSHOT_INTERVAL
+    }
+
+    /// Keep `state` as the snapshot of the machine before the next step,
+    /// forgetting the oldest steps when there are too many snapshots.
+    pub fn snapshot(&mut self, state: Vec<u8>) {
+        if self
+            .snapshots
+            .back()
+            .is_some_and(|(step, _)| *step == self.step)
+        {
+            self.snapshots.pop_back();
+        }
+        self.snapshots.push_back((self.step, state));
+        self.dirty = false;
+
+        while self.snapshots.len() > MAX_SNAPSHOTS {
+            self.snapshots.pop_front();
+            let forgotten = self.first() - (self.step - self.log.len() as u64);
+            self.log.drain(..forgotten as usize);
+        }
+    }
+
+    /// Start logging a step from `pc`.
+    pub fn begin(&mut self, pc: u16) {
+        self.log.push_back(Step {
+            pc,
+            writes: Vec::new(),
+        });
+        self.step += 1;
+    }
+
+    /// Log a write of the step running.
+    pub fn write(&mut self, location: Location, old: u16, new: u16) {
+        if let Some(step) = self.log.back_mut() {
+            step.writes.push(Write { location, old, new });
+        }
+    }
+
+    /// Return the last snapshot taken at or before `step`, with its step.
+    pub fn snapshot_before(&self, step: u64) -> Option<(u64, &[u8])> {
+        self.snapshots
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= step)
+            .map(|(start, state)| (*start, state.as_slice()))
+    }
+
+    /// Forget the steps from `step` on, when the machine goes back to it.
+    pub fn truncate(&mut self, step: u64) {
+        self.snapshots.retain(|(start, _)| *start <= step);
+        self.log.truncate((step - self.first()) as usize);
+        self.step = step;
+        self.dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_and_forget() {
+        let mut history = History::new(vec![0]);
+        assert!(!history.due());
+
+        for step in 0..3 * SNAPSHOT_INTERVAL {
+            if history.due() {
+                history.snapshot(vec![(step / SNAPSHOT_INTERVAL) as u8]);
+            }
+            history.begin(step as u16);
+            history.write(Location::Register(1), step as u16, step as u16 + 1);
+        }
+        history.begin(7);
+        history.write(Location::Memory(0x4000), 1, 2);
+
+        assert_eq!(history.step(), 3 * SNAPSHOT_INTERVAL + 1);
+        assert_eq!(
+            history.last_write(Location::Register(1)),
+            Some(LastWrite {
+                step: 3 * SNAPSHOT_INTERVAL - 1,
+                pc: 3 * SNAPSHOT_INTERVAL as u16 - 1,
+                old: 3 * SNAPSHOT_INTERVAL as u16 - 1,
+                new: 3 * SNAPSHOT_INTERVAL as u16,
+            })
+        );
+        assert_eq!(history.last_write(Location::Register(2)), None);
+        assert_eq!(
+            history.snapshot_before(SNAPSHOT_INTERVAL + 5),
+            Some((SNAPSHOT_INTERVAL, &[1][..]))
+        );
+
+        history.truncate(SNAPSHOT_INTERVAL + 5);
+        assert_eq!(history.step(), SNAPSHOT_INTERVAL + 5);
+        assert_eq!(history.get(SNAPSHOT_INTERVAL + 4).unwrap().pc, 1028);
+        assert_eq!(history.get(SNAPSHOT_INTERVAL + 5), None);
+        assert_eq!(history.last_write(Location::Memory(0x4000)), None);
+
+        history.touch();
+        assert!(history.due());
+        for step in 0..MAX_SNAPSHOTS {
+            history.snapshot(vec![]);
+            history.begin(step as u16);
+        }
+        assert_eq!(history.first(), SNAPSHOT_INTERVAL + 5);
+        assert_eq!(history.get(history.first()).unwrap().pc, 0);
+    }
+}
@@ -0,0 +1,210 @@
+//! Scripted and recorded gamepad input.
+//!
+//! An input script lists the frames at which the buttons held change, one per
+//! line, as the frame number and the [Buttons] held from that frame on:
+//!
+//! ```text
+//! # Jump to the right
+//! 30 right
+//! 45 a+right
+//! 50 -
+//! ```
+//!
+//! Blank lines and text after `#` are ignored. A script may end with the
+//! number of frames of the session, as a last line such as `120 end`.
+//!
+//! A recording of the keyboard is written in the same format, session length
+//! included, so it can be replayed without a window. As the emulator runs the
+//! same cycles each frame whatever the host does, replaying a recording from
+//! the same program, or the same [save state](crate::state), reproduces the
+//! session bit for bit. Frames count from the start of the session.
+
+use core::fmt;
+use core::str::FromStr;
+
+use crate::gamepad::Buttons;
+
+/// Buttons held frame by frame.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Script {
+    /// Frames at which the buttons change, in increasing order.
+    changes: Vec<(u64, Buttons)>,
+    /// Frames the session lasted, when it was recorded to the end.
+    frames: Option<u64>,
+}
+
+/// A line of an input script that could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the buttons held at `frame`.
+    pub fn buttons(&self, frame: u64) -> Buttons {
+        self.changes
+            .iter()
+            .take_while(|(start, _)| *start <= frame)
+            .last()
+            .map_or(Buttons::NONE, |(_, buttons)| *buttons)
+    }
+
+    /// Record that `buttons` are held at `frame`, frames must be recorded in order.
+    pub fn record(&mut self, frame: u64, buttons: Buttons) {
+        if self.buttons(frame) != buttons {
+            self.changes.push((frame, buttons));
+        }
+    }
+
+    /// Record that the session ended after `frames` frames.
+    pub fn finish(&mut self, frames: u64) {
+        self.frames = Some(frames);
+    }
+
+    /// Return the number of frames of the session, if the script gives it.
+    pub fn frames(&self) -> Option<u64> {
+        self.frames
+    }
+}
+
+impl FromStr for Script {
+    type Err = ScriptError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut script = Script::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+
+            let error = |message: String| ScriptError {
+                line: index + 1,
+                message,
+            };
+
+            let mut fields = line.split_whitespace();
+
+            let (frame, buttons) = match (fields.next(), fields.next(), fields.next()) {
+                (None, _, _) => continue,
+                _ if script.frames.is_some() => {
+                    return Err(error("the script goes on after `end`".to_string()))
+                }
+                (Some(frame), Some(buttons), None) => (frame, buttons),
+                _ => return Err(error(format!("expected `<frame> <buttons>`, got `{line}`"))),
+            };
+
+            let frame: u64 = frame
+                .parse()
+                .map_err(|_| error(format!("invalid frame `{frame}`")))?;
+
+            if script
+                .changes
+                .last()
+                .is_some_and(|(last, _)| *last >= frame)
+            {
+                return Err(error(format!(
+                    "frame {frame} is not after the previous one"
+                )));
+            }
+
+            match buttons {
+                "end" => script.frames = Some(frame),
+                _ => script
+                    .changes
+                    .push((frame, buttons.parse().map_err(error)?)),
+            }
+        }
+
+        Ok(script)
+    }
+}
+
+impl fmt::Display for Script {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (frame, buttons) in &self.changes {
+            writeln!(f, "{frame} {buttons}")?;
+        }
+
+        if let Some(frames) = self.frames {
+            writeln!(f, "{frames} end")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gamepad::Button;
+
+    #[test]
+    fn buttons_by_frame() {
+        let script: Script = "# jump\n30 right\n45 a+right # jump\n\n50 -\n"
+            .parse()
+            .unwrap();
+
+        assert_eq!(script.buttons(0), Buttons::NONE);
+        assert_eq!(script.buttons(44), Buttons::from_iter([Button::Right]));
+        assert_eq!(
+            script.buttons(49),
+            Buttons::from_iter([Button::A, Button::Right])
+        );
+        assert_eq!(script.buttons(1000), Buttons::NONE);
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let mut recording = Script::new();
+
+        for frame in 0..10 {
+            let buttons = if (3..6).contains(&frame) {
+                Buttons::from_iter([Button::Start])
+            } else {
+                Buttons::NONE
+            };
+
+            recording.record(frame, buttons);
+        }
+
+        recording.finish(10);
+
+        assert_eq!(recording.to_string(), "3 start\n6 -\n10 end\n");
+        assert_eq!(recording.to_string().parse(), Ok(recording.clone()));
+        assert_eq!(recording.frames(), Some(10));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            "10 a\n5 b\n".parse::<Script>().unwrap_err().to_string(),
+            "line 2: frame 5 is not after the previous one"
+        );
+        assert_eq!(
+            "x a\n".parse::<Script>().unwrap_err().to_string(),
+            "line 1: invalid frame `x`"
+        );
+        assert_eq!(
+            "10 end\n12 a\n".parse::<Script>().unwrap_err().to_string(),
+            "line 2: the script goes on after `end`"
+        );
+        assert_eq!(
+            "10 a\n10 end\n".parse::<Script>().unwrap_err().to_string(),
+            "line 2: frame 10 is not after the previous one"
+        );
+        assert!("1 turbo\n".parse::<Script>().is_err());
+        assert!("1\n".parse::<Script>().is_err());
+    }
+}
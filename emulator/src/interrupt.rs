@@ -0,0 +1,205 @@
+//! Interrupt controller of the Blask handheld.
+//!
+//! Peripherals raise interrupt lines, which stay pending until the CPU takes
+//! them. The controller is driven through three MMIO registers:
+//!
+//! | Address  | Register  | Access                                             |
+//! |----------|-----------|----------------------------------------------------|
+//! | `0xF000` | `ENABLE`  | bit `n` set enables line `n`                       |
+//! | `0xF001` | `MASK`    | bit 0 set masks every line                         |
+//! | `0xF002` | `PENDING` | bit `n` set while line `n` waits, write 1 to clear |
+//!
+//! The handlers are found in the vector table at the start of RAM: one word
+//! per exception, then one word per interrupt line, holding the address of
+//! the handler or `0` when there is none.
+
+use core::ops::Range;
+
+use crate::memory::RAM;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// MMIO registers of the controller.
+pub const REGISTERS: Range<usize> = 0xF000..0xF003;
+
+pub const ENABLE: usize = REGISTERS.start;
+pub const MASK: usize = REGISTERS.start + 1;
+pub const PENDING: usize = REGISTERS.start + 2;
+
+/// Vector table, exceptions first then interrupt lines.
+pub const VECTORS: Range<usize> = RAM.start..RAM.start + 16;
+
+/// First vector of the interrupt lines, the ones before are exceptions.
+pub const INTERRUPT_VECTORS: usize = VECTORS.start + 8;
+
+/// Bit of the status word saved on the stack by a handler entry, set if the
+/// lines were masked. The low bits are the flags, see [crate::alu::Flags::bits].
+pub const STATUS_MASKED: u16 = 1 << 15;
+
+/// An interrupt line, the lower the line the higher the priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    /// The frame has been drawn.
+    VBlank = 0,
+    /// The timer expired.
+    Timer = 1,
+    /// A button was pressed or released.
+    Gamepad = 2,
+}
+
+impl Interrupt {
+    pub const ALL: [Interrupt; 3] = [Interrupt::VBlank, Interrupt::Timer, Interrupt::Gamepad];
+
+    pub fn line(&self) -> u16 {
+        *self as u16
+    }
+
+    /// Address of the word holding the handler of this line.
+    pub fn vector(&self) -> usize {
+        INTERRUPT_VECTORS + *self as usize
+    }
+}
+
+/// State of the interrupt lines.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InterruptController {
+    enable: u16,
+    masked: bool,
+    pending: u16,
+}
+
+impl InterruptController {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.u16(self.enable);
+        writer.bool(self.masked);
+        writer.u16(self.pending);
+    }
+
+    pub fn restore(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.enable = reader.u16()?;
+        self.masked = reader.bool()?;
+        self.pending = reader.u16()?;
+
+        Ok(())
+    }
+
+    /// Mark `interrupt` pending, it is taken once enabled and unmasked.
+    pub fn raise(&mut self, interrupt: Interrupt) {
+        self.pending |= 1 << interrupt.line();
+    }
+
+    /// Return the pending line with the highest priority, if the CPU can take it.
+    pub fn next(&self) -> Option<Interrupt> {
+        if self.masked {
+            return None;
+        }
+
+        Interrupt::ALL
+            .into_iter()
+            .find(|interrupt| self.enable & self.pending & (1 << interrupt.line()) != 0)
+    }
+
+    /// Indicates if an enabled line is pending, which wakes the CPU from `WFI`
+    /// even while the lines are masked.
+    pub fn wakes(&self) -> bool {
+        self.enable & self.pending != 0
+    }
+
+    pub fn enabled(&self, interrupt: Interrupt) -> bool {
+        self.enable & (1 << interrupt.line()) != 0
+    }
+
+    /// Clear the pending bit of `interrupt`, when the CPU takes it.
+    pub fn acknowledge(&mut self, interrupt: Interrupt) {
+        self.pending &= !(1 << interrupt.line());
+    }
+
+    /// Indicates if every line is masked, as while a handler runs.
+    pub fn masked(&self) -> bool {
+        self.masked
+    }
+
+    pub fn set_masked(&mut self, masked: bool) {
+        self.masked = masked;
+    }
+
+    /// Read the register at `address`, one of [REGISTERS].
+    pub fn read(&self, address: usize) -> u16 {
+        match address {
+            ENABLE => self.enable,
+            MASK => self.masked as u16,
+            PENDING => self.pending,
+            _ => unreachable!("0x{address:04X} is not an interrupt controller register"),
+        }
+    }
+
+    /// Write the register at `address`, one of [REGISTERS].
+    pub fn write(&mut self, address: usize, value: u16) {
+        match address {
+            ENABLE => self.enable = value,
+            MASK => self.masked = value & 1 != 0,
+            PENDING => self.pending &= !value,
+            _ => unreachable!("0x{address:04X} is not an interrupt controller register"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn priority() {
+        let mut controller = InterruptController::new();
+        controller.write(ENABLE, 0b111);
+
+        controller.raise(Interrupt::Gamepad);
+        controller.raise(Interrupt::Timer);
+        assert_eq!(controller.next(), Some(Interrupt::Timer));
+
+        controller.acknowledge(Interrupt::Timer);
+        assert_eq!(controller.next(), Some(Interrupt::Gamepad));
+    }
+
+    #[test]
+    fn enable_and_mask() {
+        let mut controller = InterruptController::new();
+
+        controller.raise(Interrupt::VBlank);
+        assert_eq!(controller.next(), None);
+
+        controller.write(ENABLE, 1);
+        controller.write(MASK, 1);
+        assert_eq!(controller.next(), None);
+
+        controller.write(MASK, 0);
+        assert_eq!(controller.next(), Some(Interrupt::VBlank));
+    }
+
+    #[test]
+    fn wakes_while_masked() {
+        let mut controller = InterruptController::new();
+        controller.write(MASK, 1);
+
+        controller.raise(Interrupt::Gamepad);
+        assert!(!controller.wakes());
+
+        controller.write(ENABLE, 1 << Interrupt::Gamepad.line());
+        assert!(controller.wakes());
+        assert_eq!(controller.next(), None);
+    }
+
+    #[test]
+    fn pending_is_write_one_to_clear() {
+        let mut controller = InterruptController::new();
+
+        controller.raise(Interrupt::VBlank);
+        controller.raise(Interrupt::Timer);
+        controller.write(PENDING, 0b10);
+
+        assert_eq!(controller.read(PENDING), 0b01);
+    }
+}
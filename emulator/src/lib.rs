@@ -1,14 +1,90 @@
 use asmlib::instruction::*;
-use std::time::Instant;
+use blib::{Register, INSTRUCTION_SIZE};
+pub mod alu;
+pub mod apu;
+#[cfg(test)]
+mod conformance;
+pub mod counter;
+pub mod dap;
+pub mod debugger;
+mod exception;
+pub mod gamepad;
+pub mod gdb;
+pub mod history;
+pub mod input;
+pub mod interrupt;
+pub mod memory;
+pub mod ppu;
+pub mod screen;
+pub mod state;
+pub mod timer;
+pub mod timing;
+pub mod trace;
+pub mod vblank;
 mod video;
+mod warning;
+use alu::Flags;
+pub use exception::{Exception, ExceptionKind};
+use gamepad::Buttons;
+use history::{History, Location};
+use interrupt::{Interrupt, STATUS_MASKED};
+use memory::*;
+use screen::Screenshot;
+use state::{StateError, StateReader, StateWriter};
+use timing::Timing;
+use trace::{Access, Retired};
+pub use video::KeyMap;
 use video::*;
+pub use warning::Warning;
+
+/// Whether the CPU executes instructions, saved as its discriminant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum State {
+    Running = 0,
+    /// Sleeping after `WFI` until an enabled interrupt is pending.
+    Waiting = 1,
+    /// Stopped by `HALT`.
+    Halted = 2,
+}
 
+/// The Blask CPU with its memory.
+///
+/// Code and data share the memory map: the program is an image loaded at an
+/// entry address and each instruction is fetched from memory at `pc`, as two
+/// words with the low half first. Decoded instructions are cached until the
+/// words they were decoded from are written.
+///
+/// Before each instruction, the CPU takes the pending interrupt with the
+/// highest priority, if any. Taking an interrupt or an exception pushes the
+/// return address and the status word, masks interrupts and jumps to the
+/// handler in the vector table, `RETI` pops them back.
+///
+/// Time is counted in cycles, as many per instruction or handler entry as the
+/// [timing model](timing) says, one by default. In a window, the CPU runs a
+/// budget of cycles per frame with [Emulator::run_frame], then the frame is
+/// drawn.
 pub struct Emulator {
     registers: [u16; 16],
-    buffer: [u16; 32 * 32],
-    next_instruction: usize,
-    start_time: Instant,
-    program: Vec<Instruction>,
+    flags: Flags,
+    memory: Memory,
+    pc: u16,
+    entry: u16,
+    end: usize,
+    cache: Box<[Option<Instruction>]>,
+    strict: bool,
+    warnings: Vec<Warning>,
+    timing: Timing,
+    /// Cycles the step executing takes so far.
+    step_cycles: u64,
+    /// Cycles after which the program is stopped, see [Emulator::set_cycle_limit].
+    cycle_limit: Option<u64>,
+    state: State,
+    /// Instructions retired while tracing.
+    trace: Option<Vec<Retired>>,
+    /// The instruction executing while tracing, with its effects so far.
+    retiring: Option<Retired>,
+    /// Steps run while recording, to go back with [Emulator::rewind].
+    history: Option<History>,
     window: Option<Window>,
 }
 
@@ -16,10 +92,21 @@ impl Emulator {
     pub fn new() -> Self {
         Self {
             registers: [0; 16],
-            buffer: [0; 32 * 32],
-            next_instruction: 0,
-            start_time: Instant::now(),
-            program: Vec::new(),
+            flags: Flags::default(),
+            memory: Memory::new(),
+            pc: 0,
+            entry: 0,
+            end: 0,
+            cache: vec![None; ADDRESS_SPACE].into_boxed_slice(),
+            strict: false,
+            warnings: Vec::new(),
+            timing: Timing::new(),
+            step_cycles: 0,
+            cycle_limit: None,
+            state: State::Running,
+            trace: None,
+            retiring: None,
+            history: None,
             window: None,
         }
     }
@@ -32,28 +119,301 @@ impl Emulator {
         self.window.as_ref().unwrap()
     }
 
-    pub fn next_instruction(&self) -> usize {
-        self.next_instruction
+    /// Return the address of the next instruction.
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    /// Restart the program from its entry address.
+    pub fn reset_pc(&mut self) {
+        self.touch();
+        self.pc = self.entry;
+        self.state = State::Running;
+    }
+
+    /// Continue the program from `pc`.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.touch();
+        self.pc = pc;
+        self.state = State::Running;
     }
 
-    pub fn reset_next_instruction(&mut self) {
-        self.next_instruction = 0;
+    /// Indicates if the program has halted or run past its last instruction.
+    ///
+    /// An image ending at the top of the address space ends when `pc` wraps
+    /// around to 0.
+    pub fn finished(&self) -> bool {
+        self.state == State::Halted || self.pc as usize == self.end % ADDRESS_SPACE
     }
 
-    pub fn load_program(&mut self, program: Vec<Instruction>) {
-        self.program = program
+    /// Indicates if the CPU sleeps until an interrupt, after `WFI`.
+    pub fn waiting(&self) -> bool {
+        self.state == State::Waiting
     }
 
-    pub fn program(&self) -> &Vec<Instruction> {
-        &self.program
+    /// Indicates if the CPU waits for an interrupt that only the host can raise.
+    ///
+    /// The timer is the only peripheral ticking on its own, every other line
+    /// is raised from outside, such as at the end of a frame.
+    fn stalled(&self) -> bool {
+        let interrupts = self.memory.interrupts();
+        let timer = interrupts.enabled(Interrupt::Timer) && self.memory.timer().armed();
+
+        self.waiting() && !interrupts.wakes() && !timer
+    }
+
+    /// Load `image` at `entry` and start executing from there.
+    ///
+    /// The program ends when `pc` reaches the end of the image. The stack
+    /// pointer `S` starts at the end of RAM, the stack grows down from there.
+    pub fn load_image(&mut self, entry: u16, image: &[u16]) -> Result<(), BusFault> {
+        self.memory_mut().load(entry as usize, image)?;
+        self.entry = entry;
+        self.end = entry as usize + image.len();
+        self.reset_pc();
+        self.registers[Register::S as usize] = RAM.end as u16;
+
+        Ok(())
+    }
+
+    /// Load `program` at the start of ROM.
+    pub fn load_program(&mut self, program: Vec<Instruction>) -> Result<(), BusFault> {
+        let image: Vec<u16> = program
+            .into_iter()
+            .flat_map(|instruction| {
+                let word = encode_instruction(instruction);
+                [word as u16, (word >> 16) as u16]
+            })
+            .collect();
+
+        self.load_image(ROM.start as u16, &image)
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// Return the memory, writes through it invalidate every decoded instruction.
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        self.touch();
+        self.cache.fill(None);
+        &mut self.memory
+    }
+
+    pub fn registers(&self) -> &[u16; 16] {
+        &self.registers
     }
 
     pub fn registers_mut(&mut self) -> &mut [u16; 16] {
+        self.touch();
         &mut self.registers
     }
 
-    pub fn get_timer(self: &Emulator) -> u128 {
-        self.start_time.elapsed().as_millis() / 30
+    /// In strict mode, writes to the zero register are reported as [Warning]s.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Return the warnings reported since the last call.
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Record the instructions retired, see [trace].
+    pub fn set_tracing(&mut self, tracing: bool) {
+        self.trace = tracing.then(Vec::new);
+    }
+
+    /// Return the instructions retired since the last call, while tracing.
+    pub fn take_trace(&mut self) -> Vec<Retired> {
+        self.trace.as_mut().map(std::mem::take).unwrap_or_default()
+    }
+
+    /// Snapshot the whole machine, see [state].
+    ///
+    /// The registers, the flags, `pc`, the bounds of the program and whether
+    /// the CPU runs, waits or has halted come first, then the memory and the
+    /// peripherals, the cycle counter included.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.words(&self.registers);
+        writer.u16(self.flags.bits());
+        writer.u16(self.pc);
+        writer.u16(self.entry);
+        writer.u32(self.end as u32);
+        writer.u16(self.state as u16);
+        self.memory.save(&mut writer);
+
+        writer.finish()
+    }
+
+    /// Restore the machine from a save state of [Emulator::save_state].
+    ///
+    /// The machine is left untouched if the save state is invalid. The window,
+    /// strict mode, tracing, the timing model and the cycle limit are settings
+    /// of the host and are kept.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        let mut reader = StateReader::new(state)?;
+        let mut registers = [0; 16];
+        reader.words(&mut registers)?;
+        let flags = Flags::from_bits(reader.u16()?);
+        let pc = reader.u16()?;
+        let entry = reader.u16()?;
+        let end = reader.u32()? as usize;
+        let cpu = match reader.u16()? {
+            0 => State::Running,
+            1 => State::Waiting,
+            2 => State::Halted,
+            _ => return Err(StateError::Invalid("CPU state")),
+        };
+        let mut memory = Memory::new();
+        memory.restore(&mut reader)?;
+        reader.finish()?;
+
+        self.registers = registers;
+        self.flags = flags;
+        self.pc = pc;
+        self.entry = entry;
+        self.end = end;
+        self.state = cpu;
+        self.memory = memory;
+        self.cache.fill(None);
+        self.retiring = None;
+        self.touch();
+
+        Ok(())
+    }
+
+    /// Hash the save state of the machine, two machines with the same hash
+    /// almost certainly are in the same state.
+    pub fn state_hash(&self) -> u64 {
+        state::hash(&self.save_state())
+    }
+
+    /// Record the steps run from now on, to go back with [Emulator::rewind],
+    /// see [history].
+    pub fn set_recording(&mut self, recording: bool) {
+        self.history = recording.then(|| History::new(self.save_state()));
+    }
+
+    /// Return the steps run while recording.
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Record that the host changed the machine, for the history.
+    fn touch(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.touch();
+        }
+    }
+
+    /// Go back to the state after `step` steps of the history, forgetting the
+    /// steps after it, return whether that step is still recorded.
+    ///
+    /// The steps since the last snapshot before `step` run again, without
+    /// being traced. Changes made by the host since the last step are lost.
+    pub fn rewind(&mut self, step: u64) -> bool {
+        let Some(mut history) = self.history.take() else {
+            return false;
+        };
+
+        if step < history.first() || step > history.step() {
+            self.history = Some(history);
+            return false;
+        }
+
+        if step < history.step() {
+            let (start, state) = history.snapshot_before(step).unwrap();
+            self.load_state(state)
+                .expect("snapshots are valid save states");
+
+            let (trace, warnings) = (self.trace.take(), self.warnings.len());
+            for _ in start..step {
+                // The exceptions raised are already known.
+                let _ = self.execute_next_line();
+            }
+            self.trace = trace;
+            self.warnings.truncate(warnings);
+
+            history.truncate(step);
+        }
+
+        self.history = Some(history);
+        true
+    }
+
+    /// Write `value` to the register `rd` of the instruction executing.
+    ///
+    /// Register 0 is hard-wired to zero, so writes to it are discarded.
+    fn set_register(&mut self, rd: u32, value: u16) {
+        if rd != 0 {
+            self.write_register(rd as usize, value);
+        } else if self.strict {
+            let pc = self.pc.wrapping_sub(INSTRUCTION_SIZE);
+            self.warnings.push(Warning::ZeroRegisterWrite { pc });
+        }
+    }
+
+    /// Write a register, recording it in the trace.
+    fn write_register(&mut self, register: usize, value: u16) {
+        let old = std::mem::replace(&mut self.registers[register], value);
+
+        if let Some(history) = &mut self.history {
+            history.write(Location::Register(register), old, value);
+        }
+
+        if let Some(retired) = &mut self.retiring {
+            retired.registers.push((register as u8, value));
+        }
+    }
+
+    /// Record a memory access of the instruction executing in the trace.
+    fn record(&mut self, write: bool, address: usize, value: u16) {
+        if let Some(retired) = &mut self.retiring {
+            retired.memory.push(Access {
+                write,
+                address: address as u16,
+                value,
+            });
+        }
+    }
+
+    /// Return the status register, as left by the last arithmetic or logic instruction.
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Return the number of cycles run, as counted by the [counter].
+    pub fn cycles(&self) -> u64 {
+        self.memory.counter().cycles()
+    }
+
+    /// Set the cycles each instruction and handler entry takes.
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
+    pub fn timing(&self) -> &Timing {
+        &self.timing
+    }
+
+    /// Stop the program once `limit` cycles have run, counted from power-on,
+    /// or never with `None`.
+    ///
+    /// The step crossing the limit completes, so the program stops on an
+    /// instruction boundary.
+    pub fn set_cycle_limit(&mut self, limit: Option<u64>) {
+        self.cycle_limit = limit;
+    }
+
+    pub fn cycle_limit(&self) -> Option<u64> {
+        self.cycle_limit
+    }
+
+    /// Indicates if the program ran out of the cycles of [Emulator::set_cycle_limit].
+    pub fn out_of_cycles(&self) -> bool {
+        self.cycle_limit.is_some_and(|limit| self.cycles() >= limit)
     }
 
     pub fn print_all_registers(self: &mut Emulator) {
@@ -61,148 +421,381 @@ impl Emulator {
         for i in 0..14 {
             print!("Register {}: {}\n", i, self.registers[i]);
         }
-        print!("Timer: {}\n", self.get_timer());
+        print!("Flags: {}\n", self.flags);
+        print!("Cycles: {}\n", self.cycles());
         println!("");
     }
 
+    /// Return the audio samples produced since the last call, at [apu::SAMPLE_RATE].
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.memory.apu_mut().take_samples()
+    }
+
+    /// Draw the screen, as the window would show it.
+    pub fn screenshot(&self) -> Screenshot {
+        self.memory.ppu().render(self.memory.vram())
+    }
+
     pub fn render(&mut self) {
+        let screen = self.screenshot();
         let window = self.window.as_mut().unwrap();
 
-        window.update_video_buffer(&self.buffer);
+        window.update_video_buffer(&screen);
+    }
+
+    /// Play `samples` in the window.
+    pub fn play(&mut self, samples: &[i16]) {
+        self.window.as_mut().unwrap().play(samples);
+    }
+
+    /// Compute the address `base + offset` of a `LD` or `STR`, wrapping around
+    /// the address space so that negative offsets reach below `base`.
+    fn address(&self, base: u32, offset: u32) -> usize {
+        self.registers[base as usize].wrapping_add(offset as u16) as usize
+    }
+
+    /// Mark `interrupt` pending, as its peripheral would.
+    pub fn raise(&mut self, interrupt: Interrupt) {
+        self.touch();
+        self.memory.interrupts_mut().raise(interrupt);
+    }
+
+    /// Hold exactly `buttons` on the gamepad, raising its interrupt if they changed.
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        if self.memory.gamepad_mut().set_buttons(buttons) {
+            self.raise(Interrupt::Gamepad);
+        }
+    }
+
+    /// Return the instruction at `pc`, decoding it unless it is cached.
+    pub fn fetch(&mut self) -> Result<Instruction, Exception> {
+        self.decode(self.pc)
+            .map_err(|kind| Exception::new(self.pc, kind))
+    }
+
+    fn decode(&mut self, pc: u16) -> Result<Instruction, ExceptionKind> {
+        let address = pc as usize;
+
+        if !address.is_multiple_of(INSTRUCTION_SIZE as usize) {
+            return Err(ExceptionKind::Misaligned);
+        }
+
+        if let Some(instruction) = self.cache[address] {
+            return Ok(instruction);
+        }
+
+        let low = self.memory.read(address)?;
+        let high = self.memory.read(address + 1)?;
+        let word = u32::from(high) << 16 | u32::from(low);
+
+        if OpCode::decode(word as u8).is_none() {
+            return Err(ExceptionKind::IllegalInstruction(word));
+        }
+
+        let instruction = decode_instruction(word);
+
+        // Peripheral registers may change on their own, only cache plain memory.
+        if ROM.contains(&address) || RAM.contains(&address) {
+            self.cache[address] = Some(instruction);
+        }
+
+        Ok(instruction)
+    }
+
+    /// Write `value` at `address`, dropping the decoded instructions overlapping it.
+    fn store(&mut self, address: usize, value: u16) -> Result<(), BusFault> {
+        let old = match self.history {
+            Some(_) => Some(self.memory.peek(address)?),
+            None => None,
+        };
+
+        self.memory.write(address, value)?;
+        self.record(true, address, value);
+        self.step_cycles += self.timing.wait(address);
+
+        if let (Some(history), Some(old)) = (&mut self.history, old) {
+            history.write(Location::Memory(address as u16), old, value);
+        }
+
+        self.cache[address] = None;
+        if let Some(previous) = address.checked_sub(1) {
+            self.cache[previous] = None;
+        }
+
+        Ok(())
+    }
+
+    /// Read the word at `address` for the instruction executing.
+    fn load(&mut self, address: usize) -> Result<u16, BusFault> {
+        let value = self.memory.read(address)?;
+        self.record(false, address, value);
+        self.step_cycles += self.timing.wait(address);
+
+        Ok(value)
+    }
+
+    /// Push `value` on the stack, `S` points to the last word pushed.
+    fn push(&mut self, value: u16) -> Result<(), BusFault> {
+        let sp = self.registers[Register::S as usize].wrapping_sub(1);
+        self.store(sp as usize, value)?;
+        self.write_register(Register::S as usize, sp);
+
+        Ok(())
+    }
+
+    /// Pop the word on top of the stack.
+    fn pop(&mut self) -> Result<u16, BusFault> {
+        let sp = self.registers[Register::S as usize];
+        let value = self.load(sp as usize)?;
+        self.write_register(Register::S as usize, sp.wrapping_add(1));
+
+        Ok(value)
+    }
+
+    /// Save `pc` and the status word on the stack, mask interrupts and jump to
+    /// the handler at `vector`.
+    ///
+    /// Return `false` without changing anything if there is no handler.
+    fn enter(&mut self, vector: usize, pc: u16) -> Result<bool, BusFault> {
+        let handler = self.memory.read(vector)?;
+
+        if handler == 0 {
+            return Ok(false);
+        }
+
+        let mut status = self.flags.bits();
+        if self.memory.interrupts().masked() {
+            status |= STATUS_MASKED;
+        }
+
+        self.step_cycles += self.timing.entry();
+        self.push(pc)?;
+        self.push(status)?;
+        self.memory.interrupts_mut().set_masked(true);
+        self.pc = handler;
+
+        Ok(true)
+    }
+
+    /// Jump to the handler of `exception`, or return it if there is none.
+    fn exception(&mut self, exception: Exception) -> Result<(), Exception> {
+        self.pc = exception.pc();
+
+        match self.enter(exception.vector(), exception.pc()) {
+            Ok(true) => Ok(()),
+            _ => Err(exception),
+        }
+    }
+
+    /// Take the pending interrupt with the highest priority, if it has a handler.
+    fn interrupt(&mut self) -> Result<bool, Exception> {
+        let Some(interrupt) = self.memory.interrupts().next() else {
+            return Ok(false);
+        };
+
+        match self.enter(interrupt.vector(), self.pc) {
+            Ok(true) => {
+                self.memory.interrupts_mut().acknowledge(interrupt);
+                Ok(true)
+            }
+            Ok(false) => Ok(false),
+            Err(fault) => self
+                .exception(Exception::new(self.pc, fault.into()))
+                .map(|()| true),
+        }
+    }
+
+    /// Execute the instruction at `pc`, or enter the handler of a pending interrupt.
+    ///
+    /// An exception enters its handler. If the program has none, the exception
+    /// is returned and `pc` stays on the instruction that raised it.
+    ///
+    /// The step takes the cycles of the [timing model](timing). While the CPU
+    /// waits for an interrupt, a single cycle passes without executing
+    /// anything. Once halted, nothing happens anymore.
+    pub fn execute_next_line(self: &mut Emulator) -> Result<(), Exception> {
+        if self.history.as_ref().is_some_and(History::due) {
+            let state = self.save_state();
+            self.history.as_mut().unwrap().snapshot(state);
+        }
+        if let Some(history) = &mut self.history {
+            history.begin(self.pc);
+        }
+
+        match self.state {
+            State::Halted => return Ok(()),
+            State::Waiting if !self.memory.interrupts().wakes() => {
+                self.tick();
+                return Ok(());
+            }
+            _ => self.state = State::Running,
+        }
+
+        self.step_cycles = 0;
+
+        if !self.interrupt()? {
+            let pc = self.pc;
+
+            self.execute().or_else(|kind| {
+                self.retiring = None;
+                self.exception(Exception::new(pc, kind))
+            })?;
+        }
+
+        for _ in 0..self.step_cycles {
+            self.tick();
+        }
+
+        let cycles = self.cycles();
+        if let (Some(mut retired), Some(trace)) = (self.retiring.take(), &mut self.trace) {
+            retired.cycle = cycles;
+            trace.push(retired);
+        }
+
+        Ok(())
     }
 
-    pub fn execute_next_line(self: &mut Emulator) {
-        if self.next_instruction >= self.program.len().try_into().unwrap() {
-            panic!("[execute_next_line] try to execute next line when it is end of program");
+    /// Advance the clock by one cycle.
+    fn tick(&mut self) {
+        self.memory.counter_mut().tick();
+        self.memory.apu_mut().tick();
+
+        if self.memory.timer_mut().tick() {
+            self.memory.interrupts_mut().raise(Interrupt::Timer);
         }
+    }
 
-        let instruction = self.program[self.next_instruction].instruction;
-        self.next_instruction += 1;
+    fn execute(&mut self) -> Result<(), ExceptionKind> {
+        let instruction = self.decode(self.pc)?;
+        let word = encode_instruction(instruction);
+        if self.trace.is_some() {
+            self.retiring = Some(Retired::new(self.pc, word));
+        }
+
+        let opcode = instruction.get_opcode();
+        let address = self.pc as usize;
+        self.step_cycles += self.timing.wait(address) + self.timing.wait(address + 1);
+
+        let instruction = instruction.instruction;
+        self.pc = self.pc.wrapping_add(INSTRUCTION_SIZE);
+        let mut taken = false;
 
         match instruction {
-            InstructionEnum::RInstruction(instruction) => match instruction.get_opcode() {
-                OpCode::ADD => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        + self.registers[instruction.get_rs2() as usize]
-                }
-                OpCode::SUB => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        - self.registers[instruction.get_rs2() as usize]
-                }
-                OpCode::OR => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        | self.registers[instruction.get_rs2() as usize]
-                }
-                OpCode::AND => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        & self.registers[instruction.get_rs2() as usize]
-                }
-                OpCode::XOR => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        ^ self.registers[instruction.get_rs2() as usize]
-                }
-                OpCode::SLL => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        << self.registers[instruction.get_rs2() as usize]
-                }
-                OpCode::SRL => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        >> self.registers[instruction.get_rs2() as usize]
-                }
-                _ => panic!(
-                    "[Emulator] unsupported OpCode \"{}\" for RInstruction",
-                    instruction.get_opcode() as u32
-                ),
-            },
+            InstructionEnum::RInstruction(instruction) => {
+                let rs1 = self.registers[instruction.get_rs1() as usize];
+                let rs2 = self.registers[instruction.get_rs2() as usize];
+
+                let Some((result, flags)) = alu::execute(instruction.get_opcode(), rs1, rs2) else {
+                    return Err(ExceptionKind::IllegalInstruction(word));
+                };
+
+                self.set_register(instruction.get_rd(), result);
+                self.flags = flags;
+            }
             InstructionEnum::IInstruction(instruction) => match instruction.get_opcode() {
-                OpCode::ADDI => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        + instruction.get_immediate() as u16
-                }
-                OpCode::SUBI => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        - instruction.get_immediate() as u16
+                OpCode::LD => {
+                    let address = self.address(instruction.get_rs1(), instruction.get_immediate());
+                    let value = self.load(address)?;
+                    self.set_register(instruction.get_rd(), value);
                 }
-                OpCode::ORI => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        | instruction.get_immediate() as u16
+                OpCode::STR => {
+                    let address = self.address(instruction.get_rd(), instruction.get_immediate());
+                    let value = self.registers[instruction.get_rs1() as usize];
+                    self.store(address, value)?;
                 }
-                OpCode::ANDI => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        & instruction.get_immediate() as u16
+                OpCode::PUSH => {
+                    self.push(self.registers[instruction.get_rs1() as usize])?;
                 }
-                OpCode::XORI => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        ^ instruction.get_immediate() as u16
+                OpCode::POP => {
+                    let value = self.pop()?;
+                    self.set_register(instruction.get_rd(), value);
                 }
-                OpCode::SLLI => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        << instruction.get_immediate() as u16
+                OpCode::JAL => {
+                    let base = self.registers[instruction.get_rs1() as usize];
+                    let target = base.wrapping_add(instruction.get_immediate() as u16);
+                    // Register 0 discards the link on purpose, as `ret` does.
+                    if instruction.get_rd() != 0 {
+                        self.set_register(instruction.get_rd(), self.pc);
+                    }
+                    self.pc = target;
                 }
-                OpCode::SRLI => {
-                    self.registers[instruction.get_rd() as usize] = self.registers
-                        [instruction.get_rs1() as usize]
-                        >> instruction.get_immediate() as u16
+                OpCode::HALT => self.state = State::Halted,
+                OpCode::WFI => self.state = State::Waiting,
+                OpCode::RETI => {
+                    let status = self.pop()?;
+                    self.pc = self.pop()?;
+                    self.flags = Flags::from_bits(status);
+                    self.memory
+                        .interrupts_mut()
+                        .set_masked(status & STATUS_MASKED != 0);
                 }
-                OpCode::LD => {
-                    let rs1 = self.registers[instruction.get_rs1() as usize] as usize;
-                    let rd = self.registers[instruction.get_rd() as usize] as usize;
-                    let offset = instruction.get_immediate() as usize;
-                    self.registers[rd] = self.buffer[rs1 + offset];
-                }
-                OpCode::STR => {
+                opcode => {
                     let rs1 = self.registers[instruction.get_rs1() as usize];
-                    let rd = self.registers[instruction.get_rd() as usize] as usize;
-                    let offset = instruction.get_immediate() as usize;
-                    self.buffer[rd + offset] = rs1;
+                    let immediate = instruction.get_immediate() as u16;
+
+                    let Some((result, flags)) = alu::execute(opcode, rs1, immediate) else {
+                        return Err(ExceptionKind::IllegalInstruction(word));
+                    };
+
+                    self.set_register(instruction.get_rd(), result);
+                    self.flags = flags;
                 }
-                _ => panic!(
-                    "[Emulator] unsupported OpCode \"{}\" for IInstruction",
-                    instruction.get_opcode() as u32
-                ),
             },
-            InstructionEnum::BInstruction(instruction) => match instruction.get_opcode() {
-                OpCode::BE => {
-                    if self.registers[instruction.get_rs1() as usize]
-                        == self.registers[instruction.get_rs2() as usize]
-                    {
-                        self.next_instruction =
-                            (instruction.get_upper() << 4 | instruction.get_lower()) as usize;
-                    }
-                }
-                OpCode::BNE => {
-                    if self.registers[instruction.get_rs1() as usize]
-                        != self.registers[instruction.get_rs2() as usize]
-                    {
-                        self.next_instruction =
-                            (instruction.get_upper() << 4 | instruction.get_lower()) as usize;
-                    }
+            InstructionEnum::BInstruction(instruction) => {
+                let rs1 = self.registers[instruction.get_rs1() as usize];
+                let rs2 = self.registers[instruction.get_rs2() as usize];
+
+                taken = match instruction.get_opcode() {
+                    OpCode::BE => rs1 == rs2,
+                    OpCode::BNE => rs1 != rs2,
+                    OpCode::BLT => (rs1 as i16) < (rs2 as i16),
+                    OpCode::BGE => (rs1 as i16) >= (rs2 as i16),
+                    OpCode::BLTU => rs1 < rs2,
+                    OpCode::BGEU => rs1 >= rs2,
+                    _ => return Err(ExceptionKind::IllegalInstruction(word)),
+                };
+
+                if taken {
+                    self.pc = (instruction.get_upper() << 4 | instruction.get_lower()) as u16;
                 }
-                _ => panic!(
-                    "[Emulator] unsupported OpCode \"{}\" for IInstruction",
-                    instruction.get_opcode() as u32
-                ),
-            },
+            }
         }
+
+        self.step_cycles += self.timing.cycles(opcode, taken);
+
+        Ok(())
     }
 
-    pub fn execute_all(self: &mut Emulator) {
-        while self.next_instruction != self.program.len() {
-            self.execute_next_line();
+    /// Execute until the program ends or halts.
+    ///
+    /// Execution also stops when the CPU waits for an interrupt nothing but the
+    /// host can raise, as no frame ever ends here, or when it runs out of
+    /// cycles.
+    pub fn execute_all(self: &mut Emulator) -> Result<(), Exception> {
+        while !self.finished() && !self.stalled() && !self.out_of_cycles() {
+            self.execute_next_line()?;
         }
+
+        Ok(())
+    }
+
+    /// Run one frame: execute `cycles` cycles, then signal the vertical blank.
+    ///
+    /// The program keeps its state from one frame to the next. The frame ends
+    /// early if the program ends, halts or runs out of cycles. A step is never
+    /// split, so the frame may run a few cycles more than `cycles`.
+    pub fn run_frame(&mut self, cycles: u64) -> Result<(), Exception> {
+        let end = self.cycles() + cycles;
+
+        while self.cycles() < end && !self.finished() && !self.out_of_cycles() {
+            self.execute_next_line()?;
+        }
+
+        self.touch();
+        self.memory.vblank_mut().blank();
+        self.raise(Interrupt::VBlank);
+
+        Ok(())
     }
 }
 
@@ -210,6 +803,1030 @@ impl Emulator {
 mod tests {
     use crate::*;
 
+    fn memory_instruction(opcode: OpCode, rd: u32, rs1: u32, offset: u32) -> Instruction {
+        let mut instruction = IInstruction(0);
+        instruction.set_opcode(opcode);
+        instruction.set_rd(rd);
+        instruction.set_rs1(rs1);
+        instruction.set_immediate(offset);
+
+        Instruction {
+            instruction: InstructionEnum::IInstruction(instruction),
+        }
+    }
+
+    fn branch_instruction(opcode: OpCode, rs1: u32, rs2: u32, target: u32) -> Instruction {
+        let mut instruction = BInstruction(0);
+        instruction.set_opcode(opcode);
+        instruction.set_rs1(rs1);
+        instruction.set_rs2(rs2);
+        instruction.set_upper(target >> 4);
+        instruction.set_lower(target & 0xF);
+
+        Instruction {
+            instruction: InstructionEnum::BInstruction(instruction),
+        }
+    }
+
+    fn image(program: &[Instruction]) -> Vec<u16> {
+        program
+            .iter()
+            .flat_map(|&instruction| {
+                let word = encode_instruction(instruction);
+                [word as u16, (word >> 16) as u16]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fetch_from_entry() {
+        let mut emulator: Emulator = Emulator::new();
+        let entry = RAM.start as u16 + 0x100;
+        emulator
+            .load_image(entry, &image(&[memory_instruction(OpCode::ADDI, 1, 0, 5)]))
+            .unwrap();
+
+        assert_eq!(emulator.pc(), entry);
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.pc(), entry + 2);
+        assert_eq!(emulator.registers[1], 5);
+    }
+
+    #[test]
+    fn data_after_code() {
+        let mut emulator: Emulator = Emulator::new();
+        let mut rom = image(&[
+            memory_instruction(OpCode::LD, 1, 0, 4),
+            branch_instruction(OpCode::BE, 0, 0, 5),
+        ]);
+        rom.push(0xBEEF);
+        emulator.load_image(0, &rom).unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[1], 0xBEEF);
+    }
+
+    #[test]
+    fn end_at_the_top_of_the_address_space() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator.load_image(0, &[0, 0]).unwrap();
+        emulator.end = ADDRESS_SPACE;
+        emulator.set_pc(0xFFFE);
+
+        assert!(!emulator.finished());
+        emulator.pc = emulator.pc.wrapping_add(INSTRUCTION_SIZE);
+        assert!(emulator.finished());
+    }
+
+    #[test]
+    fn negative_offset() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator
+            .load_program(vec![
+                memory_instruction(OpCode::LD, 2, 1, 0xFFFF),
+                memory_instruction(OpCode::STR, 1, 2, 0xFFFE),
+            ])
+            .unwrap();
+        emulator.registers_mut()[1] = RAM.start as u16 + 2;
+        emulator.memory_mut().write(RAM.start + 1, 7).unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[2], 7);
+        assert_eq!(emulator.memory_mut().read(RAM.start).unwrap(), 7);
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        let entry = RAM.start as u32;
+        let mut emulator: Emulator = Emulator::new();
+        emulator
+            .load_image(
+                entry as u16,
+                &image(&[
+                    memory_instruction(OpCode::ADDI, 4, 0, 7),
+                    branch_instruction(OpCode::BNE, 5, 0, entry + 10),
+                    // Patch the immediate of the first instruction, already decoded.
+                    memory_instruction(OpCode::STR, 2, 3, 0),
+                    memory_instruction(OpCode::ADDI, 5, 0, 1),
+                    branch_instruction(OpCode::BE, 0, 0, entry),
+                ]),
+            )
+            .unwrap();
+        emulator.registers_mut()[2] = entry as u16 + 1;
+        emulator.registers_mut()[3] = 42;
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[4], 42);
+    }
+
+    #[test]
+    fn flags() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator
+            .load_program(vec![
+                memory_instruction(OpCode::SUBI, 1, 0, 1),
+                memory_instruction(OpCode::LD, 2, 0, 0),
+            ])
+            .unwrap();
+
+        emulator.execute_next_line().unwrap();
+        assert_eq!(emulator.registers[1], 0xFFFF);
+        assert_eq!(emulator.flags().bits(), Flags::NEGATIVE | Flags::CARRY);
+
+        // Memory accesses keep the flags.
+        emulator.execute_next_line().unwrap();
+        assert_eq!(emulator.flags().bits(), Flags::NEGATIVE | Flags::CARRY);
+    }
+
+    #[test]
+    fn zero_register() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator
+            .load_program(vec![
+                memory_instruction(OpCode::ADDI, 0, 0, 5),
+                memory_instruction(OpCode::LD, 0, 1, 0),
+                memory_instruction(OpCode::ADDI, 3, 0, 42),
+            ])
+            .unwrap();
+        emulator.registers_mut()[1] = RAM.start as u16;
+        emulator.memory_mut().write(RAM.start, 7).unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[0], 0);
+        assert_eq!(emulator.registers[3], 42);
+        // Only strict mode reports the writes.
+        assert_eq!(emulator.take_warnings(), []);
+    }
+
+    #[test]
+    fn zero_register_strict() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator.set_strict(true);
+        emulator
+            .load_program(vec![
+                memory_instruction(OpCode::ADDI, 1, 0, 1),
+                memory_instruction(OpCode::ADDI, 0, 1, 1),
+            ])
+            .unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[0], 0);
+        assert_eq!(
+            emulator.take_warnings(),
+            [Warning::ZeroRegisterWrite { pc: 2 }]
+        );
+        assert_eq!(emulator.take_warnings(), []);
+    }
+
+    #[test]
+    fn call_and_ret_strict() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator.set_strict(true);
+        emulator
+            .load_program(conformance::assemble(
+                "be 0, 0, @Main\n@Sub\naddi 1, 0, 1\nret\n@Main\ncall @Sub\n",
+            ))
+            .unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[1], 1);
+        assert_eq!(emulator.take_warnings(), []);
+    }
+
+    /// Install the handlers at `@VBlank` and `@Timer`, enable both lines and loop at `26`.
+    const HANDLERS: &str = "\
+be 0, 0, 14
+@VBlank
+addi 4, 4, 1
+reti
+@Timer
+str 0, 0, 61441 # unmask, VBlank can nest
+addi 5, 5, 1
+addi 5, 5, 1
+reti
+addi 1, 0, @VBlank
+str 0, 1, 16392
+addi 1, 0, @Timer
+str 0, 1, 16393
+addi 1, 0, 3
+str 0, 1, 61440
+@Loop
+be 0, 0, @Loop
+";
+
+    fn step(emulator: &mut Emulator, count: usize) {
+        for _ in 0..count {
+            emulator.execute_next_line().unwrap();
+        }
+    }
+
+    fn handlers() -> Emulator {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(HANDLERS))
+            .unwrap();
+
+        while emulator.pc() != 26 {
+            step(&mut emulator, 1);
+        }
+
+        emulator
+    }
+
+    #[test]
+    fn nested_interrupts() {
+        let mut emulator = handlers();
+        let top = RAM.end as u16;
+
+        emulator.raise(Interrupt::Timer);
+        step(&mut emulator, 1);
+        assert_eq!(emulator.pc(), 6);
+        assert!(emulator.memory().interrupts().masked());
+        assert_eq!(emulator.registers[Register::S as usize], top - 2);
+        assert_eq!(emulator.memory_mut().read(RAM.end - 1), Ok(26));
+
+        step(&mut emulator, 1);
+        emulator.raise(Interrupt::VBlank);
+        step(&mut emulator, 1);
+        assert_eq!(emulator.pc(), 2);
+        assert_eq!(emulator.registers[Register::S as usize], top - 4);
+
+        step(&mut emulator, 2);
+        assert_eq!(emulator.pc(), 8);
+        assert!(!emulator.memory().interrupts().masked());
+
+        step(&mut emulator, 3);
+        assert_eq!(emulator.pc(), 26);
+        assert_eq!(emulator.registers[4], 1);
+        assert_eq!(emulator.registers[5], 2);
+        assert_eq!(emulator.registers[Register::S as usize], top);
+        assert!(!emulator.memory().interrupts().masked());
+        assert_eq!(emulator.memory_mut().read(interrupt::PENDING), Ok(0));
+    }
+
+    #[test]
+    fn masked_interrupt_waits() {
+        let mut emulator = handlers();
+
+        emulator.raise(Interrupt::Timer);
+        emulator.raise(Interrupt::VBlank);
+
+        // VBlank has the highest priority and its handler keeps Timer masked.
+        step(&mut emulator, 1);
+        assert_eq!(emulator.pc(), 2);
+        step(&mut emulator, 2);
+        assert_eq!(emulator.pc(), 26);
+
+        step(&mut emulator, 1);
+        assert_eq!(emulator.pc(), 6);
+        assert_eq!(emulator.registers[4], 1);
+    }
+
+    #[test]
+    fn interrupt_without_handler_stays_pending() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "addi 1, 0, 1\nstr 0, 1, 61440\naddi 2, 0, 1\n",
+            ))
+            .unwrap();
+
+        emulator.raise(Interrupt::VBlank);
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[2], 1);
+        assert_eq!(emulator.memory_mut().read(interrupt::PENDING), Ok(1));
+    }
+
+    #[test]
+    fn illegal_instruction_handler() {
+        // The handler skips the illegal instruction by patching the saved pc.
+        let handler = conformance::assemble(
+            "\
+be 0, 0, 12
+@Illegal
+addi 3, 0, 1
+ld 4, 15, 1
+addi 4, 4, 2
+str 15, 4, 1
+reti
+addi 1, 0, @Illegal
+str 0, 1, 16384
+",
+        );
+        let mut program = image(&handler);
+        program.extend([0x00FF, 0x0000]);
+        program.extend(image(&conformance::assemble("addi 5, 0, 1\n")));
+
+        let mut emulator = Emulator::new();
+        emulator.load_image(0, &program).unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[3], 1);
+        assert_eq!(emulator.registers[4], 18);
+        assert_eq!(emulator.registers[5], 1);
+    }
+
+    #[test]
+    fn unhandled_exceptions() {
+        let mut emulator = Emulator::new();
+        emulator.load_image(0, &[0x00FF, 0x0000]).unwrap();
+
+        assert_eq!(
+            emulator.execute_next_line(),
+            Err(Exception::new(0, ExceptionKind::IllegalInstruction(0xFF)))
+        );
+
+        emulator
+            .load_program(conformance::assemble("jal 0, 0, 3\n"))
+            .unwrap();
+        step(&mut emulator, 1);
+
+        assert_eq!(
+            emulator.execute_next_line(),
+            Err(Exception::new(3, ExceptionKind::Misaligned))
+        );
+    }
+
+    #[test]
+    fn bus_fault_handler() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "\
+be 0, 0, 6
+@Fault
+ld 2, 15, 1 # address of the faulting instruction
+jal 0, 0, 12
+addi 1, 0, @Fault
+str 0, 1, 16385
+ld 3, 0, 56832
+",
+            ))
+            .unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[2], 10);
+        assert!(emulator.memory().interrupts().masked());
+    }
+
+    #[test]
+    fn gamepad() {
+        use gamepad::Button;
+
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble("ld 1, 0, 61456\n"))
+            .unwrap();
+
+        emulator.set_buttons(Buttons::from_iter([Button::A, Button::Start]));
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[1], 0b1001_0000);
+        assert_eq!(
+            emulator.memory_mut().read(interrupt::PENDING),
+            Ok(1 << Interrupt::Gamepad.line())
+        );
+    }
+
+    #[test]
+    fn timer() {
+        let program = conformance::assemble(
+            "\
+be 0, 0, 6
+@Timer
+addi 4, 4, 1
+reti
+addi 1, 0, @Timer
+str 0, 1, 16393
+addi 1, 0, 2
+str 0, 1, 61440
+addi 1, 0, 10
+str 0, 1, 61473 # match every 10 cycles
+addi 1, 0, 3
+str 0, 1, 61475 # run and interrupt
+@Loop
+addi 5, 5, 1
+be 0, 0, @Loop
+",
+        );
+
+        let run = || {
+            let mut emulator = Emulator::new();
+            emulator.load_program(program.clone()).unwrap();
+            step(&mut emulator, 1000);
+            emulator
+        };
+
+        let emulator = run();
+        assert_eq!(emulator.cycles(), 1000);
+        assert_eq!(emulator.registers[4], 99);
+        assert_eq!(emulator.registers, run().registers);
+    }
+
+    #[test]
+    fn wait_for_interrupt() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "\
+addi 1, 0, 4
+str 0, 1, 61440 # enable Gamepad
+addi 1, 0, 1
+str 0, 1, 61441 # mask
+wfi
+addi 2, 0, 1
+",
+            ))
+            .unwrap();
+
+        emulator.execute_all().unwrap();
+        assert!(emulator.waiting());
+        assert_eq!(emulator.registers[2], 0);
+
+        let cycles = emulator.cycles();
+        step(&mut emulator, 10);
+        assert_eq!(emulator.pc(), 10);
+        assert_eq!(emulator.cycles(), cycles + 10);
+
+        // Masked, the interrupt wakes the CPU without entering a handler.
+        emulator.set_buttons(Buttons::from_bits(1));
+        emulator.execute_all().unwrap();
+        assert!(!emulator.waiting());
+        assert_eq!(emulator.registers[2], 1);
+    }
+
+    #[test]
+    fn wait_for_timer() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "\
+addi 1, 0, 2
+str 0, 1, 61440 # enable Timer
+addi 1, 0, 1
+str 0, 1, 61441 # mask
+addi 1, 0, 100
+str 0, 1, 61473 # COMPARE
+addi 1, 0, 3
+str 0, 1, 61475 # run and interrupt
+wfi
+addi 2, 0, 1
+",
+            ))
+            .unwrap();
+
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[2], 1);
+        assert_eq!(emulator.cycles(), 108);
+    }
+
+    #[test]
+    fn timing_model() {
+        let mut emulator = Emulator::new();
+        emulator.set_timing(
+            "ld 3\nbe 1 4\nbne 2 5\nentry 4\nwait rom 1\nwait ram 2\n"
+                .parse()
+                .unwrap(),
+        );
+        emulator
+            .load_program(conformance::assemble(
+                "addi 1, 0, 16384\nld 2, 1, 0\nbe 0, 0, 6\nbne 0, 0, 0\n",
+            ))
+            .unwrap();
+
+        // Each instruction also waits 2 cycles for its words in ROM
+        let mut cycles = Vec::new();
+        while !emulator.finished() {
+            emulator.execute_next_line().unwrap();
+            cycles.push(emulator.cycles());
+        }
+        assert_eq!(cycles, [3, 10, 16, 20]);
+
+        // Entering a handler pushes 2 words in RAM
+        let mut emulator = handlers();
+        emulator.set_timing("entry 4\nwait ram 2\n".parse().unwrap());
+        let cycles = emulator.cycles();
+        emulator.raise(Interrupt::VBlank);
+        step(&mut emulator, 1);
+        assert_eq!(emulator.pc(), 2);
+        assert_eq!(emulator.cycles(), cycles + 8);
+    }
+
+    #[test]
+    fn cycle_counter() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "addi 1, 0, 1\nld 1, 0, 61536 # CYCLES0\nld 2, 0, 61537\nstr 0, 1, 61536\n",
+            ))
+            .unwrap();
+
+        step(&mut emulator, 3);
+        assert_eq!(emulator.registers[1], 1);
+        assert_eq!(emulator.registers[2], 0);
+
+        assert_eq!(
+            emulator
+                .execute_next_line()
+                .map_err(|exception| exception.kind()),
+            Err(ExceptionKind::BusFault(BusFault::new(
+                counter::CYCLES0,
+                BusFaultKind::ReadOnly
+            )))
+        );
+    }
+
+    #[test]
+    fn cycle_limit() {
+        let mut emulator = Emulator::new();
+        emulator.set_timing("be 1 3\n".parse().unwrap());
+        emulator
+            .load_program(conformance::assemble("@Loop\nbe 0, 0, @Loop\n"))
+            .unwrap();
+        emulator.set_cycle_limit(Some(100));
+
+        // The step crossing the limit completes
+        emulator.execute_all().unwrap();
+        assert!(emulator.out_of_cycles());
+        assert_eq!(emulator.cycles(), 102);
+
+        emulator.run_frame(1000).unwrap();
+        assert_eq!(emulator.cycles(), 102);
+    }
+
+    #[test]
+    fn trace() {
+        use trace::{Access, Retired};
+
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "\
+addi 1, 0, 16384
+addi 2, 0, 5
+str 1, 2, 3
+ld 3, 1, 3
+push 3
+add 0, 0, 0
+ld 4, 0, 56832 # unmapped
+",
+            ))
+            .unwrap();
+        emulator.set_tracing(true);
+
+        assert!(emulator.execute_all().is_err());
+
+        let trace = emulator.take_trace();
+        let effects: Vec<_> = trace
+            .iter()
+            .map(|retired| {
+                (
+                    retired.cycle,
+                    retired.pc,
+                    &retired.registers[..],
+                    &retired.memory[..],
+                )
+            })
+            .collect();
+        let (write, read) = (true, false);
+        let access = |write, address, value| Access {
+            write,
+            address,
+            value,
+        };
+
+        assert_eq!(
+            effects,
+            [
+                (1, 0, &[(1, 0x4000)][..], &[][..]),
+                (2, 2, &[(2, 5)], &[]),
+                (3, 4, &[], &[access(write, 0x4003, 5)]),
+                (4, 6, &[(3, 5)], &[access(read, 0x4003, 5)]),
+                (5, 8, &[(15, 0xBFFF)], &[access(write, 0xBFFF, 5)]),
+                (6, 10, &[], &[]),
+            ]
+        );
+        assert_eq!(trace[0].disassembly(), "addi 1, 0, 16384");
+        assert_eq!(emulator.take_trace(), Vec::<Retired>::new());
+    }
+
+    #[test]
+    fn frames() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "\
+addi 1, 0, 1
+str 0, 1, 61440 # enable VBlank
+str 0, 1, 61441 # mask
+@Frame
+wfi
+str 0, 1, 61442 # clear VBlank
+addi 2, 2, 1
+be 0, 0, @Frame
+",
+            ))
+            .unwrap();
+
+        for _ in 0..5 {
+            emulator.run_frame(100).unwrap();
+        }
+
+        // The first frame has no vertical blank to wait for.
+        assert_eq!(emulator.registers[2], 4);
+        assert_eq!(emulator.cycles(), 500);
+        assert_eq!(emulator.memory_mut().read(vblank::FRAMES), Ok(5));
+        assert_eq!(emulator.memory_mut().read(vblank::STATUS), Ok(1));
+    }
+
+    #[test]
+    fn halt_ends_frames() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "halt
+addi 1, 0, 1
+",
+            ))
+            .unwrap();
+
+        emulator.run_frame(100).unwrap();
+        emulator.run_frame(100).unwrap();
+
+        assert!(emulator.finished());
+        assert_eq!(emulator.pc(), 2);
+        assert_eq!(emulator.cycles(), 1);
+        assert_eq!(emulator.registers[1], 0);
+    }
+
+    #[test]
+    fn replay_from_save_state() {
+        use input::Script;
+
+        // Sum the buttons held in a gamepad handler while the timer runs.
+        let program = conformance::assemble(
+            "\
+be 0, 0, 8
+@Gamepad
+ld 1, 0, 61456
+add 4, 4, 1
+reti
+addi 1, 0, @Gamepad
+str 0, 1, 16394
+addi 1, 0, 5
+str 0, 1, 61440 # enable VBlank and Gamepad
+addi 1, 0, 7
+str 0, 1, 61473 # COMPARE
+addi 1, 0, 1
+str 0, 1, 61475 # run
+@Frame
+ld 5, 0, 61472
+str 0, 5, 16640
+wfi
+str 0, 1, 61442 # clear VBlank
+be 0, 0, @Frame
+",
+        );
+        let frames = 20;
+
+        // Record a session where the buttons change every few frames.
+        let mut recording = Script::new();
+        let mut live = Emulator::new();
+        live.load_program(program.clone()).unwrap();
+        let mut saved = Vec::new();
+        for frame in 0..frames {
+            if frame == 8 {
+                saved = live.save_state();
+            }
+            let buttons = Buttons::from_bits((frame / 3 % 4) as u16);
+            recording.record(frame, buttons);
+            live.set_buttons(buttons);
+            live.run_frame(100).unwrap();
+        }
+        recording.finish(frames);
+        assert_ne!(live.registers[4], 0);
+
+        let replay = |emulator: &mut Emulator, script: &Script, start: u64| {
+            for frame in start..script.frames().unwrap() {
+                emulator.set_buttons(script.buttons(frame));
+                emulator.run_frame(100).unwrap();
+            }
+        };
+        let script: Script = recording.to_string().parse().unwrap();
+
+        let mut replayed = Emulator::new();
+        replayed.load_program(program).unwrap();
+        replay(&mut replayed, &script, 0);
+        assert_eq!(replayed.state_hash(), live.state_hash());
+
+        let mut resumed = Emulator::new();
+        resumed.load_state(&saved).unwrap();
+        assert_eq!(resumed.save_state(), saved);
+        replay(&mut resumed, &script, 8);
+        assert_eq!(resumed.state_hash(), live.state_hash());
+        assert_eq!(resumed.registers, live.registers);
+    }
+
+    #[test]
+    fn rewind() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(
+                "\
+be 0, 0, 6
+@Timer
+addi 4, 4, 1
+reti
+addi 1, 0, @Timer
+str 0, 1, 16393
+addi 1, 0, 2
+str 0, 1, 61440
+addi 1, 0, 10
+str 0, 1, 61473 # match every 10 cycles
+addi 1, 0, 3
+str 0, 1, 61475 # run and interrupt
+@Loop
+addi 5, 5, 1
+str 5, 5, 16640
+be 0, 0, @Loop
+",
+            ))
+            .unwrap();
+        emulator.set_recording(true);
+
+        let steps = [2999, 2048, 1500, 1024, 400, 3, 0];
+        let mut hashes = std::collections::HashMap::new();
+        for count in 0..3000 {
+            if steps.contains(&count) {
+                hashes.insert(count, emulator.state_hash());
+            }
+            emulator.execute_next_line().unwrap();
+        }
+
+        for step in steps {
+            assert!(emulator.rewind(step));
+            assert_eq!(emulator.state_hash(), hashes[&step]);
+        }
+        assert!(!emulator.rewind(1));
+
+        // The host changes the machine, the steps after it replay from there
+        step(&mut emulator, 500);
+        emulator.registers_mut()[4] = 1000;
+        let changed = emulator.state_hash();
+        step(&mut emulator, 100);
+        assert!(emulator.rewind(500));
+        assert_eq!(emulator.state_hash(), changed);
+        assert!(emulator.rewind(400));
+        assert_eq!(emulator.state_hash(), hashes[&400]);
+    }
+
+    #[test]
+    fn invalid_save_state() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble("addi 1, 0, 1\n"))
+            .unwrap();
+        let hash = emulator.state_hash();
+        let mut state = emulator.save_state();
+
+        state.push(0);
+        assert_eq!(emulator.load_state(&state), Err(StateError::TrailingData));
+        state.truncate(100);
+        assert_eq!(emulator.load_state(&state), Err(StateError::Truncated));
+        assert_eq!(emulator.state_hash(), hash);
+
+        emulator.execute_all().unwrap();
+        assert_ne!(emulator.state_hash(), hash);
+    }
+
+    /// Compare the screen with the golden image `name`, or write it when
+    /// `UPDATE_GOLDEN` is set.
+    fn assert_golden(emulator: &Emulator, name: &str) {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("golden")
+            .join(name);
+
+        let mut image = Vec::new();
+        emulator.screenshot().write_ppm(&mut image).unwrap();
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &image).unwrap();
+        }
+
+        let golden = std::fs::read(&path).unwrap();
+        assert!(
+            golden == image,
+            "the screen differs from {}",
+            path.display()
+        );
+    }
+
+    #[test]
+    fn golden_red_square() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(include_str!(
+                "../../demos/blasm/red_square.blasm"
+            )))
+            .unwrap();
+
+        for _ in 0..3 {
+            emulator.run_frame(vblank::CYCLES_PER_FRAME).unwrap();
+        }
+
+        assert!(emulator.finished());
+        assert_golden(&emulator, "red_square.ppm");
+    }
+
+    #[test]
+    fn golden_chime() {
+        let mut emulator = Emulator::new();
+        emulator
+            .load_program(conformance::assemble(include_str!(
+                "../../demos/blasm/chime.blasm"
+            )))
+            .unwrap();
+
+        let mut samples = Vec::new();
+
+        for _ in 0..16 {
+            emulator.run_frame(vblank::CYCLES_PER_FRAME).unwrap();
+            samples.extend(emulator.take_samples());
+        }
+
+        // 16 frames of 1/30 s
+        assert_eq!(samples.len(), 16 * apu::SAMPLE_RATE as usize / 30);
+
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("golden/chime.wav");
+        let mut wav = Vec::new();
+        apu::write_wav(&mut wav, &samples).unwrap();
+
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            std::fs::write(&path, &wav).unwrap();
+        }
+
+        let golden = std::fs::read(&path).unwrap();
+        assert!(golden == wav, "the sound differs from {}", path.display());
+    }
+
+    /// Set up a scene in tile mode on a screen of `width` by `height` tiles:
+    /// a checkerboard on background 0, frames on background 1 and triangles
+    /// as sprites, flipped and behind the backgrounds.
+    fn tile_scene(width: u16, height: u16, scroll: [u16; 4]) -> Emulator {
+        use ppu::*;
+
+        let mut emulator = Emulator::new();
+        let memory = emulator.memory_mut();
+        let mut write = |address: usize, value: u16| memory.write(address, value).unwrap();
+
+        // Palette 0: dark blue backdrop, white and grey, palette 1: red and yellow.
+        for (index, color) in [0x0008, 0x0FFF, 0x0888, 0x0000, 0x0F00, 0x0FF0]
+            .into_iter()
+            .enumerate()
+        {
+            let palette = index / 3;
+            write(PALETTES.start + palette * 16 + index % 3, color);
+        }
+
+        for row in 0..8 {
+            // Tile 1: a checkerboard of 4x4 squares of colors 1 and 2.
+            let (left, right) = if row < 4 {
+                (0x1111, 0x2222)
+            } else {
+                (0x2222, 0x1111)
+            };
+            write(PATTERNS.start + 16 + row * 2, left);
+            write(PATTERNS.start + 16 + row * 2 + 1, right);
+
+            // Tile 2: a triangle of color 1, in the bottom left corner.
+            let triangle: u32 = (0..8)
+                .filter(|x| *x <= row)
+                .map(|x| 1 << (28 - 4 * x))
+                .sum();
+            write(PATTERNS.start + 32 + row * 2, (triangle >> 16) as u16);
+            write(PATTERNS.start + 32 + row * 2 + 1, triangle as u16);
+
+            // Tile 3: a frame of color 2.
+            let frame: u32 = if row == 0 || row == 7 {
+                0x2222_2222
+            } else {
+                0x2000_0002
+            };
+            write(PATTERNS.start + 48 + row * 2, (frame >> 16) as u16);
+            write(PATTERNS.start + 48 + row * 2 + 1, frame as u16);
+        }
+
+        for entry in 0..MAP_SIZE * MAP_SIZE {
+            let (x, y) = (entry % MAP_SIZE, entry / MAP_SIZE);
+
+            if (x + y) % 2 == 0 {
+                write(BACKGROUNDS[0].start + entry, 1);
+            }
+            if x == y {
+                write(BACKGROUNDS[1].start + entry, 1 << 8 | 3);
+            }
+        }
+
+        let sprites = [
+            (20, 10, VISIBLE | 1 << 8 | 2),
+            (30, 10, VISIBLE | FLIP_X | 1 << 8 | 2),
+            (40, 10, VISIBLE | FLIP_Y | 1 << 8 | 2),
+            (12, 28, VISIBLE | BEHIND | 1 << 8 | 2),
+            (0, 0, 1 << 8 | 2),
+        ];
+        for (index, (x, y, entry)) in sprites.into_iter().enumerate() {
+            write(SPRITES.start + index * 4, x);
+            write(SPRITES.start + index * 4 + 1, y);
+            write(SPRITES.start + index * 4 + 2, entry);
+        }
+
+        for (offset, value) in scroll.into_iter().enumerate() {
+            write(SCROLL + offset, value);
+        }
+        write(SCREEN, height << 8 | width);
+        write(
+            CONTROL,
+            TILE_MODE | SHOW_BACKGROUND[0] | SHOW_BACKGROUND[1] | SHOW_SPRITES,
+        );
+
+        emulator
+    }
+
+    #[test]
+    fn golden_tile_scene() {
+        let emulator = tile_scene(8, 6, [0; 4]);
+
+        assert_eq!(emulator.screenshot().width(), 64);
+        assert_golden(&emulator, "tile_scene.ppm");
+    }
+
+    #[test]
+    fn golden_scrolled_tile_scene() {
+        // Background 1 wraps around the left and top edges.
+        let emulator = tile_scene(4, 4, [4, 2, 252, 250]);
+
+        assert_golden(&emulator, "scrolled_tile_scene.ppm");
+    }
+
+    #[test]
+    fn store_and_load() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator.registers_mut()[1] = RAM.start as u16;
+        emulator.registers_mut()[2] = 42;
+        emulator
+            .load_program(vec![
+                memory_instruction(OpCode::STR, 1, 2, 3),
+                memory_instruction(OpCode::LD, 3, 1, 3),
+            ])
+            .unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.registers[3], 42);
+        assert_eq!(emulator.memory_mut().read(RAM.start + 3), Ok(42));
+        assert_eq!(emulator.memory().vram()[3], 0);
+    }
+
+    #[test]
+    fn store_to_vram() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator.registers_mut()[1] = VRAM.start as u16;
+        emulator.registers_mut()[2] = 0xF00;
+        emulator
+            .load_program(vec![memory_instruction(OpCode::STR, 1, 2, 33)])
+            .unwrap();
+        emulator.execute_all().unwrap();
+
+        assert_eq!(emulator.memory().vram()[33], 0xF00);
+    }
+
+    #[test]
+    fn bus_faults() {
+        let mut emulator: Emulator = Emulator::new();
+        emulator
+            .load_program(vec![memory_instruction(OpCode::STR, 0, 2, 7)])
+            .unwrap();
+
+        let fault = BusFault::new(7, BusFaultKind::ReadOnly);
+        assert_eq!(
+            emulator.execute_next_line(),
+            Err(Exception::new(0, ExceptionKind::BusFault(fault)))
+        );
+        assert_eq!(emulator.pc(), 0);
+
+        emulator
+            .load_program(vec![memory_instruction(OpCode::LD, 3, 1, 1)])
+            .unwrap();
+        emulator.registers_mut()[1] = 0xFFFF;
+
+        // The address wraps around to the first word of the ROM.
+        let word = emulator.memory_mut().read(0).unwrap();
+        assert_eq!(emulator.execute_next_line(), Ok(()));
+        assert_eq!(emulator.registers[3], word);
+    }
+
     #[test]
     fn jmp() {
         let mut instruction = BInstruction(0);
@@ -220,14 +1837,14 @@ mod tests {
         let mut program = Vec::new();
         program.push(general_instruction);
         let mut emulator: Emulator = Emulator::new();
-        emulator.load_program(program);
-        emulator.execute_all();
+        emulator.load_program(program).unwrap();
+        emulator.execute_next_line().unwrap();
         assert_eq!(
-            emulator.next_instruction(),
+            emulator.pc(),
             0xabcd,
             "expected 0x{:x} got 0x{:x}",
             0xabcd,
-            emulator.next_instruction()
+            emulator.pc()
         );
     }
 
@@ -241,10 +1858,12 @@ mod tests {
         instruction.set_immediate(3);
         emulator.registers_mut()[8] = 10;
         instruction.set_rs1(8);
-        emulator.program.push(Instruction {
-            instruction: InstructionEnum::IInstruction(instruction),
-        });
-        emulator.execute_all();
+        emulator
+            .load_program(vec![Instruction {
+                instruction: InstructionEnum::IInstruction(instruction),
+            }])
+            .unwrap();
+        emulator.execute_all().unwrap();
         assert_eq!(emulator.registers[2], 13);
     }
 
@@ -261,8 +1880,8 @@ mod tests {
         });
         let mut emulator: Emulator = Emulator::new();
         emulator.registers_mut()[1] = 1;
-        emulator.load_program(program);
-        emulator.execute_all();
+        emulator.load_program(program).unwrap();
+        emulator.execute_all().unwrap();
         assert_eq!(emulator.registers[2], 11);
     }
 }
@@ -1,70 +1,411 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::net::TcpListener;
 use std::time::Duration;
 use std::time::Instant;
 
 // https://stackoverflow.com/questions/20922091/how-do-you-use-parent-module-imports-in-rust
 use asmlib::instruction::Instruction;
-use asmlib::instruction::InstructionEnum;
 use clap::Parser;
-use emulator::Emulator;
+use emulator::apu;
+use emulator::dap;
+use emulator::debugger::{Command, Debugger, Stop};
+use emulator::gdb;
+use emulator::input::Script;
+use emulator::timing::Timing;
+use emulator::trace::TraceWriter;
+use emulator::vblank;
+use emulator::{Emulator, KeyMap};
 use files;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Input file that the Emulator will execute.
-    #[arg(short, long)]
-    path: String,
+    #[arg(short, long, required_unless_present_any = ["dap", "load_state"])]
+    path: Option<String>,
 
-    /// Set Emulator to Debug mode.
+    /// Run the program in the debugger, reading commands from the standard input.
     #[arg(short, long)]
     debug: bool,
 
+    /// Wait for GDB on localhost at this port, then run the program from GDB.
+    #[arg(long, conflicts_with_all = ["debug", "video", "headless"])]
+    gdb: Option<u16>,
+
+    /// Serve the Debug Adapter Protocol over the standard input and output,
+    /// the client launching the program.
+    #[arg(long, conflicts_with_all = ["path", "debug", "gdb", "video", "headless"])]
+    dap: bool,
+
     /// Dump all the registers at the end of the execution
     #[arg(long)]
     dump_regs: bool,
 
     /// Enable the Window for the Emulator
     ///
-    /// This will execute your input file every frame.
+    /// The program runs for a budget of cycles each frame, then the frame is drawn.
     #[arg(short, long)]
     video: bool,
+
+    /// Run `--frames` frames without opening a window, for machines without a display.
+    #[arg(long, conflicts_with = "video")]
+    headless: bool,
+
+    /// Number of frames to run with `--headless`, by default the length of
+    /// the `--input` script if it gives one, 1 otherwise.
+    #[arg(long)]
+    frames: Option<u64>,
+
+    /// Save the screen to an image at the end, PPM if the path ends with `.ppm`, PNG otherwise.
+    #[arg(long)]
+    screenshot: Option<String>,
+
+    /// Save the sound played to a WAV file at the end.
+    #[arg(long)]
+    audio: Option<String>,
+
+    /// Cycles the program runs each frame, in the window or with `--headless`.
+    #[arg(long, default_value_t = vblank::CYCLES_PER_FRAME)]
+    cycles_per_frame: u64,
+
+    /// Timing table giving the cycles of each instruction, handler entry and
+    /// memory access, one cycle per instruction by default.
+    #[arg(long)]
+    timing: Option<String>,
+
+    /// Stop the program after this many cycles, reporting where it was.
+    #[arg(long)]
+    max_cycles: Option<u64>,
+
+    /// Warn when the program writes to the zero register.
+    #[arg(long)]
+    strict: bool,
+
+    /// Keys for the gamepad buttons in the window, such as `a=k,b=j`.
+    #[arg(long)]
+    keymap: Option<KeyMap>,
+
+    /// Input script giving the buttons held frame by frame, instead of the keyboard.
+    #[arg(long)]
+    input: Option<String>,
+
+    /// Record the buttons held in the window to an input script.
+    #[arg(long)]
+    record_input: Option<String>,
+
+    /// Start from a save state instead of the beginning of the program, whose
+    /// path is then only needed for its labels.
+    #[arg(long)]
+    load_state: Option<String>,
+
+    /// Save the state of the machine at the end.
+    #[arg(long)]
+    save_state: Option<String>,
+
+    /// Print the hash of the state of the machine at the end, equal for runs
+    /// that end in the same state.
+    #[arg(long)]
+    state_hash: bool,
+
+    /// Save each instruction retired with its effects, as JSON lines if the
+    /// path ends with `.jsonl` and in a compact binary form otherwise.
+    #[arg(long, conflicts_with_all = ["debug", "gdb"])]
+    trace: Option<String>,
+}
+
+/// Print the warnings of strict mode.
+fn print_warnings(emulator: &mut Emulator) {
+    for warning in emulator.take_warnings() {
+        eprintln!("[Emulator] warning: {}", warning);
+    }
+}
+
+/// Report an error that stopped the program and exit.
+fn fault(error: impl std::fmt::Display) -> ! {
+    eprintln!("[Emulator] {}", error);
+    std::process::exit(1);
+}
+
+/// Save `samples` to the WAV file at `path`, exit if it fails.
+fn save_audio(path: &str, samples: &[i16]) {
+    let saved = std::fs::File::create(path)
+        .map(std::io::BufWriter::new)
+        .and_then(|writer| apu::write_wav(writer, samples));
+
+    if let Err(error) = saved {
+        fault(format!("{}: {}", path, error));
+    }
+}
+
+/// Save the instructions retired since the last call to the trace, exit if it fails.
+fn save_trace(emulator: &mut Emulator, trace: &mut Option<TraceWriter>) {
+    if let Some(writer) = trace {
+        let saved = writer.write(&emulator.take_trace());
+
+        if let Err(error) = saved.and_then(|()| writer.flush()) {
+            fault(error);
+        }
+    }
+}
+
+const HELP: &str = "\
+break <location> [if <condition>]  stop before the instruction at location, such as @Loop
+watch <address>                    stop when the word at address changes
+delete <id>                        remove a breakpoint or a watchpoint
+info                               list the breakpoints and the watchpoints
+continue                           run until something stops the program
+step [count]                       run count instructions, 1 by default
+next                               run one instruction, a call until it returns
+finish                             run until the current subroutine returns
+reverse-step [count]               go back count steps, 1 by default
+reverse-continue                   go back to the previous breakpoint
+who <register|address>             show the step that last wrote r0 to r15 or a word
+print                              show the registers
+set <register|address> <value>     write r0 to r15, pc or a word of memory
+x <address> [count]                dump count words of memory
+disassemble [location] [count]     disassemble around pc or from location
+history                            list the commands, !<n> runs the n-th again
+quit                               stop debugging
+A condition compares a register with a value: r1 == 5, with ==, !=, <, <=, > or >=.
+Commands may be shortened to their first letter, rs and rc going back, an empty line runs the last one again.";
+
+/// Print the registers, `pc`, the flags and the cycles run.
+fn print_registers(emulator: &Emulator) {
+    for (index, value) in emulator.registers().iter().enumerate() {
+        let separator = if index % 4 == 3 { "\n" } else { "  " };
+        print!("r{:<2} 0x{:04X}{}", index, value, separator);
+    }
+    println!(
+        "pc  0x{:04X}  flags {}  cycles {}",
+        emulator.pc(),
+        emulator.flags(),
+        emulator.cycles()
+    );
 }
 
-fn print_instruction(emulator: &Emulator) {
-    print!(
-        "Executing opcode : {} with ",
-        emulator.program()[emulator.next_instruction()].get_opcode()
+/// Print why the program stopped and the next instruction.
+fn print_stop(
+    emulator: &mut Emulator,
+    debugger: &Debugger,
+    stop: Result<Stop, emulator::Exception>,
+) {
+    match stop {
+        Ok(Stop::Step) => (),
+        Ok(stop) => println!("Stopped: {}", stop),
+        Err(error) => println!("[Emulator] {}", error),
+    }
+
+    let pc = emulator.pc();
+    println!(
+        "=> {}  {}",
+        debugger.location(pc),
+        debugger.instruction_at(emulator, pc)
     );
-    match emulator.program()[emulator.next_instruction()].instruction {
-        InstructionEnum::RInstruction(instruction) => println!(
-            "rd {} rs1 {} rs2 {} ",
-            instruction.get_rd(),
-            instruction.get_rs1(),
-            instruction.get_rs2()
-        ),
-        InstructionEnum::IInstruction(instruction) => println!(
-            "rd {} rs1 {} immediate {} ",
-            instruction.get_rd(),
-            instruction.get_rs1(),
-            instruction.get_immediate()
-        ),
-        InstructionEnum::BInstruction(instruction) => println!(
-            "rs1 {} rs2 {} ",
-            instruction.get_rs1(),
-            instruction.get_rs2()
-        ),
+}
+
+/// Run the program in the debugger, reading commands from the standard input.
+fn debug(emulator: &mut Emulator, mut debugger: Debugger) {
+    let mut history: Vec<String> = Vec::new();
+
+    println!("Type help for the commands");
+    print_stop(emulator, &debugger, Ok(Stop::Step));
+
+    loop {
+        print!("(blask) ");
+        std::io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+
+        let line = match line.trim() {
+            "" => match history.last() {
+                Some(last) => last.clone(),
+                None => continue,
+            },
+            line => match line.strip_prefix('!').map(str::parse::<usize>) {
+                Some(Ok(index)) => {
+                    match index.checked_sub(1).and_then(|index| history.get(index)) {
+                        Some(line) => line.clone(),
+                        None => {
+                            println!("No command {} in the history", index);
+                            continue;
+                        }
+                    }
+                }
+                _ => line.to_string(),
+            },
+        };
+
+        let command = match debugger.parse(&line) {
+            Ok(command) => command,
+            Err(error) => {
+                println!("{}", error);
+                continue;
+            }
+        };
+        history.push(line);
+
+        match command {
+            Command::Break(address, condition) => {
+                let id = debugger.add_breakpoint(address, condition);
+                match condition {
+                    Some(condition) => println!(
+                        "Breakpoint {} at {} if {}",
+                        id,
+                        debugger.location(address),
+                        condition
+                    ),
+                    None => println!("Breakpoint {} at {}", id, debugger.location(address)),
+                }
+            }
+            Command::Watch(address) => match debugger.add_watchpoint(emulator, address) {
+                Ok(id) => println!("Watchpoint {} at 0x{:04X}", id, address),
+                Err(fault) => println!("{}", fault),
+            },
+            Command::Delete(id) => {
+                if !debugger.delete(id) {
+                    println!("No breakpoint or watchpoint {}", id);
+                }
+            }
+            Command::Info => {
+                for breakpoint in debugger.breakpoints() {
+                    print!(
+                        "Breakpoint {} at {}",
+                        breakpoint.id,
+                        debugger.location(breakpoint.address)
+                    );
+                    match breakpoint.condition {
+                        Some(condition) => println!(" if {}", condition),
+                        None => println!(),
+                    }
+                }
+                for watchpoint in debugger.watchpoints() {
+                    println!(
+                        "Watchpoint {} at 0x{:04X} = {}",
+                        watchpoint.id, watchpoint.address, watchpoint.value
+                    );
+                }
+            }
+            Command::Continue => {
+                let stop = debugger.resume(emulator);
+                print_stop(emulator, &debugger, stop);
+            }
+            Command::Step(count) => {
+                let stop = debugger.step(emulator, count);
+                print_stop(emulator, &debugger, stop);
+            }
+            Command::Next => {
+                let stop = debugger.next(emulator);
+                print_stop(emulator, &debugger, stop);
+            }
+            Command::Finish => {
+                let stop = debugger.finish(emulator);
+                print_stop(emulator, &debugger, stop);
+            }
+            Command::ReverseStep(count) => match debugger.reverse_step(emulator, count) {
+                Ok(stop) => print_stop(emulator, &debugger, Ok(stop)),
+                Err(error) => println!("{}", error),
+            },
+            Command::ReverseContinue => match debugger.reverse_resume(emulator) {
+                Ok(stop) => print_stop(emulator, &debugger, Ok(stop)),
+                Err(error) => println!("{}", error),
+            },
+            Command::LastWrite(location) => match Debugger::last_write(emulator, location) {
+                Ok(Some(write)) => println!(
+                    "{} written at step {} by {}  {}: {} -> {}",
+                    location,
+                    write.step,
+                    debugger.location(write.pc),
+                    debugger.instruction_at(emulator, write.pc),
+                    write.old,
+                    write.new
+                ),
+                Ok(None) => println!("{} not written since the start of the history", location),
+                Err(error) => println!("{}", error),
+            },
+            Command::Print => print_registers(emulator),
+            Command::Set(target, value) => {
+                if let Err(error) = Debugger::set(emulator, target, value) {
+                    println!("{}", error);
+                }
+            }
+            Command::Examine(address, count) => {
+                println!("{}", Debugger::examine(emulator, address, count))
+            }
+            Command::Disassemble(start, count) => {
+                println!("{}", debugger.disassemble(emulator, start, count))
+            }
+            Command::History => {
+                for (index, line) in history.iter().enumerate() {
+                    println!("{:>4}  {}", index + 1, line);
+                }
+            }
+            Command::Help => println!("{}", HELP),
+            Command::Quit => break,
+        }
+
+        print_warnings(emulator);
     }
 }
 
+/// Report that the program ran out of the cycles of `--max-cycles`.
+fn report_cycle_limit(emulator: &mut Emulator, debugger: &Debugger) {
+    let pc = emulator.pc();
+
+    eprintln!(
+        "[Emulator] reached the limit of {} cycles after {} cycles, at {}  {}",
+        emulator.cycle_limit().unwrap_or_default(),
+        emulator.cycles(),
+        debugger.location(pc),
+        debugger.instruction_at(emulator, pc)
+    );
+}
+
+/// Read the timing table at `path`, exit if it is invalid.
+fn read_timing(path: &str) -> Timing {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|error| fault(error));
+
+    text.parse()
+        .unwrap_or_else(|error| fault(format!("{}: {}", path, error)))
+}
+
+/// Read the input script at `path`, exit if it is invalid.
+fn read_script(path: &str) -> Script {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|error| fault(error));
+
+    text.parse()
+        .unwrap_or_else(|error| fault(format!("{}: {}", path, error)))
+}
+
 fn main() {
     let args = Args::parse();
 
-    let program: Vec<Instruction>;
+    if args.dap {
+        let input = std::io::BufReader::new(std::io::stdin());
+
+        if let Err(error) = dap::serve(input, std::io::stdout()) {
+            fault(error);
+        }
+        return;
+    }
+
+    let path = args.path.unwrap_or_default();
+    let script = args.input.as_deref().map(read_script);
 
-    if args.path.ends_with(".blasm") {
-        program = files::blasm_to_instructions(args.path.as_str());
-    } else if args.path.ends_with(".bin") {
-        program = files::binary_to_instructions(args.path.as_str());
+    let mut program: Vec<Instruction> = Vec::new();
+    let mut labels = HashMap::new();
+
+    if path.is_empty() {
+        // The program comes from the save state.
+    } else if path.ends_with(".blasm") {
+        let assembled = files::try_blasm_to_program(&path).unwrap_or_else(|error| fault(error));
+        program = assembled.instructions;
+        labels = assembled.labels;
+    } else if path.ends_with(".bin") {
+        program = files::binary_to_instructions(&path);
     } else {
         panic!("File must end with blasm or bin");
     }
@@ -73,32 +414,57 @@ fn main() {
         println!("Program size : {}", program.len());
     }
 
+    // The sound played so far, kept for `--audio`
+    let mut sound: Vec<i16> = Vec::new();
+
     let mut emulator: Emulator = Emulator::new();
-    emulator.load_program(program);
+    emulator.set_strict(args.strict);
+    emulator.set_cycle_limit(args.max_cycles);
+    if let Some(path) = &args.timing {
+        emulator.set_timing(read_timing(path));
+    }
+    if let Err(error) = emulator.load_program(program) {
+        fault(error);
+    }
 
-    if args.debug {
-        println!("Executing file {}...", args.path);
-        println!("Type p to print registers n to go to next instruction");
-
-        while emulator.next_instruction() < emulator.program().len().try_into().unwrap() {
-            let mut command = String::new();
-            std::io::stdin().read_line(&mut command).unwrap();
-            println!("{}", emulator.get_timer());
-
-            match command.as_str().trim() {
-                "p" => emulator.print_all_registers(),
-                "n" => {
-                    print_instruction(&emulator);
-                    emulator.execute_next_line();
-                }
-                _ => println!("Not recognized"),
-            }
+    if let Some(path) = &args.load_state {
+        let state = std::fs::read(path).unwrap_or_else(|error| fault(error));
+
+        if let Err(error) = emulator.load_state(&state) {
+            fault(format!("{}: {}", path, error));
+        }
+    }
+
+    let mut trace = args.trace.as_deref().map(|path| {
+        emulator.set_tracing(true);
+        TraceWriter::create(path).unwrap_or_else(|error| fault(format!("{}: {}", path, error)))
+    });
+
+    // Without a window, the program runs once, as the first frame.
+    if let Some(script) = &script {
+        emulator.set_buttons(script.buttons(0));
+    }
+
+    // Labels to report where the program ran out of cycles
+    let reporter = Debugger::new(labels.clone());
+
+    if let Some(port) = args.gdb {
+        let listener = TcpListener::bind(("127.0.0.1", port)).unwrap_or_else(|error| fault(error));
+        println!("Waiting for GDB on 127.0.0.1:{}...", port);
+
+        if let Err(error) = gdb::serve(&mut emulator, &mut Debugger::new(labels), &listener) {
+            fault(error);
         }
-        emulator.print_all_registers();
-        println!("End of program");
+    } else if args.debug {
+        println!("Debugging file {}...", path);
+        emulator.set_recording(true);
+        debug(&mut emulator, Debugger::new(labels));
     } else if args.video {
-        let frame_time = Duration::from_millis(1_000 / 30);
+        // A frame lasts as long as its cycles take at the clock of the CPU
+        let frame_time = Duration::from_secs_f64(args.cycles_per_frame as f64 / apu::CLOCK as f64);
         let mut frame = 0;
+        let keymap = args.keymap.unwrap_or_default();
+        let mut recording = Script::new();
 
         emulator.start_window();
 
@@ -109,16 +475,35 @@ fn main() {
             let start = Instant::now();
 
             // 1. Fetch the inputs
-            // TODO
+            let buttons = match &script {
+                Some(script) => script.buttons(frame),
+                None => emulator.window().buttons(&keymap),
+            };
+            recording.record(frame, buttons);
+            emulator.set_buttons(buttons);
 
-            // 2. Execute the program
-            emulator.reset_next_instruction(); // Set program counter to the start of the program
-            emulator.execute_all();
+            // 2. Run the program for a frame, up to the vertical blank
+            let result = emulator.run_frame(args.cycles_per_frame);
+            save_trace(&mut emulator, &mut trace);
+            if let Err(error) = result {
+                fault(error);
+            }
+            print_warnings(&mut emulator);
+            if emulator.out_of_cycles() {
+                break;
+            }
 
             // 3. Update the Video buffer
             emulator.render();
 
-            // 4. Wait for the frame time
+            // 4. Play the sound of the frame
+            let samples = emulator.take_samples();
+            emulator.play(&samples);
+            if args.audio.is_some() {
+                sound.extend(samples);
+            }
+
+            // 5. Wait for the frame time
             let end = Instant::now();
 
             let time_elapsed = end - start;
@@ -129,11 +514,81 @@ fn main() {
 
             frame += 1;
         }
+        recording.finish(frame);
+
+        if let Some(path) = &args.record_input {
+            if let Err(error) = std::fs::write(path, recording.to_string()) {
+                fault(error);
+            }
+        }
+    } else if args.headless {
+        let frames = args
+            .frames
+            .or(script.as_ref().and_then(Script::frames))
+            .unwrap_or(1);
+
+        for frame in 0..frames {
+            if let Some(script) = &script {
+                emulator.set_buttons(script.buttons(frame));
+            }
+
+            let result = emulator.run_frame(args.cycles_per_frame);
+            save_trace(&mut emulator, &mut trace);
+            if let Err(error) = result {
+                fault(error);
+            }
+            print_warnings(&mut emulator);
+
+            let samples = emulator.take_samples();
+            if args.audio.is_some() {
+                sound.extend(samples);
+            }
+
+            if emulator.out_of_cycles() {
+                break;
+            }
+        }
+
+        if args.dump_regs {
+            emulator.print_all_registers();
+        }
     } else {
-        emulator.execute_all();
+        let result = emulator.execute_all();
+        save_trace(&mut emulator, &mut trace);
+        if let Err(error) = result {
+            fault(error);
+        }
+        print_warnings(&mut emulator);
 
         if args.dump_regs {
             emulator.print_all_registers();
         }
     }
+
+    if let Some(path) = &args.audio {
+        sound.extend(emulator.take_samples());
+        save_audio(path, &sound);
+    }
+
+    if let Some(path) = &args.screenshot {
+        if let Err(error) = emulator.screenshot().save(path) {
+            fault(format!("{}: {}", path, error));
+        }
+    }
+
+    if let Some(path) = &args.save_state {
+        if let Err(error) = std::fs::write(path, emulator.save_state()) {
+            fault(format!("{}: {}", path, error));
+        }
+    }
+
+    if args.state_hash {
+        println!("State hash: {:016x}", emulator.state_hash());
+    }
+
+    // The outputs above help finding where a runaway program is stuck
+    if emulator.out_of_cycles() && !args.debug && args.gdb.is_none() {
+        report_cycle_limit(&mut emulator, &reporter);
+        std::process::exit(1);
+    }
 }
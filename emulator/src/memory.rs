@@ -0,0 +1,450 @@
+//! Memory map of the Blask handheld.
+//!
+//! The address space is 64K words of 16 bits, addressed by word:
+//!
+//! | Start    | End      | Region                                   |
+//! |----------|----------|------------------------------------------|
+//! | `0x0000` | `0x3FFF` | ROM, the program image, read-only        |
+//! | `0x4000` | `0xBFFF` | RAM                                      |
+//! | `0xC000` | `0xC3FF` | VRAM, the 32x32 framebuffer              |
+//! | `0xC400` | `0xDDFF` | PPU, tiles, maps, sprites and palettes   |
+//! | `0xDE00` | `0xEFFF` | unmapped                                 |
+//! | `0xF000` | `0xFFFF` | MMIO, registers of the peripherals       |
+//!
+//! The MMIO registers answering so far are the ones of the
+//! [interrupt controller](crate::interrupt), of the [gamepad](crate::gamepad),
+//! of the [timer](crate::timer), of the [frame timing](crate::vblank), of the
+//! [PPU](crate::ppu), of the [APU](crate::apu) and of the
+//! [cycle counter](crate::counter), the rest of the region is unmapped.
+//!
+//! Any access to an unmapped address, a write to ROM or an address past the
+//! end of the address space is a [BusFault].
+
+use core::fmt;
+use core::ops::Range;
+
+use crate::apu::{self, Apu};
+use crate::counter::{self, Counter};
+use crate::gamepad::{self, Gamepad};
+use crate::interrupt::{self, InterruptController};
+use crate::ppu::{self, Ppu};
+use crate::state::{StateError, StateReader, StateWriter};
+use crate::timer::{self, Timer};
+use crate::vblank::{self, Vblank};
+
+/// Number of words in the address space.
+pub const ADDRESS_SPACE: usize = 0x1_0000;
+
+pub const ROM: Range<usize> = 0x0000..0x4000;
+pub const RAM: Range<usize> = 0x4000..0xC000;
+pub const VRAM: Range<usize> = 0xC000..0xC000 + VRAM_SIZE;
+pub const MMIO: Range<usize> = 0xF000..ADDRESS_SPACE;
+
+/// Number of words of the framebuffer, one per pixel.
+pub const VRAM_SIZE: usize = 32 * 32;
+
+/// Something the CPU reads and writes words through.
+pub trait Bus {
+    fn read(&mut self, address: usize) -> Result<u16, BusFault>;
+
+    fn write(&mut self, address: usize, value: u16) -> Result<(), BusFault>;
+}
+
+/// A failed access on the [Bus].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusFault {
+    address: usize,
+    kind: BusFaultKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusFaultKind {
+    /// The address is past the end of the address space.
+    OutOfRange,
+    /// Nothing answers at this address.
+    Unmapped,
+    /// The address can only be read.
+    ReadOnly,
+}
+
+impl BusFault {
+    pub fn new(address: usize, kind: BusFaultKind) -> Self {
+        Self { address, kind }
+    }
+
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    pub fn kind(&self) -> BusFaultKind {
+        self.kind
+    }
+}
+
+impl fmt::Display for BusFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.kind {
+            BusFaultKind::OutOfRange => "out of the address space",
+            BusFaultKind::Unmapped => "unmapped",
+            BusFaultKind::ReadOnly => "read-only",
+        };
+
+        write!(f, "bus fault at 0x{:04X}: {}", self.address, reason)
+    }
+}
+
+impl std::error::Error for BusFault {}
+
+/// The regions of the memory map backed by storage, and the peripherals.
+pub struct Memory {
+    rom: Box<[u16]>,
+    ram: Box<[u16]>,
+    vram: Box<[u16; VRAM_SIZE]>,
+    interrupts: InterruptController,
+    gamepad: Gamepad,
+    timer: Timer,
+    vblank: Vblank,
+    ppu: Ppu,
+    apu: Apu,
+    counter: Counter,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self {
+            rom: vec![0; ROM.len()].into_boxed_slice(),
+            ram: vec![0; RAM.len()].into_boxed_slice(),
+            vram: Box::new([0; VRAM_SIZE]),
+            interrupts: InterruptController::new(),
+            gamepad: Gamepad::new(),
+            timer: Timer::new(),
+            vblank: Vblank::new(),
+            ppu: Ppu::new(),
+            apu: Apu::new(),
+            counter: Counter::new(),
+        }
+    }
+
+    /// Copy `image` at `address`, ROM included, as when flashing a cartridge.
+    pub fn load(&mut self, address: usize, image: &[u16]) -> Result<(), BusFault> {
+        for (offset, &word) in image.iter().enumerate() {
+            *self.cell(address + offset)?.0 = word;
+        }
+
+        Ok(())
+    }
+
+    /// Return the framebuffer.
+    pub fn vram(&self) -> &[u16; VRAM_SIZE] {
+        &self.vram
+    }
+
+    /// Write the storage, ROM included, then the state of every peripheral.
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.words(&self.rom);
+        writer.words(&self.ram);
+        writer.words(&self.vram[..]);
+        self.interrupts.save(writer);
+        self.gamepad.save(writer);
+        self.timer.save(writer);
+        self.vblank.save(writer);
+        self.ppu.save(writer);
+        self.apu.save(writer);
+        self.counter.save(writer);
+    }
+
+    /// Restore the state written by [Memory::save].
+    pub fn restore(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.words(&mut self.rom)?;
+        reader.words(&mut self.ram)?;
+        reader.words(&mut self.vram[..])?;
+        self.interrupts.restore(reader)?;
+        self.gamepad.restore(reader)?;
+        self.timer.restore(reader)?;
+        self.vblank.restore(reader)?;
+        self.ppu.restore(reader)?;
+        self.apu.restore(reader)?;
+        self.counter.restore(reader)
+    }
+
+    pub fn interrupts(&self) -> &InterruptController {
+        &self.interrupts
+    }
+
+    pub fn interrupts_mut(&mut self) -> &mut InterruptController {
+        &mut self.interrupts
+    }
+
+    pub fn gamepad(&self) -> &Gamepad {
+        &self.gamepad
+    }
+
+    pub fn gamepad_mut(&mut self) -> &mut Gamepad {
+        &mut self.gamepad
+    }
+
+    pub fn timer(&self) -> &Timer {
+        &self.timer
+    }
+
+    pub fn timer_mut(&mut self) -> &mut Timer {
+        &mut self.timer
+    }
+
+    pub fn vblank(&self) -> &Vblank {
+        &self.vblank
+    }
+
+    pub fn vblank_mut(&mut self) -> &mut Vblank {
+        &mut self.vblank
+    }
+
+    pub fn ppu(&self) -> &Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut Ppu {
+        &mut self.ppu
+    }
+
+    pub fn apu(&self) -> &Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut Apu {
+        &mut self.apu
+    }
+
+    pub fn counter(&self) -> &Counter {
+        &self.counter
+    }
+
+    pub fn counter_mut(&mut self) -> &mut Counter {
+        &mut self.counter
+    }
+
+    /// Read the word at `address` as [Bus::read] does, without the side effects
+    /// of reading, for the debugger.
+    pub fn peek(&mut self, address: usize) -> Result<u16, BusFault> {
+        if counter::REGISTERS.contains(&address) {
+            return Ok(self.counter.peek(address));
+        }
+
+        self.read(address)
+    }
+
+    /// Return the word at `address` and whether it can be written.
+    fn cell(&mut self, address: usize) -> Result<(&mut u16, bool), BusFault> {
+        let writable = !ROM.contains(&address);
+
+        let cell = if ROM.contains(&address) {
+            &mut self.rom[address - ROM.start]
+        } else if RAM.contains(&address) {
+            &mut self.ram[address - RAM.start]
+        } else if VRAM.contains(&address) {
+            &mut self.vram[address - VRAM.start]
+        } else if ppu::MEMORY.contains(&address) {
+            &mut self.ppu.memory_mut()[address - ppu::MEMORY.start]
+        } else if address < ADDRESS_SPACE {
+            return Err(BusFault::new(address, BusFaultKind::Unmapped));
+        } else {
+            return Err(BusFault::new(address, BusFaultKind::OutOfRange));
+        };
+
+        Ok((cell, writable))
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for Memory {
+    fn read(&mut self, address: usize) -> Result<u16, BusFault> {
+        if interrupt::REGISTERS.contains(&address) {
+            return Ok(self.interrupts.read(address));
+        }
+
+        if address == gamepad::GAMEPAD {
+            return Ok(self.gamepad.read());
+        }
+
+        if timer::REGISTERS.contains(&address) {
+            return Ok(self.timer.read(address));
+        }
+
+        if vblank::REGISTERS.contains(&address) {
+            return Ok(self.vblank.read(address));
+        }
+
+        if ppu::REGISTERS.contains(&address) {
+            return Ok(self.ppu.read(address));
+        }
+
+        if apu::REGISTERS.contains(&address) {
+            return Ok(self.apu.read(address));
+        }
+
+        if counter::REGISTERS.contains(&address) {
+            return Ok(self.counter.read(address));
+        }
+
+        self.cell(address).map(|(cell, _)| *cell)
+    }
+
+    fn write(&mut self, address: usize, value: u16) -> Result<(), BusFault> {
+        if interrupt::REGISTERS.contains(&address) {
+            self.interrupts.write(address, value);
+            return Ok(());
+        }
+
+        if address == gamepad::GAMEPAD {
+            return Err(BusFault::new(address, BusFaultKind::ReadOnly));
+        }
+
+        if timer::REGISTERS.contains(&address) {
+            self.timer.write(address, value);
+            return Ok(());
+        }
+
+        if address == vblank::STATUS {
+            self.vblank.write_status(value);
+            return Ok(());
+        }
+
+        if address == vblank::FRAMES {
+            return Err(BusFault::new(address, BusFaultKind::ReadOnly));
+        }
+
+        if ppu::REGISTERS.contains(&address) {
+            self.ppu.write(address, value);
+            return Ok(());
+        }
+
+        if apu::REGISTERS.contains(&address) {
+            self.apu.write(address, value);
+            return Ok(());
+        }
+
+        if counter::REGISTERS.contains(&address) {
+            return Err(BusFault::new(address, BusFaultKind::ReadOnly));
+        }
+
+        match self.cell(address)? {
+            (cell, true) => {
+                *cell = value;
+                Ok(())
+            }
+            (_, false) => Err(BusFault::new(address, BusFaultKind::ReadOnly)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_do_not_overlap() {
+        let regions = [ROM, RAM, VRAM, ppu::MEMORY, MMIO];
+
+        for pair in regions.windows(2) {
+            assert!(pair[0].end <= pair[1].start);
+        }
+        assert_eq!(MMIO.end, ADDRESS_SPACE);
+    }
+
+    #[test]
+    fn ram_and_vram() {
+        let mut memory = Memory::new();
+
+        memory.write(RAM.start, 1).unwrap();
+        memory.write(RAM.end - 1, 2).unwrap();
+        memory.write(VRAM.start + 33, 0xF00).unwrap();
+
+        assert_eq!(memory.read(RAM.start), Ok(1));
+        assert_eq!(memory.read(RAM.end - 1), Ok(2));
+        assert_eq!(memory.vram()[33], 0xF00);
+    }
+
+    #[test]
+    fn rom_is_read_only() {
+        let mut memory = Memory::new();
+        memory.load(ROM.start, &[7, 8]).unwrap();
+
+        assert_eq!(memory.read(1), Ok(8));
+        assert_eq!(
+            memory.write(1, 0),
+            Err(BusFault::new(1, BusFaultKind::ReadOnly))
+        );
+        assert_eq!(memory.read(1), Ok(8));
+    }
+
+    #[test]
+    fn faults() {
+        let mut memory = Memory::new();
+
+        assert_eq!(
+            memory.read(ppu::MEMORY.end),
+            Err(BusFault::new(ppu::MEMORY.end, BusFaultKind::Unmapped))
+        );
+        assert_eq!(
+            memory.write(interrupt::REGISTERS.end, 0),
+            Err(BusFault::new(
+                interrupt::REGISTERS.end,
+                BusFaultKind::Unmapped
+            ))
+        );
+        assert_eq!(
+            memory.load(ppu::MEMORY.end - 1, &[1, 2]),
+            Err(BusFault::new(ppu::MEMORY.end, BusFaultKind::Unmapped))
+        );
+        assert_eq!(
+            memory.read(ADDRESS_SPACE),
+            Err(BusFault::new(ADDRESS_SPACE, BusFaultKind::OutOfRange))
+        );
+    }
+
+    #[test]
+    fn interrupt_registers() {
+        let mut memory = Memory::new();
+
+        memory.write(interrupt::ENABLE, 0b101).unwrap();
+        memory.interrupts_mut().raise(interrupt::Interrupt::Timer);
+
+        assert_eq!(memory.read(interrupt::ENABLE), Ok(0b101));
+        assert_eq!(memory.read(interrupt::PENDING), Ok(0b010));
+    }
+
+    #[test]
+    fn gamepad_register() {
+        let mut memory = Memory::new();
+
+        memory
+            .gamepad_mut()
+            .set_buttons(gamepad::Buttons::from_bits(0b1001));
+
+        assert_eq!(memory.read(gamepad::GAMEPAD), Ok(0b1001));
+        assert_eq!(
+            memory.write(gamepad::GAMEPAD, 0),
+            Err(BusFault::new(gamepad::GAMEPAD, BusFaultKind::ReadOnly))
+        );
+    }
+
+    #[test]
+    fn vblank_registers() {
+        let mut memory = Memory::new();
+
+        memory.vblank_mut().blank();
+        memory.write(vblank::STATUS, vblank::VBLANK).unwrap();
+
+        assert_eq!(memory.read(vblank::STATUS), Ok(0));
+        assert_eq!(memory.read(vblank::FRAMES), Ok(1));
+        assert_eq!(
+            memory.write(vblank::FRAMES, 0),
+            Err(BusFault::new(vblank::FRAMES, BusFaultKind::ReadOnly))
+        );
+    }
+}
@@ -0,0 +1,379 @@
+//! Picture processing unit of the Blask handheld.
+//!
+//! The PPU draws the screen either from the 32x32 framebuffer in VRAM, or
+//! from tiles: two scrolling background layers and 64 sprites, all made of
+//! 8x8 tiles. Its memory sits right after VRAM:
+//!
+//! | Start    | End      | Region                                          |
+//! |----------|----------|-------------------------------------------------|
+//! | `0xC400` | `0xD3FF` | patterns, 256 tiles of 16 words                 |
+//! | `0xD400` | `0xD7FF` | background 0 map, 32x32 entries                 |
+//! | `0xD800` | `0xDBFF` | background 1 map, 32x32 entries                 |
+//! | `0xDC00` | `0xDCFF` | sprite attributes, 64 sprites of 4 words        |
+//! | `0xDD00` | `0xDDFF` | palettes, 16 palettes of 16 `0x0RGB` colors     |
+//!
+//! A tile is 8 rows of 2 words, 4 bits per pixel, the leftmost pixel in the
+//! high bits: each pixel is a color index in a palette. Index 0 is
+//! transparent, except for the backdrop, color 0 of palette 0.
+//!
+//! A map entry and the third word of a sprite hold the tile in bits 0-7, the
+//! palette in bits 8-11 and the horizontal and vertical flips in bits 12 and
+//! 13. A sprite is then its `x` and `y` on screen in its first two words,
+//! with bit 14 of the third word drawing it behind the backgrounds and bit 15
+//! showing it. Its fourth word is unused.
+//!
+//! The PPU is set up through MMIO registers:
+//!
+//! | Address  | Register  | Access                                                      |
+//! |----------|-----------|-------------------------------------------------------------|
+//! | `0xF040` | `CONTROL` | bit 0 tile mode, bits 1 to 3 show backgrounds 0, 1, sprites |
+//! | `0xF041` | `SCREEN`  | width in tiles in the low byte, height in the high byte     |
+//! | `0xF042` | `SCROLL`  | background 0 `x` and `y`, then background 1 `x` and `y`     |
+//!
+//! The background maps are 256x256 pixels and wrap around while scrolling.
+//! Background 1 is drawn over background 0. Among sprites, the lower index is
+//! drawn on top, and a sprite behind the backgrounds only shows where they
+//! are transparent.
+
+use core::ops::Range;
+
+use crate::memory::VRAM_SIZE;
+use crate::screen::Screenshot;
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// Memory of the PPU.
+pub const MEMORY: Range<usize> = 0xC400..0xDE00;
+
+pub const PATTERNS: Range<usize> = 0xC400..0xD400;
+pub const BACKGROUNDS: [Range<usize>; 2] = [0xD400..0xD800, 0xD800..0xDC00];
+pub const SPRITES: Range<usize> = 0xDC00..0xDD00;
+pub const PALETTES: Range<usize> = 0xDD00..0xDE00;
+
+/// MMIO registers of the PPU.
+pub const REGISTERS: Range<usize> = 0xF040..0xF046;
+
+pub const CONTROL: usize = REGISTERS.start;
+pub const SCREEN: usize = REGISTERS.start + 1;
+/// Scroll `x` of background `n` at `SCROLL + 2 * n`, `y` right after it.
+pub const SCROLL: usize = REGISTERS.start + 2;
+
+/// Bit of `CONTROL` drawing from tiles instead of the framebuffer.
+pub const TILE_MODE: u16 = 1;
+/// Bits of `CONTROL` showing background 0 and 1.
+pub const SHOW_BACKGROUND: [u16; 2] = [1 << 1, 1 << 2];
+/// Bit of `CONTROL` showing the sprites.
+pub const SHOW_SPRITES: u16 = 1 << 3;
+
+/// Bits of a map entry or sprite flipping the tile horizontally and vertically.
+pub const FLIP_X: u16 = 1 << 12;
+pub const FLIP_Y: u16 = 1 << 13;
+/// Bit of a sprite drawing it behind the backgrounds.
+pub const BEHIND: u16 = 1 << 14;
+/// Bit of a sprite showing it.
+pub const VISIBLE: u16 = 1 << 15;
+
+/// Width and height of a tile in pixels.
+pub const TILE_SIZE: usize = 8;
+/// Width and height of a background map in tiles.
+pub const MAP_SIZE: usize = 32;
+pub const SPRITE_COUNT: usize = 64;
+
+const TILE_WORDS: usize = 16;
+const SPRITE_WORDS: usize = 4;
+const PALETTE_SIZE: usize = 16;
+
+/// Screen of 20x18 tiles, 160x144 pixels, until the program sets `SCREEN`.
+const DEFAULT_SCREEN: u16 = 18 << 8 | 20;
+
+/// Side of the framebuffer in pixels.
+const FRAMEBUFFER_SIZE: usize = 32;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ppu {
+    memory: Box<[u16]>,
+    control: u16,
+    screen: u16,
+    scroll: [u16; 4],
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A sprite shown on screen.
+struct Sprite {
+    x: u16,
+    y: u16,
+    entry: u16,
+}
+
+impl Ppu {
+    pub fn new() -> Self {
+        Self {
+            memory: vec![0; MEMORY.len()].into_boxed_slice(),
+            control: 0,
+            screen: DEFAULT_SCREEN,
+            scroll: [0; 4],
+        }
+    }
+
+    /// Return the memory of the PPU, from [PATTERNS] to [PALETTES].
+    pub fn memory_mut(&mut self) -> &mut [u16] {
+        &mut self.memory
+    }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.words(&self.memory);
+        writer.u16(self.control);
+        writer.u16(self.screen);
+        writer.words(&self.scroll);
+    }
+
+    pub fn restore(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        reader.words(&mut self.memory)?;
+        self.control = reader.u16()?;
+        self.screen = reader.u16()?;
+        reader.words(&mut self.scroll)
+    }
+
+    fn word(&self, address: usize) -> u16 {
+        self.memory[address - MEMORY.start]
+    }
+
+    /// Read the register at `address`, one of [REGISTERS].
+    pub fn read(&self, address: usize) -> u16 {
+        match address {
+            CONTROL => self.control,
+            SCREEN => self.screen,
+            _ if REGISTERS.contains(&address) => self.scroll[address - SCROLL],
+            _ => unreachable!("0x{address:04X} is not a PPU register"),
+        }
+    }
+
+    /// Write the register at `address`, one of [REGISTERS].
+    pub fn write(&mut self, address: usize, value: u16) {
+        match address {
+            CONTROL => self.control = value,
+            SCREEN => self.screen = value,
+            _ if REGISTERS.contains(&address) => self.scroll[address - SCROLL] = value,
+            _ => unreachable!("0x{address:04X} is not a PPU register"),
+        }
+    }
+
+    /// Return the width and height of the screen in pixels.
+    ///
+    /// Each side of the tile mode screen is 1 to 32 tiles.
+    pub fn resolution(&self) -> (usize, usize) {
+        if self.control & TILE_MODE == 0 {
+            return (FRAMEBUFFER_SIZE, FRAMEBUFFER_SIZE);
+        }
+
+        let tiles = |value: u16| (value as usize).clamp(1, MAP_SIZE);
+
+        (
+            tiles(self.screen & 0xFF) * TILE_SIZE,
+            tiles(self.screen >> 8) * TILE_SIZE,
+        )
+    }
+
+    /// Draw the screen, from `vram` unless in tile mode.
+    pub fn render(&self, vram: &[u16; VRAM_SIZE]) -> Screenshot {
+        let (width, height) = self.resolution();
+
+        if self.control & TILE_MODE == 0 {
+            return Screenshot::new(width, height, vram);
+        }
+
+        let sprites: Vec<Sprite> = (0..SPRITE_COUNT)
+            .map(|index| {
+                let address = SPRITES.start + index * SPRITE_WORDS;
+
+                Sprite {
+                    x: self.word(address),
+                    y: self.word(address + 1),
+                    entry: self.word(address + 2),
+                }
+            })
+            .filter(|sprite| sprite.entry & VISIBLE != 0)
+            .collect();
+
+        let mut colors = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                colors.push(self.pixel(&sprites, x as u16, y as u16));
+            }
+        }
+
+        Screenshot::new(width, height, &colors)
+    }
+
+    /// Return the color of the screen at `x`, `y` in tile mode.
+    fn pixel(&self, sprites: &[Sprite], x: u16, y: u16) -> u16 {
+        let background = [1, 0]
+            .into_iter()
+            .filter(|layer| self.control & SHOW_BACKGROUND[*layer] != 0)
+            .find_map(|layer| self.background(layer, x, y));
+
+        let sprite = sprites
+            .iter()
+            .filter(|_| self.control & SHOW_SPRITES != 0)
+            .find_map(|sprite| {
+                let dx = x.wrapping_sub(sprite.x) as usize;
+                let dy = y.wrapping_sub(sprite.y) as usize;
+
+                if dx >= TILE_SIZE || dy >= TILE_SIZE {
+                    return None;
+                }
+
+                self.tile(sprite.entry, dx, dy)
+                    .map(|color| (color, sprite.entry & BEHIND != 0))
+            });
+
+        match (sprite, background) {
+            (Some((color, false)), _) | (Some((color, true)), None) => color,
+            (_, Some(color)) => color,
+            (None, None) => self.word(PALETTES.start),
+        }
+    }
+
+    /// Return the color of background `layer` at `x`, `y` on screen, `None` if transparent.
+    fn background(&self, layer: usize, x: u16, y: u16) -> Option<u16> {
+        let side = (MAP_SIZE * TILE_SIZE) as u16;
+        let x = (x.wrapping_add(self.scroll[2 * layer]) % side) as usize;
+        let y = (y.wrapping_add(self.scroll[2 * layer + 1]) % side) as usize;
+
+        let entry =
+            self.word(BACKGROUNDS[layer].start + (y / TILE_SIZE) * MAP_SIZE + x / TILE_SIZE);
+
+        self.tile(entry, x % TILE_SIZE, y % TILE_SIZE)
+    }
+
+    /// Return the color at `x`, `y` in the tile of a map entry or sprite, `None` if transparent.
+    fn tile(&self, entry: u16, x: usize, y: usize) -> Option<u16> {
+        let x = if entry & FLIP_X != 0 {
+            TILE_SIZE - 1 - x
+        } else {
+            x
+        };
+        let y = if entry & FLIP_Y != 0 {
+            TILE_SIZE - 1 - y
+        } else {
+            y
+        };
+
+        let tile = (entry & 0xFF) as usize;
+        let word = self.word(PATTERNS.start + tile * TILE_WORDS + y * 2 + x / 4);
+        let index = (word >> (12 - 4 * (x % 4))) & 0xF;
+
+        if index == 0 {
+            return None;
+        }
+
+        let palette = ((entry >> 8) & 0xF) as usize;
+        Some(self.word(PALETTES.start + palette * PALETTE_SIZE + index as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: u16 = 0x0F00;
+    const GREEN: u16 = 0x00F0;
+    const BLUE: u16 = 0x000F;
+
+    fn set(ppu: &mut Ppu, address: usize, value: u16) {
+        ppu.memory_mut()[address - MEMORY.start] = value;
+    }
+
+    /// A PPU in tile mode with a 1x1 tile screen, palette 0 black, red and
+    /// green, palette 1 blue, and tile 1 with its top left pixel at color 1.
+    fn ppu(control: u16) -> Ppu {
+        let mut ppu = Ppu::new();
+        ppu.write(CONTROL, TILE_MODE | control);
+        ppu.write(SCREEN, 1 << 8 | 1);
+
+        set(&mut ppu, PALETTES.start + 1, RED);
+        set(&mut ppu, PALETTES.start + 2, GREEN);
+        set(&mut ppu, PALETTES.start + PALETTE_SIZE + 1, BLUE);
+        set(&mut ppu, PATTERNS.start + TILE_WORDS, 0x1000);
+
+        ppu
+    }
+
+    fn color(ppu: &Ppu, x: u16, y: u16) -> u16 {
+        let (width, _) = ppu.resolution();
+        let pixels = ppu.render(&[0; VRAM_SIZE]);
+        let offset = (y as usize * width + x as usize) * 3;
+
+        match pixels.pixels()[offset..offset + 3] {
+            [r, g, b] => (r as u16 >> 4) << 8 | (g as u16 >> 4) << 4 | b as u16 >> 4,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn resolution() {
+        let mut ppu = Ppu::new();
+        assert_eq!(ppu.resolution(), (32, 32));
+
+        ppu.write(CONTROL, TILE_MODE);
+        assert_eq!(ppu.resolution(), (160, 144));
+
+        ppu.write(SCREEN, 40 << 8);
+        assert_eq!(ppu.resolution(), (8, 256));
+    }
+
+    #[test]
+    fn flips_and_palettes() {
+        let mut ppu = ppu(SHOW_BACKGROUND[0]);
+
+        set(&mut ppu, BACKGROUNDS[0].start, 1);
+        assert_eq!(color(&ppu, 0, 0), RED);
+        assert_eq!(color(&ppu, 7, 7), 0);
+
+        set(&mut ppu, BACKGROUNDS[0].start, FLIP_X | FLIP_Y | 1 << 8 | 1);
+        assert_eq!(color(&ppu, 0, 0), 0);
+        assert_eq!(color(&ppu, 7, 7), BLUE);
+    }
+
+    #[test]
+    fn scrolling_wraps_around() {
+        let mut ppu = ppu(SHOW_BACKGROUND[1]);
+
+        set(&mut ppu, BACKGROUNDS[1].end - 1, 1);
+        ppu.write(SCROLL + 2, 248);
+        ppu.write(SCROLL + 3, 248);
+
+        assert_eq!(color(&ppu, 0, 0), RED);
+    }
+
+    #[test]
+    fn layers_and_priority() {
+        let mut ppu = ppu(SHOW_BACKGROUND[0] | SHOW_BACKGROUND[1] | SHOW_SPRITES);
+
+        // Background 0 is green everywhere, background 1 is red at the top left.
+        set(&mut ppu, PATTERNS.start + 2 * TILE_WORDS, 0x2222);
+        set(&mut ppu, BACKGROUNDS[0].start, 2);
+        set(&mut ppu, BACKGROUNDS[1].start, 1);
+        assert_eq!(color(&ppu, 0, 0), RED);
+        assert_eq!(color(&ppu, 1, 0), GREEN);
+
+        // Sprite 1 is hidden by sprite 0, both blue and red at (1, 0).
+        set(&mut ppu, SPRITES.start, 1);
+        set(&mut ppu, SPRITES.start + 2, VISIBLE | 1 << 8 | 1);
+        set(&mut ppu, SPRITES.start + SPRITE_WORDS, 1);
+        set(&mut ppu, SPRITES.start + SPRITE_WORDS + 2, VISIBLE | 1);
+        assert_eq!(color(&ppu, 1, 0), BLUE);
+
+        // Behind an opaque background, the sprite does not show.
+        set(&mut ppu, SPRITES.start + 2, VISIBLE | BEHIND | 1 << 8 | 1);
+        assert_eq!(color(&ppu, 1, 0), GREEN);
+
+        ppu.write(CONTROL, TILE_MODE | SHOW_SPRITES);
+        assert_eq!(color(&ppu, 1, 0), BLUE);
+    }
+}
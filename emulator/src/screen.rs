@@ -0,0 +1,119 @@
+//! Screen decoding, independent of any window.
+//!
+//! The [PPU](crate::ppu) draws the screen row by row, one word per pixel in
+//! the `0x0RGB` format, 4 bits per channel. A [Screenshot] decodes it to 8-bit
+//! RGB, to be drawn in the window or saved as a PNG or PPM image.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Decode a `0x0RGB` pixel to 8-bit channels, the 4 bits are the high ones.
+pub fn rgb(pixel: u16) -> [u8; 3] {
+    let channel = |shift: u16| ((pixel >> shift) & 0xF) as u8 * 0x10;
+
+    [channel(8), channel(4), channel(0)]
+}
+
+/// The screen decoded to 8-bit RGB, row by row from the top left corner.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Screenshot {
+    width: usize,
+    height: usize,
+    pixels: Vec<u8>,
+}
+
+impl Screenshot {
+    /// Decode a screen of `width` by `height` `colors`, row by row.
+    pub fn new(width: usize, height: usize, colors: &[u16]) -> Self {
+        assert_eq!(
+            colors.len(),
+            width * height,
+            "the screen is not {width}x{height}"
+        );
+
+        Self {
+            width,
+            height,
+            pixels: colors.iter().flat_map(|color| rgb(*color)).collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Return the red, green and blue bytes of each pixel.
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Write the screenshot as a binary PPM image.
+    pub fn write_ppm(&self, mut writer: impl Write) -> io::Result<()> {
+        write!(writer, "P6\n{} {}\n255\n", self.width, self.height)?;
+        writer.write_all(&self.pixels)
+    }
+
+    /// Write the screenshot as a PNG image.
+    pub fn write_png(&self, writer: impl Write) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        encoder.write_header()?.write_image_data(&self.pixels)?;
+
+        Ok(())
+    }
+
+    /// Save the screenshot to `path`, as a PPM image if it ends with `.ppm`
+    /// and as a PNG image otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        if path.extension().is_some_and(|extension| extension == "ppm") {
+            self.write_ppm(&mut writer)?;
+        } else {
+            self.write_png(&mut writer)?;
+        }
+
+        writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert_eq!(rgb(0x0F00), [0xF0, 0x00, 0x00]);
+        assert_eq!(rgb(0x0D98), [0xD0, 0x90, 0x80]);
+        assert_eq!(rgb(0xF00F), [0x00, 0x00, 0xF0]);
+
+        let mut colors = [0; 4 * 2];
+        colors[4 + 1] = 0x0FFF;
+        let screenshot = Screenshot::new(4, 2, &colors);
+
+        assert_eq!(screenshot.pixels().len(), 4 * 2 * 3);
+        assert_eq!(screenshot.pixels()[(4 + 1) * 3..][..3], [0xF0; 3]);
+    }
+
+    #[test]
+    fn ppm_and_png() {
+        let screenshot = Screenshot::new(32, 16, &[0x0F00; 32 * 16]);
+
+        let mut ppm = Vec::new();
+        screenshot.write_ppm(&mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n32 16\n255\n\xF0\x00\x00"));
+        assert_eq!(ppm.len(), 13 + 32 * 16 * 3);
+
+        let mut png = Vec::new();
+        screenshot.write_png(&mut png).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+    }
+}
@@ -0,0 +1,223 @@
+//! Save states, snapshots of the whole machine.
+//!
+//! A save state holds the registers, `pc`, the flags, the memory, ROM
+//! included, and the state of every peripheral: the interrupt controller with
+//! its pending lines, the gamepad, the timer, the frame timing, the PPU, the
+//! APU and the cycle counter with the cycles run. Restoring it resumes the program exactly
+//! where it was saved, so with the same [input script](crate::input) a run
+//! from a save state is replayed bit for bit. The samples the APU produced
+//! and nobody took yet are output, not state, and are dropped.
+//!
+//! The file starts with [MAGIC] and the [VERSION] of the format, as a `u16`,
+//! followed by the values of [Emulator::save_state](crate::Emulator::save_state)
+//! in order, all little-endian. A save state of another version is refused.
+
+use core::fmt;
+
+/// First bytes of a save state.
+pub const MAGIC: &[u8; 4] = b"BLSS";
+
+/// Version of the format, raised whenever the state saved changes.
+pub const VERSION: u16 = 2;
+
+/// A save state that could not be restored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StateError {
+    /// The data does not start with [MAGIC].
+    NotAState,
+    /// The save state is of another version of the format.
+    Version(u16),
+    /// The data ends in the middle of the state.
+    Truncated,
+    /// The data goes on after the state.
+    TrailingData,
+    /// A value is out of its range.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::NotAState => write!(f, "not a save state"),
+            StateError::Version(version) => write!(
+                f,
+                "save state of version {version}, expected version {VERSION}"
+            ),
+            StateError::Truncated => write!(f, "the save state is truncated"),
+            StateError::TrailingData => write!(f, "the save state goes on after its end"),
+            StateError::Invalid(what) => write!(f, "invalid {what} in the save state"),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
+
+/// Writes the values of a save state after its header.
+pub struct StateWriter {
+    bytes: Vec<u8>,
+}
+
+impl Default for StateWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_le_bytes());
+
+        Self { bytes }
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.bytes.push(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    pub fn words(&mut self, words: &[u16]) {
+        for word in words {
+            self.u16(*word);
+        }
+    }
+
+    /// Return the save state.
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Reads the values of a save state, in the order they were written.
+pub struct StateReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> StateReader<'a> {
+    /// Check the header of the save state `bytes`.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, StateError> {
+        let Some(bytes) = bytes.strip_prefix(MAGIC) else {
+            return Err(StateError::NotAState);
+        };
+
+        let mut reader = Self { bytes };
+        match reader.u16()? {
+            VERSION => Ok(reader),
+            version => Err(StateError::Version(version)),
+        }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let (value, rest) = self
+            .bytes
+            .split_first_chunk()
+            .ok_or(StateError::Truncated)?;
+        self.bytes = rest;
+
+        Ok(*value)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.take::<1>()? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(StateError::Invalid("boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    /// Fill `words` with the next words.
+    pub fn words(&mut self, words: &mut [u16]) -> Result<(), StateError> {
+        for word in words {
+            *word = self.u16()?;
+        }
+
+        Ok(())
+    }
+
+    /// Check that the whole save state was read.
+    pub fn finish(self) -> Result<(), StateError> {
+        match self.bytes.is_empty() {
+            true => Ok(()),
+            false => Err(StateError::TrailingData),
+        }
+    }
+}
+
+/// Hash a save state with 64-bit FNV-1a, stable from one run to the next.
+pub fn hash(state: &[u8]) -> u64 {
+    state.iter().fold(0xCBF2_9CE4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01B3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_and_errors() {
+        let mut writer = StateWriter::new();
+        writer.bool(true);
+        writer.u16(0x1234);
+        writer.u32(0x89AB_CDEF);
+        writer.u64(u64::MAX - 1);
+        writer.words(&[1, 2, 3]);
+        let state = writer.finish();
+
+        let mut reader = StateReader::new(&state).unwrap();
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u16(), Ok(0x1234));
+        assert_eq!(reader.u32(), Ok(0x89AB_CDEF));
+        assert_eq!(reader.u64(), Ok(u64::MAX - 1));
+        let mut words = [0; 3];
+        assert_eq!(reader.words(&mut words), Ok(()));
+        assert_eq!(words, [1, 2, 3]);
+        assert_eq!(reader.finish(), Ok(()));
+
+        let mut reader = StateReader::new(&state[..state.len() - 1]).unwrap();
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.words(&mut [0; 10]), Err(StateError::Truncated));
+
+        let mut invalid = state.clone();
+        invalid[MAGIC.len() + 2] = 2;
+        assert_eq!(
+            StateReader::new(&invalid).unwrap().bool(),
+            Err(StateError::Invalid("boolean"))
+        );
+
+        let mut old = state.clone();
+        old[4] = 0;
+        assert_eq!(StateReader::new(&old).err(), Some(StateError::Version(0)));
+        assert_eq!(StateReader::new(b"RIFF").err(), Some(StateError::NotAState));
+        assert_eq!(
+            StateReader::new(&state).unwrap().finish(),
+            Err(StateError::TrailingData)
+        );
+
+        assert_eq!(hash(b""), 0xCBF2_9CE4_8422_2325);
+        assert_eq!(hash(b"a"), 0xAF63_DC4C_8601_EC8C);
+    }
+}
@@ -0,0 +1,183 @@
+//! Programmable timer of the Blask handheld.
+//!
+//! The timer is driven by the CPU clock, in the cycles of the
+//! [timing model](crate::timing), so a program sees the same counts on every
+//! run. It is set up through
+//! four MMIO registers:
+//!
+//! | Address  | Register    | Access                                              |
+//! |----------|-------------|-----------------------------------------------------|
+//! | `0xF020` | `COUNT`     | counter, read and write                             |
+//! | `0xF021` | `COMPARE`   | the counter restarts from 0 when it reaches it      |
+//! | `0xF022` | `PRESCALER` | the counter advances every `PRESCALER + 1` cycles   |
+//! | `0xF023` | `CONTROL`   | bit 0 runs the timer, bit 1 interrupts on a match   |
+//!
+//! A match raises the [timer interrupt](crate::interrupt::Interrupt::Timer)
+//! when bit 1 of `CONTROL` is set. With `COMPARE` at 0, the counter matches
+//! when it wraps around.
+
+use core::ops::Range;
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// MMIO registers of the timer.
+pub const REGISTERS: Range<usize> = 0xF020..0xF024;
+
+pub const COUNT: usize = REGISTERS.start;
+pub const COMPARE: usize = REGISTERS.start + 1;
+pub const PRESCALER: usize = REGISTERS.start + 2;
+pub const CONTROL: usize = REGISTERS.start + 3;
+
+/// Bit of `CONTROL` running the timer.
+pub const RUN: u16 = 1;
+/// Bit of `CONTROL` raising the interrupt on a match.
+pub const INTERRUPT: u16 = 1 << 1;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Timer {
+    count: u16,
+    compare: u16,
+    prescaler: u16,
+    control: u16,
+    /// Cycles since the counter last advanced.
+    divider: u16,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.words(&[
+            self.count,
+            self.compare,
+            self.prescaler,
+            self.control,
+            self.divider,
+        ]);
+    }
+
+    pub fn restore(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        let mut words = [0; 5];
+        reader.words(&mut words)?;
+        [
+            self.count,
+            self.compare,
+            self.prescaler,
+            self.control,
+            self.divider,
+        ] = words;
+
+        Ok(())
+    }
+
+    /// Indicates if the timer runs and interrupts on a match.
+    pub fn armed(&self) -> bool {
+        self.control & (RUN | INTERRUPT) == RUN | INTERRUPT
+    }
+
+    /// Advance the timer by one cycle, return whether it raises its interrupt.
+    pub fn tick(&mut self) -> bool {
+        if self.control & RUN == 0 {
+            return false;
+        }
+
+        if self.divider < self.prescaler {
+            self.divider += 1;
+            return false;
+        }
+
+        self.divider = 0;
+        self.count = self.count.wrapping_add(1);
+
+        if self.count != self.compare {
+            return false;
+        }
+
+        self.count = 0;
+        self.control & INTERRUPT != 0
+    }
+
+    /// Read the register at `address`, one of [REGISTERS].
+    pub fn read(&self, address: usize) -> u16 {
+        match address {
+            COUNT => self.count,
+            COMPARE => self.compare,
+            PRESCALER => self.prescaler,
+            CONTROL => self.control,
+            _ => unreachable!("0x{address:04X} is not a timer register"),
+        }
+    }
+
+    /// Write the register at `address`, one of [REGISTERS].
+    ///
+    /// Writing `COUNT` or `PRESCALER` restarts the prescaler.
+    pub fn write(&mut self, address: usize, value: u16) {
+        match address {
+            COUNT => self.count = value,
+            COMPARE => self.compare = value,
+            PRESCALER => self.prescaler = value,
+            CONTROL => self.control = value & (RUN | INTERRUPT),
+            _ => unreachable!("0x{address:04X} is not a timer register"),
+        }
+
+        if matches!(address, COUNT | PRESCALER) {
+            self.divider = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ticks(timer: &mut Timer, cycles: usize) -> usize {
+        (0..cycles).filter(|_| timer.tick()).count()
+    }
+
+    #[test]
+    fn stopped() {
+        let mut timer = Timer::new();
+
+        assert_eq!(ticks(&mut timer, 10), 0);
+        assert_eq!(timer.read(COUNT), 0);
+    }
+
+    #[test]
+    fn prescaler() {
+        let mut timer = Timer::new();
+        timer.write(PRESCALER, 3);
+        timer.write(CONTROL, RUN);
+
+        ticks(&mut timer, 10);
+
+        assert_eq!(timer.read(COUNT), 2);
+    }
+
+    #[test]
+    fn match_restarts_and_interrupts() {
+        let mut timer = Timer::new();
+        timer.write(COMPARE, 5);
+        timer.write(CONTROL, RUN | INTERRUPT);
+
+        assert_eq!(ticks(&mut timer, 4), 0);
+        assert!(timer.tick());
+        assert_eq!(timer.read(COUNT), 0);
+        assert_eq!(ticks(&mut timer, 20), 4);
+
+        timer.write(CONTROL, RUN);
+        assert_eq!(ticks(&mut timer, 20), 0);
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut timer = Timer::new();
+        timer.write(COUNT, 0xFFFE);
+        timer.write(CONTROL, RUN | INTERRUPT);
+
+        assert!(!timer.tick());
+        assert!(timer.tick());
+        assert_eq!(timer.read(COUNT), 0);
+    }
+}
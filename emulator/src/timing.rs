@@ -0,0 +1,233 @@
+//! Timing model of the CPU, the cycles each step takes.
+//!
+//! An instruction takes the cycles of its opcode, a branch more when it is
+//! taken, plus the wait states of each word it accesses: the two words of
+//! the instruction and the word a load, a store, a push or a pop reads or
+//! writes, by region of the memory map. Entering a handler takes its own
+//! cycles, plus the wait states of the two words pushed. A cycle waiting for
+//! an interrupt is a single cycle.
+//!
+//! By default every instruction and handler entry takes one cycle, without
+//! wait states. A timing table changes it, one setting per line:
+//!
+//! ```text
+//! # Loads and stores go through a slower bus
+//! ld 2
+//! str 2
+//! # Branches take 1 cycle, 3 when taken
+//! be 1 3
+//! bne 1 3
+//! wait rom 1
+//! wait vram 2
+//! entry 4
+//! ```
+//!
+//! An instruction is named by its opcode, as [OpCode] writes it, and a branch
+//! may be given a second count, its cost when taken. The regions are `rom`,
+//! `ram`, `vram`, `ppu` and `mmio`. An instruction or a handler entry takes
+//! at least one cycle, a word may have no wait states. Blank lines and text
+//! after `#` are ignored, and what a table leaves out keeps its default.
+
+use core::fmt;
+use core::str::FromStr;
+
+use asmlib::instruction::OpCode;
+
+use crate::memory::{MMIO, RAM, ROM, VRAM};
+use crate::ppu;
+
+/// Regions of the memory map with their own wait states.
+const REGIONS: [&str; 5] = ["rom", "ram", "vram", "ppu", "mmio"];
+
+/// Cycles of each instruction and handler entry.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Timing {
+    /// Cycles of each instruction by its encoded opcode, not taken and taken.
+    instructions: [(u64, u64); 256],
+    /// Cycles entering a handler.
+    entry: u64,
+    /// Wait states of each word accessed, by region in the order of [REGIONS].
+    wait: [u64; REGIONS.len()],
+}
+
+/// A line of a timing table that could not be parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TimingError {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for TimingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for TimingError {}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timing {
+    /// One cycle per instruction or handler entry, without wait states.
+    pub fn new() -> Self {
+        Self {
+            instructions: [(1, 1); 256],
+            entry: 1,
+            wait: [0; REGIONS.len()],
+        }
+    }
+
+    /// Return the cycles of an instruction with `opcode`, `taken` if it jumps.
+    pub fn cycles(&self, opcode: OpCode, taken: bool) -> u64 {
+        let (not_taken, taken_cycles) = self.instructions[u8::from(opcode) as usize];
+
+        if taken {
+            taken_cycles
+        } else {
+            not_taken
+        }
+    }
+
+    /// Return the cycles entering a handler.
+    pub fn entry(&self) -> u64 {
+        self.entry
+    }
+
+    /// Return the wait states of an access to the word at `address`.
+    pub fn wait(&self, address: usize) -> u64 {
+        let region = if ROM.contains(&address) {
+            0
+        } else if RAM.contains(&address) {
+            1
+        } else if VRAM.contains(&address) {
+            2
+        } else if ppu::MEMORY.contains(&address) {
+            3
+        } else if MMIO.contains(&address) {
+            4
+        } else {
+            return 0;
+        };
+
+        self.wait[region]
+    }
+}
+
+/// Return the opcode written `name`, in any case.
+fn opcode(name: &str) -> Option<OpCode> {
+    (0..=u8::MAX)
+        .filter_map(OpCode::decode)
+        .find(|opcode| opcode.to_string().eq_ignore_ascii_case(name))
+}
+
+/// Indicates if `opcode` is a branch, whose cost depends on whether it is taken.
+fn branch(opcode: &OpCode) -> bool {
+    matches!(
+        opcode,
+        OpCode::BE | OpCode::BNE | OpCode::BLT | OpCode::BGE | OpCode::BLTU | OpCode::BGEU
+    )
+}
+
+impl FromStr for Timing {
+    type Err = TimingError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut timing = Timing::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+
+            let error = |message: String| TimingError {
+                line: index + 1,
+                message,
+            };
+
+            let wait = |text: &str| {
+                text.parse::<u64>()
+                    .map_err(|_| error(format!("invalid number of cycles `{text}`")))
+            };
+
+            let cycles = |text: &str| match wait(text)? {
+                0 => Err(error("an instruction takes at least 1 cycle".to_string())),
+                count => Ok(count),
+            };
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields[..] {
+                [] => (),
+                ["entry", count] => timing.entry = cycles(count)?,
+                ["wait", region, count] => {
+                    let region = REGIONS
+                        .iter()
+                        .position(|name| *name == region)
+                        .ok_or_else(|| error(format!("unknown region `{region}`")))?;
+
+                    timing.wait[region] = wait(count)?;
+                }
+                [name, count, ref taken @ ..] if taken.len() <= 1 => {
+                    let opcode = opcode(name)
+                        .ok_or_else(|| error(format!("unknown instruction `{name}`")))?;
+
+                    let count = cycles(count)?;
+                    let taken = match taken {
+                        [taken] if branch(&opcode) => cycles(taken)?,
+                        [_] => return Err(error(format!("`{name}` is not a branch"))),
+                        _ => count,
+                    };
+
+                    timing.instructions[u8::from(opcode) as usize] = (count, taken);
+                }
+                _ => {
+                    return Err(error(format!(
+                        "expected `<instruction> <cycles>`, `wait <region> <cycles>` or `entry <cycles>`, got `{}`",
+                        line.trim()
+                    )))
+                }
+            }
+        }
+
+        Ok(timing)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let timing: Timing = "# slow memory\nLD 2\nbe 1 3 # taken\n\nwait vram 2\nentry 4\n"
+            .parse()
+            .unwrap();
+
+        assert_eq!(timing.cycles(OpCode::LD, false), 2);
+        assert_eq!(timing.cycles(OpCode::BE, false), 1);
+        assert_eq!(timing.cycles(OpCode::BE, true), 3);
+        assert_eq!(timing.cycles(OpCode::ADD, false), 1);
+        assert_eq!(timing.wait(VRAM.start), 2);
+        assert_eq!(timing.wait(RAM.start), 0);
+        assert_eq!(timing.entry(), 4);
+        assert_eq!(Timing::default().cycles(OpCode::BNE, true), 1);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text: &str| text.parse::<Timing>().unwrap_err().to_string();
+
+        assert_eq!(error("add 1\nmul 2\n"), "line 2: unknown instruction `mul`");
+        assert_eq!(error("add 1 2\n"), "line 1: `add` is not a branch");
+        assert_eq!(error("wait flash 1\n"), "line 1: unknown region `flash`");
+        assert_eq!(error("entry -1\n"), "line 1: invalid number of cycles `-1`");
+        assert_eq!(
+            error("ld 0\n"),
+            "line 1: an instruction takes at least 1 cycle"
+        );
+        assert!("wait rom 0\n".parse::<Timing>().is_ok());
+        assert!(error("be\n").starts_with("line 1: expected"));
+    }
+}
@@ -0,0 +1,482 @@
+//! Execution traces, to compare two runs instruction by instruction.
+//!
+//! While tracing, the [Emulator](crate::Emulator) records each instruction it
+//! retires with its effects: the registers it wrote and the memory it read or
+//! wrote, fetches aside. Instructions raising an exception are not retired,
+//! and neither are the entries of interrupt handlers, which only show as
+//! cycles between two instructions.
+//!
+//! A trace is saved as JSON lines, one object per instruction, or in a
+//! compact binary form:
+//!
+//! ```text
+//! {"asm":"addi 1, 0, 10","cycle":2,"memory":[],"pc":32,"registers":[{"register":1,"value":10}],"word":655617}
+//! ```
+//!
+//! The binary form starts with [MAGIC], then each instruction is its cycle
+//! (`u64`), `pc` (`u16`) and word (`u32`), the number of registers written
+//! (`u8`) each as its number (`u8`) and value (`u16`), and the number of
+//! memory accesses (`u8`) each as `0` for a read or `1` for a write (`u8`),
+//! its address and its value (`u16`), all little-endian.
+
+use core::fmt;
+use core::str::FromStr;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+
+use asmlib::instruction::decode_instruction;
+use serde_json::{json, Value};
+
+use crate::debugger::Debugger;
+
+/// First bytes of a trace in the binary form, with its version.
+pub const MAGIC: &[u8; 5] = b"BLTR\x01";
+
+/// A memory access of an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access {
+    pub write: bool,
+    pub address: u16,
+    pub value: u16,
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let arrow = if self.write { "<-" } else { "->" };
+
+        write!(f, "[0x{:04X}] {arrow} 0x{:04X}", self.address, self.value)
+    }
+}
+
+/// An instruction retired by the CPU, with its effects.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Retired {
+    /// Cycles run once it retired.
+    pub cycle: u64,
+    pub pc: u16,
+    /// The instruction, as encoded in memory.
+    pub word: u32,
+    /// Registers written, in order, with their new value.
+    pub registers: Vec<(u8, u16)>,
+    pub memory: Vec<Access>,
+}
+
+impl Retired {
+    pub fn new(pc: u16, word: u32) -> Self {
+        Self {
+            cycle: 0,
+            pc,
+            word,
+            registers: Vec::new(),
+            memory: Vec::new(),
+        }
+    }
+
+    /// Disassemble the instruction, its targets as addresses.
+    pub fn disassembly(&self) -> String {
+        Debugger::default().instruction(&decode_instruction(self.word))
+    }
+
+    pub fn to_json(&self) -> Value {
+        let registers: Vec<Value> = self
+            .registers
+            .iter()
+            .map(|(register, value)| json!({ "register": register, "value": value }))
+            .collect();
+        let memory: Vec<Value> = self
+            .memory
+            .iter()
+            .map(|access| {
+                let kind = if access.write { "write" } else { "read" };
+                json!({ "access": kind, "address": access.address, "value": access.value })
+            })
+            .collect();
+
+        json!({
+            "cycle": self.cycle,
+            "pc": self.pc,
+            "word": self.word,
+            "asm": self.disassembly(),
+            "registers": registers,
+            "memory": memory,
+        })
+    }
+
+    /// Read an instruction written by [Retired::to_json], the disassembly aside.
+    pub fn from_json(value: &Value) -> Option<Self> {
+        let number = |value: &Value| value.as_u64();
+
+        let registers = value["registers"]
+            .as_array()?
+            .iter()
+            .map(|register| {
+                let index = u8::try_from(number(&register["register"])?).ok()?;
+                Some((index, u16::try_from(number(&register["value"])?).ok()?))
+            })
+            .collect::<Option<_>>()?;
+        let memory = value["memory"]
+            .as_array()?
+            .iter()
+            .map(|access| {
+                Some(Access {
+                    write: match access["access"].as_str()? {
+                        "read" => false,
+                        "write" => true,
+                        _ => return None,
+                    },
+                    address: u16::try_from(number(&access["address"])?).ok()?,
+                    value: u16::try_from(number(&access["value"])?).ok()?,
+                })
+            })
+            .collect::<Option<_>>()?;
+
+        Some(Self {
+            cycle: number(&value["cycle"])?,
+            pc: u16::try_from(number(&value["pc"])?).ok()?,
+            word: u32::try_from(number(&value["word"])?).ok()?,
+            registers,
+            memory,
+        })
+    }
+
+    /// Write the instruction in the binary form.
+    pub fn write_binary(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(&self.cycle.to_le_bytes())?;
+        writer.write_all(&self.pc.to_le_bytes())?;
+        writer.write_all(&self.word.to_le_bytes())?;
+
+        writer.write_all(&[self.registers.len() as u8])?;
+        for (register, value) in &self.registers {
+            writer.write_all(&[*register])?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        writer.write_all(&[self.memory.len() as u8])?;
+        for access in &self.memory {
+            writer.write_all(&[access.write as u8])?;
+            writer.write_all(&access.address.to_le_bytes())?;
+            writer.write_all(&access.value.to_le_bytes())?;
+        }
+
+        Ok(())
+    }
+
+    /// Read an instruction in the binary form, `None` at the end of the trace.
+    pub fn read_binary(reader: &mut impl Read) -> io::Result<Option<Self>> {
+        let mut cycle = [0; 8];
+        match reader.read_exact(&mut cycle) {
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            result => result?,
+        }
+
+        let mut retired = Self::new(read_u16(reader)?, read_u32(reader)?);
+        retired.cycle = u64::from_le_bytes(cycle);
+
+        for _ in 0..read_u8(reader)? {
+            retired
+                .registers
+                .push((read_u8(reader)?, read_u16(reader)?));
+        }
+
+        for _ in 0..read_u8(reader)? {
+            retired.memory.push(Access {
+                write: read_u8(reader)? != 0,
+                address: read_u16(reader)?,
+                value: read_u16(reader)?,
+            });
+        }
+
+        Ok(Some(retired))
+    }
+}
+
+impl fmt::Display for Retired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut line = format!(
+            "cycle {:<6} 0x{:04X}  {:<20}",
+            self.cycle,
+            self.pc,
+            self.disassembly()
+        );
+
+        for (register, value) in &self.registers {
+            line += &format!("  r{register} = 0x{value:04X}");
+        }
+        for access in &self.memory {
+            line += &format!("  {access}");
+        }
+
+        write!(f, "{}", line.trim_end())
+    }
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16(reader: &mut impl Read) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_le_bytes(bytes))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Writes a trace as it is recorded, as JSON lines or in the binary form.
+pub struct TraceWriter {
+    writer: BufWriter<File>,
+    json: bool,
+}
+
+impl TraceWriter {
+    /// Create the trace at `path`, as JSON lines if it ends with `.jsonl` and
+    /// in the binary form otherwise.
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let json = path
+            .extension()
+            .is_some_and(|extension| extension == "jsonl");
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        if !json {
+            writer.write_all(MAGIC)?;
+        }
+
+        Ok(Self { writer, json })
+    }
+
+    pub fn write(&mut self, trace: &[Retired]) -> io::Result<()> {
+        for retired in trace {
+            if self.json {
+                writeln!(self.writer, "{}", retired.to_json())?;
+            } else {
+                retired.write_binary(&mut self.writer)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Read the trace at `path`, in either form.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Retired>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let invalid = |line: usize| io::Error::new(ErrorKind::InvalidData, format!("line {line}"));
+
+    let mut trace = Vec::new();
+
+    if reader.fill_buf()?.starts_with(MAGIC) {
+        reader.consume(MAGIC.len());
+
+        while let Some(retired) = Retired::read_binary(&mut reader)? {
+            trace.push(retired);
+        }
+    } else {
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let value: Value = serde_json::from_str(&line).map_err(|_| invalid(index + 1))?;
+            trace.push(Retired::from_json(&value).ok_or_else(|| invalid(index + 1))?);
+        }
+    }
+
+    Ok(trace)
+}
+
+/// What two traces are compared on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Field {
+    Cycle,
+    Pc,
+    Word,
+    Registers,
+    Memory,
+}
+
+impl Field {
+    pub const ALL: [Field; 5] = [
+        Field::Cycle,
+        Field::Pc,
+        Field::Word,
+        Field::Registers,
+        Field::Memory,
+    ];
+
+    fn differs(&self, left: &Retired, right: &Retired) -> bool {
+        match self {
+            Field::Cycle => left.cycle != right.cycle,
+            Field::Pc => left.pc != right.pc,
+            Field::Word => left.word != right.word,
+            Field::Registers => left.registers != right.registers,
+            Field::Memory => left.memory != right.memory,
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Cycle => "cycle",
+            Field::Pc => "pc",
+            Field::Word => "word",
+            Field::Registers => "registers",
+            Field::Memory => "memory",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        Field::ALL
+            .into_iter()
+            .find(|field| field.to_string() == text)
+            .ok_or_else(|| {
+                format!("unknown field `{text}`, expected cycle, pc, word, registers or memory")
+            })
+    }
+}
+
+/// The first instruction two traces differ on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Number of the instruction in the traces, from 0.
+    pub index: usize,
+    /// The fields they differ on, empty when a trace ends first.
+    pub fields: Vec<Field>,
+    pub left: Option<Retired>,
+    pub right: Option<Retired>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the traces diverge at instruction {}", self.index)?;
+
+        match (&self.left, &self.right) {
+            (Some(_), None) => writeln!(f, ", the right trace ends")?,
+            (None, Some(_)) => writeln!(f, ", the left trace ends")?,
+            _ => {
+                let fields: Vec<String> = self.fields.iter().map(Field::to_string).collect();
+                writeln!(f, ", on {}", fields.join(", "))?;
+            }
+        }
+
+        if let Some(left) = &self.left {
+            writeln!(f, "< {left}")?;
+        }
+        if let Some(right) = &self.right {
+            writeln!(f, "> {right}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Return the first instruction `left` and `right` differ on, comparing
+/// all the fields but those `ignored`.
+pub fn diff(left: &[Retired], right: &[Retired], ignored: &[Field]) -> Option<Divergence> {
+    let compared: Vec<Field> = Field::ALL
+        .into_iter()
+        .filter(|field| !ignored.contains(field))
+        .collect();
+
+    for index in 0..left.len().max(right.len()) {
+        let (left, right) = (left.get(index), right.get(index));
+
+        let fields: Vec<Field> = match (left, right) {
+            (Some(left), Some(right)) => compared
+                .iter()
+                .copied()
+                .filter(|field| field.differs(left, right))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        if left.is_none() || right.is_none() || !fields.is_empty() {
+            return Some(Divergence {
+                index,
+                fields,
+                left: left.cloned(),
+                right: right.cloned(),
+            });
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retired() -> Retired {
+        let mut retired = Retired::new(4, 0x0002_0810);
+        retired.cycle = 3;
+        retired.registers.push((15, 0xBFFF));
+        retired.memory.push(Access {
+            write: true,
+            address: 0xBFFF,
+            value: 0x1234,
+        });
+        retired
+    }
+
+    #[test]
+    fn json_and_binary() {
+        let retired = retired();
+
+        let json: Value = serde_json::from_str(&retired.to_json().to_string()).unwrap();
+        assert_eq!(Retired::from_json(&json), Some(retired.clone()));
+        assert_eq!(json["asm"], retired.disassembly());
+
+        let mut binary = Vec::new();
+        retired.write_binary(&mut binary).unwrap();
+        assert_eq!(binary.len(), 8 + 2 + 4 + 1 + 3 + 1 + 5);
+
+        let mut reader = binary.as_slice();
+        assert_eq!(Retired::read_binary(&mut reader).unwrap(), Some(retired));
+        assert_eq!(Retired::read_binary(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn first_divergence() {
+        let left = vec![retired(); 3];
+        let mut right = left.clone();
+        assert_eq!(diff(&left, &right, &[]), None);
+
+        right[1].cycle += 1;
+        right[1].registers[0].1 = 0;
+        let divergence = diff(&left, &right, &[]).unwrap();
+        assert_eq!(divergence.index, 1);
+        assert_eq!(divergence.fields, [Field::Cycle, Field::Registers]);
+
+        assert_eq!(
+            diff(&left, &right, &[Field::Registers]).unwrap().fields,
+            [Field::Cycle]
+        );
+
+        right[1] = left[1].clone();
+        right.pop();
+        let divergence = diff(&left, &right, &[]).unwrap();
+        assert_eq!(divergence.index, 2);
+        assert!(divergence.right.is_none());
+        assert!(divergence.to_string().contains("the right trace ends"));
+    }
+}
@@ -0,0 +1,93 @@
+//! Frame timing of the Blask handheld.
+//!
+//! The CPU runs a fixed budget of [CYCLES_PER_FRAME] cycles per frame, then
+//! the screen is drawn from VRAM during the vertical blank. A program syncs
+//! with the screen through two MMIO registers:
+//!
+//! | Address  | Register | Access                                              |
+//! |----------|----------|-----------------------------------------------------|
+//! | `0xF030` | `STATUS` | bit 0 set at each vertical blank, write 1 to clear  |
+//! | `0xF031` | `FRAMES` | frames drawn so far, wrapping around, read-only     |
+//!
+//! Each vertical blank also raises the
+//! [VBlank interrupt](crate::interrupt::Interrupt::VBlank), so a program can
+//! wait for the next frame with `WFI` instead of polling `STATUS`.
+
+use core::ops::Range;
+
+use crate::state::{StateError, StateReader, StateWriter};
+
+/// MMIO registers of the frame timing.
+pub const REGISTERS: Range<usize> = 0xF030..0xF032;
+
+pub const STATUS: usize = REGISTERS.start;
+pub const FRAMES: usize = REGISTERS.start + 1;
+
+/// Bit of `STATUS` set at each vertical blank.
+pub const VBLANK: u16 = 1;
+
+/// Cycles run per frame by default, 30 frames per second at 300 kHz.
+pub const CYCLES_PER_FRAME: u64 = 10_000;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Vblank {
+    status: u16,
+    frames: u16,
+}
+
+impl Vblank {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn save(&self, writer: &mut StateWriter) {
+        writer.u16(self.status);
+        writer.u16(self.frames);
+    }
+
+    pub fn restore(&mut self, reader: &mut StateReader) -> Result<(), StateError> {
+        self.status = reader.u16()?;
+        self.frames = reader.u16()?;
+
+        Ok(())
+    }
+
+    /// Signal the end of a frame.
+    pub fn blank(&mut self) {
+        self.status |= VBLANK;
+        self.frames = self.frames.wrapping_add(1);
+    }
+
+    /// Read the register at `address`, one of [REGISTERS].
+    pub fn read(&self, address: usize) -> u16 {
+        match address {
+            STATUS => self.status,
+            FRAMES => self.frames,
+            _ => unreachable!("0x{address:04X} is not a frame timing register"),
+        }
+    }
+
+    /// Write the `STATUS` register, `FRAMES` is read-only.
+    pub fn write_status(&mut self, value: u16) {
+        self.status &= !value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_is_write_one_to_clear() {
+        let mut vblank = Vblank::new();
+
+        vblank.blank();
+        vblank.blank();
+        assert_eq!(vblank.read(STATUS), VBLANK);
+        assert_eq!(vblank.read(FRAMES), 2);
+
+        vblank.write_status(VBLANK);
+        assert_eq!(vblank.read(STATUS), 0);
+        assert_eq!(vblank.read(FRAMES), 2);
+    }
+}
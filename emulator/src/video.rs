@@ -1,5 +1,120 @@
+use core::str::FromStr;
+
 use raylib::{ffi::LoadTextureFromImage, prelude::*};
 
+use crate::apu::SAMPLE_RATE;
+use crate::gamepad::{Button, Buttons};
+use crate::screen::Screenshot;
+
+/// Samples sent to the audio device at once, about 1/20 s.
+const AUDIO_BUFFER: usize = 1024;
+
+/// Keyboard keys held for the gamepad buttons in the window.
+///
+/// The default binds the D-pad to the arrow keys, A to `X`, B to `Z`, Start
+/// to Enter and Select to Backspace. It is changed with a comma-separated list
+/// of `button=key`, such as `a=k,b=j`, where a key is a letter or one of `up`,
+/// `down`, `left`, `right`, `enter`, `backspace`, `space`, `tab`, `lshift`
+/// and `rshift`.
+#[derive(Clone, Debug)]
+pub struct KeyMap {
+    keys: Vec<(Button, KeyboardKey)>,
+}
+
+impl Default for KeyMap {
+    fn default() -> Self {
+        use KeyboardKey::*;
+
+        Self {
+            keys: vec![
+                (Button::Right, KEY_RIGHT),
+                (Button::Left, KEY_LEFT),
+                (Button::Up, KEY_UP),
+                (Button::Down, KEY_DOWN),
+                (Button::A, KEY_X),
+                (Button::B, KEY_Z),
+                (Button::Select, KEY_BACKSPACE),
+                (Button::Start, KEY_ENTER),
+            ],
+        }
+    }
+}
+
+impl FromStr for KeyMap {
+    type Err = String;
+
+    /// Parse bindings, the buttons not listed keep their default key.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut map = KeyMap::default();
+
+        for binding in text.split(',').filter(|binding| !binding.is_empty()) {
+            let Some((button, name)) = binding.split_once('=') else {
+                return Err(format!("expected `button=key`, got `{binding}`"));
+            };
+
+            let button: Button = button.trim().parse()?;
+            let key = key(name.trim()).ok_or_else(|| format!("unknown key `{name}`"))?;
+
+            for (bound, bound_key) in map.keys.iter_mut() {
+                if *bound == button {
+                    *bound_key = key;
+                }
+            }
+        }
+
+        Ok(map)
+    }
+}
+
+/// Return the key named `name`, ignoring case.
+fn key(name: &str) -> Option<KeyboardKey> {
+    use KeyboardKey::*;
+
+    let name = name.to_ascii_lowercase();
+
+    let key = match name.as_str() {
+        "up" => KEY_UP,
+        "down" => KEY_DOWN,
+        "left" => KEY_LEFT,
+        "right" => KEY_RIGHT,
+        "enter" => KEY_ENTER,
+        "backspace" => KEY_BACKSPACE,
+        "space" => KEY_SPACE,
+        "tab" => KEY_TAB,
+        "lshift" => KEY_LEFT_SHIFT,
+        "rshift" => KEY_RIGHT_SHIFT,
+        "a" => KEY_A,
+        "b" => KEY_B,
+        "c" => KEY_C,
+        "d" => KEY_D,
+        "e" => KEY_E,
+        "f" => KEY_F,
+        "g" => KEY_G,
+        "h" => KEY_H,
+        "i" => KEY_I,
+        "j" => KEY_J,
+        "k" => KEY_K,
+        "l" => KEY_L,
+        "m" => KEY_M,
+        "n" => KEY_N,
+        "o" => KEY_O,
+        "p" => KEY_P,
+        "q" => KEY_Q,
+        "r" => KEY_R,
+        "s" => KEY_S,
+        "t" => KEY_T,
+        "u" => KEY_U,
+        "v" => KEY_V,
+        "w" => KEY_W,
+        "x" => KEY_X,
+        "y" => KEY_Y,
+        "z" => KEY_Z,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
 /// Handle the window creation for the Emulator.
 ///
 /// It contains all the Raylib structure used to create a Window and interact with video buffer.
@@ -7,6 +122,53 @@ pub struct Window {
     handle: RaylibHandle,
     thread: RaylibThread,
     video: Texture2D,
+    speaker: Option<Speaker>,
+}
+
+/// Stream of the APU samples to the audio device.
+struct Speaker {
+    stream: AudioStream<'static>,
+    /// Samples waiting for the device to take a buffer.
+    queue: Vec<i16>,
+}
+
+impl Speaker {
+    /// Open the audio device, or return `None` if there is none.
+    fn new() -> Option<Self> {
+        let audio = match RaylibAudio::init_audio_device() {
+            Ok(audio) => audio,
+            Err(error) => {
+                eprintln!("[Emulator] no sound: {}", error);
+                return None;
+            }
+        };
+
+        // The device stays open as long as the emulator runs
+        let audio: &'static RaylibAudio = Box::leak(Box::new(audio));
+        audio.set_audio_stream_buffer_size_default(AUDIO_BUFFER as i32);
+
+        let stream = audio.new_audio_stream(SAMPLE_RATE, 16, 1);
+        stream.play();
+
+        Some(Self {
+            stream,
+            queue: Vec::new(),
+        })
+    }
+
+    /// Queue `samples`, then send full buffers while the device takes them.
+    fn play(&mut self, samples: &[i16]) {
+        self.queue.extend_from_slice(samples);
+
+        while self.queue.len() >= AUDIO_BUFFER && self.stream.is_processed() {
+            self.stream.update(&self.queue[..AUDIO_BUFFER]);
+            self.queue.drain(..AUDIO_BUFFER);
+        }
+
+        // Drop what the device is too late to play, rather than lag behind
+        let late = self.queue.len().saturating_sub(4 * AUDIO_BUFFER);
+        self.queue.drain(..late);
+    }
 }
 
 impl Window {
@@ -18,19 +180,18 @@ impl Window {
             .title("Blask Emulator")
             .build();
 
-        let mut img = Image::gen_image_color(32, 32, Color::BLACK);
-        img.set_format(PixelFormat::PIXELFORMAT_UNCOMPRESSED_R8G8B8);
-
-        let tex: Texture2D;
-
-        unsafe {
-            tex = Texture2D::from_raw(LoadTextureFromImage(img.to_raw()));
-        }
-
         Self {
             handle: rl,
             thread,
-            video: tex,
+            video: texture(32, 32),
+            speaker: Speaker::new(),
+        }
+    }
+
+    /// Play `samples` at [SAMPLE_RATE], after those already queued.
+    pub fn play(&mut self, samples: &[i16]) {
+        if let Some(speaker) = &mut self.speaker {
+            speaker.play(samples);
         }
     }
 
@@ -39,43 +200,56 @@ impl Window {
         self.handle.window_should_close()
     }
 
-    /// Set the video buffer with the given buffer.
-    pub fn update_video_buffer(&mut self, buffer: &[u16; 32 * 32]) {
-        let mut video = self.handle.begin_drawing(&self.thread);
+    /// Return the buttons whose key is held down.
+    pub fn buttons(&self, map: &KeyMap) -> Buttons {
+        map.keys
+            .iter()
+            .filter(|(_, key)| self.handle.is_key_down(*key))
+            .map(|(button, _)| *button)
+            .collect()
+    }
 
-        // Compute required scaling
-        let scale = (video.get_screen_width() / 32)
-            .min(video.get_screen_height() / 32) as f32;
+    /// Draw `screen`, scaled to the window.
+    pub fn update_video_buffer(&mut self, screen: &Screenshot) {
+        let (width, height) = (screen.width() as i32, screen.height() as i32);
 
-        let mut pixels: [u8; 32 * 32 * 3] = [0; 32 * 32 * 3];
+        // The program may change the resolution between frames
+        if (self.video.width, self.video.height) != (width, height) {
+            self.video = texture(width, height);
+        }
 
-        for y in 0..32 {
-            for x in 0..32 {
-                let mut b: u8 = (buffer[x + y * 32] & ((1 << 4) - 1)) as u8;
-                let mut g: u8 = ((buffer[x + y * 32] & ((1 << 8) - 1)) >> 4) as u8;
-                let mut r: u8 = ((buffer[x + y * 32] & ((1 << 12) - 1)) >> 8) as u8;
+        let mut video = self.handle.begin_drawing(&self.thread);
 
-                r <<= 4;
-                g <<= 4;
-                b <<= 4;
+        // Compute required scaling
+        let scale =
+            (video.get_screen_width() / width).min(video.get_screen_height() / height) as f32;
 
-                pixels[(x * 3) + (y * 32 * 3)] = r;
-                pixels[(x * 3) + 1 + (y * 32 * 3)] = g;
-                pixels[(x * 3) + 2 + (y * 32 * 3)] = b;
-            }
-        }
+        self.video.update_texture(screen.pixels());
 
-        self.video.update_texture(&pixels);
+        let (width, height) = (width as f32 * scale, height as f32 * scale);
 
         // Draw Texture2D to window, properly scaled
-        video.draw_texture_pro(&self.video, 
-                               rrect(0.0, 0.0, self.video.width, -self.video.height),
-                               rrect((video.get_screen_width() as f32 - (32.0 * scale)) * 0.5,
-                                       (video.get_screen_height() as f32 - (32.0 * scale)) * 0.5,
-                                       32.0 * scale, 32.0 * scale),
-                               rvec2(0, 0),
-                               0.0,
-                               Color::WHITE);
+        video.draw_texture_pro(
+            &self.video,
+            rrect(0.0, 0.0, self.video.width, self.video.height),
+            rrect(
+                (video.get_screen_width() as f32 - width) * 0.5,
+                (video.get_screen_height() as f32 - height) * 0.5,
+                width,
+                height,
+            ),
+            rvec2(0, 0),
+            0.0,
+            Color::WHITE,
+        );
         // video.draw_texture(&self.video, 0, 0, Color::WHITE);
     }
 }
+
+/// Create a black RGB texture of `width` by `height` pixels.
+fn texture(width: i32, height: i32) -> Texture2D {
+    let mut img = Image::gen_image_color(width, height, Color::BLACK);
+    img.set_format(PixelFormat::PIXELFORMAT_UNCOMPRESSED_R8G8B8);
+
+    unsafe { Texture2D::from_raw(LoadTextureFromImage(img.to_raw())) }
+}
@@ -0,0 +1,20 @@
+use core::fmt;
+
+/// Something a program did that is allowed but probably a mistake.
+///
+/// Only reported in strict mode, see [crate::Emulator::set_strict].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Warning {
+    /// The instruction at `pc` wrote to register 0, the write was discarded.
+    ZeroRegisterWrite { pc: u16 },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::ZeroRegisterWrite { pc } => {
+                write!(f, "write to the zero register discarded (pc 0x{:04X})", pc)
+            }
+        }
+    }
+}
@@ -1,10 +1,13 @@
 use asmlib::instruction::*;
+use std::collections::HashMap;
 use std::fs::*;
 use std::io;
 use std::io::BufRead;
 use std::path::*;
 use blex::Lexer;
 use blas::asm::ASM;
+use blas::ASMError;
+use blib::Span;
 
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
 where
@@ -15,29 +18,90 @@ where
 }
 
 pub fn blasm_to_instructions(filename: &str) -> Vec<Instruction> {
-    let mut instructions: Vec<Instruction> = Vec::with_capacity(20);
+    blasm_to_program(filename).instructions
+}
+
+/// An assembled program, with what a debugger needs to show its source.
+pub struct Program {
+    pub instructions: Vec<Instruction>,
+    /// Address of each label, with its `@`.
+    pub labels: HashMap<String, u16>,
+    /// Span in `source` of the instruction at address `2 * n`.
+    pub spans: Vec<Span>,
+    pub source: String,
+}
+
+impl Program {
+    /// Return the line, from 1, of the instruction at `address`.
+    pub fn line(&self, address: u16) -> Option<usize> {
+        let span = self.spans.get(address as usize / 2)?;
+
+        Some(self.source[..span.start()].matches('\n').count() + 1)
+    }
+}
 
+/// Assemble `filename`, returning its instructions with their labels and spans.
+pub fn blasm_to_program(filename: &str) -> Program {
     let read_attempt = read_to_string(filename);
 
     if let Ok(lines_of_code) = read_attempt {
-        let lexer = Lexer::new(&lines_of_code);
-        let asm = ASM::new(&lines_of_code, &lexer);
+        let (program, errors) = assemble(lines_of_code);
 
-        for pseudo_instruction in asm {
-            if let Ok(pseudo_instruction) = pseudo_instruction {
-                let instruction: Instruction = pseudo_instruction.into();
-
-                instructions.push(instruction);
-            } else {
-                println!("[blasm_to_instructions] fail to convert pseudo_instruction");
-            }
+        for _ in errors {
+            println!("[blasm_to_instructions] fail to convert pseudo_instruction");
         }
 
-        return instructions;
+        return program;
     }
     panic!("[blasm_to_instructions] fail to read file");
 }
 
+/// Assemble `filename` like [blasm_to_program], failing with a message for
+/// the first instruction that does not assemble instead of dropping it.
+pub fn try_blasm_to_program(filename: &str) -> Result<Program, String> {
+    let source = read_to_string(filename).map_err(|error| format!("{filename}: {error}"))?;
+    let (program, errors) = assemble(source);
+
+    match errors.first() {
+        Some(error) => {
+            let start = error.span().start();
+            let line = program.source[..start].matches('\n').count() + 1;
+            let text = program.source[error.span().range()].trim();
+
+            Err(format!("{filename}:{line}: cannot assemble `{text}`"))
+        }
+        None => Ok(program),
+    }
+}
+
+/// Assemble `source`, keeping the instructions that assemble.
+fn assemble(source: String) -> (Program, Vec<ASMError>) {
+    let mut instructions: Vec<Instruction> = Vec::with_capacity(20);
+    let mut errors = Vec::new();
+
+    let lexer = Lexer::new(&source);
+    let mut asm = ASM::new(&source, &lexer);
+
+    for pseudo_instruction in asm.by_ref() {
+        match pseudo_instruction {
+            Ok(pseudo_instruction) => instructions.push(pseudo_instruction.into()),
+            Err(error) => errors.push(error),
+        }
+    }
+
+    let labels = asm.labels().clone();
+    let spans = asm.spans().to_vec();
+
+    let program = Program {
+        instructions,
+        labels,
+        spans,
+        source,
+    };
+
+    (program, errors)
+}
+
 pub fn binary_to_instructions(filename: &str) -> Vec<Instruction> {
     let mut instructions: Vec<Instruction> = Vec::with_capacity(20);
     if let Ok(lines) = read_lines(filename) {
@@ -55,6 +119,27 @@ pub fn binary_to_instructions(filename: &str) -> Vec<Instruction> {
 mod tests {
     use crate::*;
 
+    #[test]
+    fn failed_instruction() {
+        let source = "addi 1, 0, 1\naddi 99, 0, 1\n@Next\naddi 2, 0, 2\n";
+        let (program, errors) = assemble(source.to_string());
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(program.instructions.len(), 2);
+        assert_eq!(program.line(0), Some(1));
+        assert_eq!(program.line(2), Some(4));
+
+        let path = std::env::temp_dir().join("files_failed_instruction.blasm");
+        write(&path, source).unwrap();
+        let program = try_blasm_to_program(path.to_str().unwrap());
+        remove_file(&path).unwrap();
+
+        let Err(error) = program else {
+            panic!("the program assembled");
+        };
+        assert!(error.ends_with(":2: cannot assemble `99`"), "{error}");
+    }
+
     #[test]
     fn decode_instruction_iinstruction() {
         let instruction = binary_to_instructions("examples/binary1");